@@ -0,0 +1,58 @@
+use sdl2::AudioSubsystem;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+const TONE_HZ: f32 = 880.0;
+const BURST_MS: u32 = 60;
+const VOLUME: f32 = 0.2;
+/// fade in/out over this many samples so the burst doesn't click
+const RAMP: usize = 240;
+
+/// Sine burst generator; silent until `remaining` is set.
+struct Burst {
+    phase: f32,
+    phase_inc: f32,
+    remaining: usize,
+    length: usize,
+}
+
+impl AudioCallback for Burst {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            if self.remaining == 0 {
+                *sample = 0.0;
+                continue;
+            }
+            let done = self.length - self.remaining;
+            let ramp = (done.min(self.remaining) as f32 / RAMP as f32).min(1.0);
+            *sample = (self.phase * std::f32::consts::TAU).sin() * VOLUME * ramp;
+            self.phase = (self.phase + self.phase_inc).fract();
+            self.remaining -= 1;
+        }
+    }
+}
+
+/// Short beep for `--beep-on-change`, generated in the audio callback so no sound
+/// file is needed.
+pub struct Beeper {
+    device: AudioDevice<Burst>,
+}
+
+impl Beeper {
+    pub fn open(audio: &AudioSubsystem) -> Result<Self, String> {
+        let desired = AudioSpecDesired { freq: Some(48_000), channels: Some(1), samples: Some(512) };
+        let device = audio.open_playback(None, &desired, |spec| {
+            let length = (spec.freq as u32 * BURST_MS / 1000) as usize;
+            Burst { phase: 0.0, phase_inc: TONE_HZ / spec.freq as f32, remaining: 0, length }
+        })?;
+        device.resume();
+        Ok(Self { device })
+    }
+
+    /// Start a burst (restarting one that is still playing).
+    pub fn beep(&mut self) {
+        let mut burst = self.device.lock();
+        burst.remaining = burst.length;
+    }
+}
@@ -0,0 +1,93 @@
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+use crate::lan::{self, Connection, ProtocolError, WorkerConfig};
+
+// Headless relay (--bridge): sit between a ColourSpace server and a display client
+// somewhere else, logging what goes through.
+//
+// One display client at a time. When it connects we open our own connection upstream
+// (with the usual bind/TLS/framing options) and run two pumps:
+//   - upstream -> downstream on the calling thread, frame by frame, so every frame is
+//     checked against `max_payload` and logged before it is passed on unchanged;
+//   - downstream -> upstream on a helper thread, copied byte for byte (the client's
+//     init handshake isn't framed, so there is nothing to split on).
+// Whichever side ends first shuts both sockets down, which unblocks the other pump.
+// Then the next display client is accepted. Nothing is drawn and nothing is parsed
+// beyond the framing.
+
+/// Bind `listen` and relay display clients to `upstream` until the process is killed.
+pub fn run(upstream: &str, listen: SocketAddr, config: &WorkerConfig) -> io::Result<()> {
+    serve(TcpListener::bind(listen)?, upstream, config);
+    Ok(())
+}
+
+/// The accept loop of `run`, on an already bound listener.
+pub fn serve(listener: TcpListener, upstream: &str, config: &WorkerConfig) {
+    for stream in listener.incoming() {
+        let downstream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Bridge listener error: {}", e);
+                continue;
+            }
+        };
+        let peer = downstream.peer_addr().map_or("?".to_string(), |a| a.to_string());
+        eprintln!("Bridge: display client {} connected, connecting to {}", peer, upstream);
+        let conn = match lan::connect(upstream, config) {
+            Ok(c) => c,
+            Err(e) => {
+                // drop the client; it will retry like it would against a missing server
+                eprintln!("Bridge: upstream {} unreachable: {}", upstream, e);
+                continue;
+            }
+        };
+        let (frames, why) = relay(downstream, conn, config);
+        eprintln!("Bridge: session with {} ended after {} frame(s): {}", peer, frames, why);
+    }
+}
+
+/// Run one session; returns how many frames went downstream and why it stopped.
+fn relay(downstream: TcpStream, conn: Connection, config: &WorkerConfig) -> (u64, ProtocolError) {
+    let Connection { socket, mut reader, mut writer } = conn;
+    let (mut down_read, mut down_write) = match (downstream.try_clone(), downstream.try_clone()) {
+        (Ok(r), Ok(w)) => (r, w),
+        (Err(e), _) | (_, Err(e)) => return (0, ProtocolError::Io(e)),
+    };
+    if let Err(e) = socket.set_read_timeout(config.read_timeout) {
+        return (0, ProtocolError::Io(e));
+    }
+    let up = match socket.try_clone() {
+        Ok(t) => t,
+        Err(e) => return (0, ProtocolError::Io(e)),
+    };
+
+    let uplink = thread::spawn(move || {
+        let copied = io::copy(&mut down_read, &mut writer);
+        let _ = up.shutdown(Shutdown::Both);
+        copied
+    });
+
+    let mut frames = 0u64;
+    let mut upstream = lan::FrameReader::new(config.max_payload, config.endianness);
+    let why = loop {
+        let msg = match upstream.read_frame(&mut reader, config.read_timeout) {
+            Ok(m) => m,
+            Err(e) => break e,
+        };
+        frames += 1;
+        eprintln!("Bridge: frame {} ({} bytes)", frames, msg.len());
+        if let Err(e) = lan::send_message_to_stream(&mut down_write, &msg, config.endianness) {
+            break ProtocolError::Io(e);
+        }
+    };
+    let _ = downstream.shutdown(Shutdown::Both);
+    let _ = socket.shutdown(Shutdown::Both);
+    match uplink.join() {
+        Ok(Ok(n)) => eprintln!("Bridge: {} byte(s) sent upstream", n),
+        Ok(Err(e)) => eprintln!("Bridge: upstream send failed: {}", e),
+        Err(_) => eprintln!("Bridge: uplink thread panicked"),
+    }
+    (frames, why)
+}
@@ -0,0 +1,99 @@
+use crate::lan::ColorRGB;
+
+// Greys of a given correlated colour temperature, for quick white-point checks
+// (--measure-cct / --target-y) without working out code values by hand.
+//
+// The model is deliberately naive: chromaticity from the CIE daylight locus, shown
+// on an idealised sRGB/Rec.709 display (Rec.709 primaries, D65 white, pure 2.2
+// power transfer). Luminance is relative to that display's white, so 100 means a
+// full white. Real panels differ; this only gets you close to the target.
+
+/// The daylight locus is only defined over this range (kelvin).
+pub const CCT_RANGE: std::ops::RangeInclusive<f64> = 4000.0..=25000.0;
+
+const DISPLAY_GAMMA: f64 = 2.2;
+
+/// How far past white a target may land and still be scaled down to fit (6500K on the
+/// locus is a hair off D65, so "6500K at 100%" would otherwise be refused).
+const OVERSHOOT_SLACK: f64 = 1.005;
+
+/// CIE daylight chromaticity x,y for `cct` kelvin (4000-25000K).
+pub fn daylight_xy(cct: f64) -> (f64, f64) {
+    let t = cct;
+    let x = if t <= 7000.0 {
+        -4.6070e9 / t.powi(3) + 2.9678e6 / t.powi(2) + 0.09911e3 / t + 0.244063
+    } else {
+        -2.0064e9 / t.powi(3) + 1.9018e6 / t.powi(2) + 0.24748e3 / t + 0.237040
+    };
+    (x, -3.0 * x * x + 2.87 * x - 0.275)
+}
+
+/// Linear Rec.709 RGB of chromaticity x,y at relative luminance `lum` (1.0 = white).
+fn linear_rgb(x: f64, y: f64, lum: f64) -> (f64, f64, f64) {
+    let (cx, cz) = (x / y * lum, (1.0 - x - y) / y * lum);
+    (
+        3.2404542 * cx - 1.5371385 * lum - 0.4985314 * cz,
+        -0.9692660 * cx + 1.8760108 * lum + 0.0415560 * cz,
+        0.0556434 * cx - 0.2040259 * lum + 1.0572252 * cz,
+    )
+}
+
+/// Code values at `bits` for a `cct` kelvin grey at `target_y` percent of white.
+/// Fails outside the locus range, or when the display can't get that bright at
+/// that colour temperature (the error says how far it can go).
+pub fn patch(cct: f64, target_y: f64, bits: u8) -> Result<ColorRGB, String> {
+    if !CCT_RANGE.contains(&cct) {
+        return Err(format!("colour temperature {}K out of range {}-{}K", cct, CCT_RANGE.start(), CCT_RANGE.end()));
+    }
+    if !(target_y > 0.0 && target_y <= 100.0) {
+        return Err(format!("target luminance {} out of range, expected 0-100 (% of white)", target_y));
+    }
+    let (x, y) = daylight_xy(cct);
+    let (r, g, b) = linear_rgb(x, y, target_y / 100.0);
+    let peak = r.max(g).max(b);
+    if peak > OVERSHOOT_SLACK {
+        return Err(format!("{}K can only reach {:.1}% of white on an sRGB display, asked for {}%", cct, target_y / peak, target_y));
+    }
+    let fit = peak.max(1.0);
+    let encode = |v: f64| (v / fit).max(0.0).powf(1.0 / DISPLAY_GAMMA);
+    Ok(ColorRGB::from_normalized(encode(r), encode(g), encode(b), bits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn d65_is_a_near_equal_grey() {
+        // 6504K is D65 (6500K under the old radiation constant)
+        let (x, y) = daylight_xy(6504.0);
+        assert!((x - 0.3127).abs() < 2e-4 && (y - 0.3290).abs() < 2e-4, "{} {}", x, y);
+
+        for bits in [8, 10, 16] {
+            let white = patch(6500.0, 100.0, bits).unwrap();
+            assert!(white.is_near_grey(0.01), "{:?}", white);
+            assert_eq!(white.red.max(white.green).max(white.blue), ((1u32 << bits) - 1) as u16);
+        }
+        // half the luminance through the 2.2 power: about 186 of 255
+        let half = patch(6500.0, 50.0, 8).unwrap();
+        assert!(half.is_near_grey(0.01), "{:?}", half);
+        assert!(half.green.abs_diff(186) <= 1, "{:?}", half);
+    }
+
+    #[test]
+    fn colour_temperature_tints_the_grey() {
+        let warm = patch(5000.0, 50.0, 8).unwrap();
+        let cool = patch(9300.0, 50.0, 8).unwrap();
+        assert!(warm.red > warm.blue, "{:?}", warm);
+        assert!(cool.blue > cool.red, "{:?}", cool);
+    }
+
+    #[test]
+    fn out_of_range_requests_are_refused() {
+        assert!(patch(3000.0, 50.0, 8).unwrap_err().contains("out of range"));
+        assert!(patch(6500.0, 0.0, 8).is_err());
+        assert!(patch(6500.0, 120.0, 8).is_err());
+        // a cool white can't reach full luminance on D65 primaries
+        assert!(patch(20000.0, 100.0, 8).unwrap_err().contains("can only reach"));
+    }
+}
@@ -0,0 +1,155 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// What a lone left click does. Double-click always toggles fullscreen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SingleClickAction {
+    None,
+    /// show/hide the info overlay
+    Overlay,
+}
+
+impl FromStr for SingleClickAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(SingleClickAction::None),
+            "overlay" => Ok(SingleClickAction::Overlay),
+            other => Err(format!("unknown click action '{}', expected: none, overlay", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Click {
+    Single,
+    Double,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Idle,
+    /// one press seen; becomes a single click unless another press follows in time
+    Pending(Instant),
+    /// a double click just fired; further presses within the threshold are swallowed
+    /// so a triple click doesn't also produce a single
+    AfterDouble(Instant),
+}
+
+/// Tells single clicks from double clicks. A single click is only reported once
+/// the threshold has passed without a second press, so it never fires as the
+/// first half of a double click.
+#[derive(Debug)]
+pub struct ClickTracker {
+    threshold: Duration,
+    state: State,
+}
+
+impl ClickTracker {
+    pub fn new(threshold: Duration) -> Self {
+        Self { threshold, state: State::Idle }
+    }
+
+    /// Record a button press. Returns `Double` immediately when it completes one.
+    pub fn press(&mut self, now: Instant) -> Option<Click> {
+        match self.state {
+            State::Pending(prev) if now.duration_since(prev) <= self.threshold => {
+                self.state = State::AfterDouble(now);
+                Some(Click::Double)
+            }
+            State::AfterDouble(prev) if now.duration_since(prev) <= self.threshold => {
+                self.state = State::AfterDouble(now);
+                None
+            }
+            _ => {
+                self.state = State::Pending(now);
+                None
+            }
+        }
+    }
+
+    /// Call regularly; returns `Single` once a lone press has timed out.
+    pub fn poll(&mut self, now: Instant) -> Option<Click> {
+        match self.state {
+            State::Pending(prev) if now.duration_since(prev) > self.threshold => {
+                self.state = State::Idle;
+                Some(Click::Single)
+            }
+            State::AfterDouble(prev) if now.duration_since(prev) > self.threshold => {
+                self.state = State::Idle;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Forget the current press (e.g. it turned into a drag).
+    pub fn cancel(&mut self) {
+        self.state = State::Idle;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: Duration = Duration::from_millis(400);
+
+    /// Feed presses at the given millisecond offsets, polling every 10 ms until a second
+    /// past the last one, and collect what the tracker reports.
+    fn clicks(presses: &[u64]) -> Vec<Click> {
+        let start = Instant::now();
+        let mut tracker = ClickTracker::new(THRESHOLD);
+        let mut out = Vec::new();
+        let end = presses.last().copied().unwrap_or(0) + 1000;
+        for ms in (0..=end).step_by(10) {
+            let now = start + Duration::from_millis(ms);
+            if presses.contains(&ms) {
+                out.extend(tracker.press(now));
+            }
+            out.extend(tracker.poll(now));
+        }
+        out
+    }
+
+    #[test]
+    fn single_click_fires_only_after_the_threshold() {
+        let start = Instant::now();
+        let mut tracker = ClickTracker::new(THRESHOLD);
+        assert_eq!(tracker.press(start), None);
+        assert_eq!(tracker.poll(start + THRESHOLD), None);
+        assert_eq!(tracker.poll(start + THRESHOLD + Duration::from_millis(1)), Some(Click::Single));
+        assert_eq!(tracker.poll(start + THRESHOLD * 2), None);
+    }
+
+    #[test]
+    fn single_double_and_triple_clicks() {
+        assert_eq!(clicks(&[0]), [Click::Single]);
+        assert_eq!(clicks(&[0, 200]), [Click::Double]);
+        // the third press is swallowed rather than starting a single
+        assert_eq!(clicks(&[0, 200, 400]), [Click::Double]);
+        // presses further apart than the threshold are two singles
+        assert_eq!(clicks(&[0, 500]), [Click::Single, Click::Single]);
+        // once the double has settled, a new press is a fresh click
+        assert_eq!(clicks(&[0, 200, 700]), [Click::Double, Click::Single]);
+    }
+
+    #[test]
+    fn cancelled_press_reports_nothing() {
+        let start = Instant::now();
+        let mut tracker = ClickTracker::new(THRESHOLD);
+        tracker.press(start);
+        tracker.cancel();
+        assert_eq!(tracker.poll(start + THRESHOLD * 2), None);
+        // and the next press doesn't pair with the cancelled one
+        assert_eq!(tracker.press(start + Duration::from_millis(100)), None);
+    }
+
+    #[test]
+    fn single_click_action_parses_any_case() {
+        assert_eq!("Overlay".parse(), Ok(SingleClickAction::Overlay));
+        assert_eq!("none".parse(), Ok(SingleClickAction::None));
+        assert!("advance".parse::<SingleClickAction>().is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::delta_e::{Formula, xyy_to_lab};
+
+// Before/after comparison of two saved sweep runs (--compare A.csv --compare B.csv).
+// Any of the sweep CSVs works (gamma, uniformity, patch list): columns are found by
+// header, and patches are paired by their position in the run (step, line, or row and
+// col), so both runs must come from the same sweep. ΔE uses the same Lab conversion as
+// --target, against the --reference-white-y white; ΔY is B's luminance minus A's.
+
+/// One measured patch of a saved run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunRow {
+    /// position in the sweep: `step`, `line`, or `row,col`
+    pub key: String,
+    pub label: Option<String>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub y_lum: Option<f64>,
+}
+
+/// Split one CSV line, honouring the double-quoted (and quote-doubled) label field.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => { chars.next(); fields.last_mut().unwrap().push('"'); }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Read a sweep results CSV written by this client.
+pub fn load_run(path: &Path) -> io::Result<Vec<RunRow>> {
+    let bad = |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), msg));
+    let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let header = split_csv(lines.next().ok_or_else(|| bad("empty file".to_string()))?);
+    let column = |name: &str| header.iter().position(|h| h.trim() == name);
+    let key_columns: Vec<usize> = match (column("step"), column("line"), column("row"), column("col")) {
+        (Some(step), _, _, _) => vec![step],
+        (None, Some(line), _, _) => vec![line],
+        (None, None, Some(row), Some(col)) => vec![row, col],
+        _ => return Err(bad("no step, line or row/col column; not a sweep results CSV".to_string())),
+    };
+    let (Some(xc), Some(yc), Some(lum)) = (column("x"), column("y"), column("Y")) else {
+        return Err(bad("missing x, y or Y column".to_string()));
+    };
+    let label = column("label");
+
+    let mut rows = Vec::new();
+    for line in lines {
+        let fields = split_csv(line);
+        let field = |i: usize| fields.get(i).map(|f| f.trim()).filter(|f| !f.is_empty());
+        let number = |i: usize| field(i).and_then(|f| f.parse::<f64>().ok());
+        rows.push(RunRow {
+            key: key_columns.iter().map(|&i| field(i).unwrap_or("?")).collect::<Vec<_>>().join(","),
+            label: label.and_then(field).map(str::to_string),
+            x: number(xc),
+            y: number(yc),
+            y_lum: number(lum),
+        });
+    }
+    Ok(rows)
+}
+
+/// How one patch changed from run A to run B. Each delta is None when either run has
+/// no reading for the patch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchDelta {
+    pub key: String,
+    pub label: Option<String>,
+    pub delta_e: Option<f64>,
+    pub delta_y: Option<f64>,
+    /// ΔY in % of A's Y
+    pub delta_y_pct: Option<f64>,
+}
+
+/// Pair the patches of `a` and `b` by position, in A's order. Patches only one run has
+/// are left out.
+pub fn compare(a: &[RunRow], b: &[RunRow], formula: Formula, white_y: f64) -> Vec<PatchDelta> {
+    a.iter()
+    .filter_map(|ra| {
+        let rb = b.iter().find(|rb| rb.key == ra.key)?;
+        let lab = |r: &RunRow| Some(xyy_to_lab(r.x?, r.y?, r.y_lum?, white_y));
+        let delta_e = lab(ra).zip(lab(rb)).map(|(la, lb)| formula.delta_e(la, lb));
+        let delta_y = ra.y_lum.zip(rb.y_lum).map(|(ya, yb)| yb - ya);
+        let delta_y_pct = ra.y_lum.zip(delta_y).and_then(|(ya, dy)| (ya > 0.0).then(|| dy / ya * 100.0));
+        Some(PatchDelta { key: ra.key.clone(), label: ra.label.clone().or_else(|| rb.label.clone()), delta_e, delta_y, delta_y_pct })
+    })
+    .collect()
+}
+
+/// The comparison as a text table, with mean and worst ΔE at the bottom.
+pub fn report(deltas: &[PatchDelta], formula: Formula) -> String {
+    let opt = |v: Option<f64>, decimals: usize| v.map(|v| format!("{:.*}", decimals, v)).unwrap_or_else(|| "-".to_string());
+    let mut out = format!("{:<10} {:>9} {:>11} {:>8}  label\n", "patch", formula.name(), "dY", "dY %");
+    for d in deltas {
+        out.push_str(&format!(
+            "{:<10} {:>9} {:>11} {:>8}  {}\n",
+            d.key, opt(d.delta_e, 3), opt(d.delta_y, 4), opt(d.delta_y_pct, 2), d.label.as_deref().unwrap_or("")
+        ));
+    }
+    let des: Vec<f64> = deltas.iter().filter_map(|d| d.delta_e).collect();
+    match deltas.iter().filter_map(|d| Some((d.delta_e?, d))).max_by(|p, q| p.0.total_cmp(&q.0)) {
+        None => out.push_str(&format!("{} patch(es) paired, none measured in both runs\n", deltas.len())),
+        Some((worst, d)) => out.push_str(&format!(
+            "{} patch(es) compared: mean {} {:.3}, max {:.3} (patch {})\n",
+            des.len(), formula.name(), des.iter().sum::<f64>() / des.len() as f64, worst, d.key
+        )),
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "step,level,red,green,blue,bits,x,y,Y,Y_stddev,samples,gamma,label,status\n";
+
+    /// Write a gamma sweep CSV for `rows` (step, x, y, Y, label) and load it back.
+    fn run(name: &str, rows: &[(u32, &str, &str, &str, &str)]) -> Vec<RunRow> {
+        let mut text = HEADER.to_string();
+        for (step, x, y, lum, label) in rows {
+            text.push_str(&format!("{},0.5,128,128,128,8,{},{},{},,1,,{},ok\n", step, x, y, lum, label));
+        }
+        let path = std::env::temp_dir().join(format!("colourspace-compare-{}-{}.csv", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        let rows = load_run(&path);
+        let _ = fs::remove_file(&path);
+        rows.unwrap()
+    }
+
+    #[test]
+    fn two_runs_pair_by_step() {
+        let a = run("a", &[(0, "0.3127", "0.3290", "100", "\"white, D65\""), (1, "0.3127", "0.3290", "50", ""), (2, "", "", "", "")]);
+        let b = run("b", &[(0, "0.3127", "0.3290", "100", ""), (1, "0.3127", "0.3290", "55", ""), (2, "0.3127", "0.3290", "20", ""), (3, "0.3", "0.3", "1", "")]);
+        assert_eq!(a[0].label.as_deref(), Some("white, D65"));
+
+        let deltas = compare(&a, &b, Formula::Cie76, 100.0);
+        // step 3 is only in B
+        assert_eq!(deltas.iter().map(|d| d.key.as_str()).collect::<Vec<_>>(), ["0", "1", "2"]);
+        assert!(deltas[0].delta_e.unwrap() < 1e-9);
+        assert_eq!(deltas[0].delta_y, Some(0.0));
+        assert!(deltas[1].delta_e.unwrap() > 1.0);
+        assert_eq!(deltas[1].delta_y, Some(5.0));
+        assert_eq!(deltas[1].delta_y_pct, Some(10.0));
+        // no reading in A
+        assert_eq!((deltas[2].delta_e, deltas[2].delta_y), (None, None));
+
+        let report = report(&deltas, Formula::Cie76);
+        assert!(report.contains(&format!("2 patch(es) compared: mean dE76 {:.3}, max {:.3} (patch 1)", deltas[1].delta_e.unwrap() / 2.0, deltas[1].delta_e.unwrap())), "{}", report);
+    }
+
+    #[test]
+    fn report_without_common_readings() {
+        let a = run("empty-a", &[(0, "", "", "", "")]);
+        let b = run("empty-b", &[(0, "0.3127", "0.3290", "100", "")]);
+        let report = report(&compare(&a, &b, Formula::Cie2000, 100.0), Formula::Cie2000);
+        assert!(report.ends_with("1 patch(es) paired, none measured in both runs\n"), "{}", report);
+    }
+}
@@ -0,0 +1,167 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::lan::PatchRegion;
+
+/// Settings persisted between runs in a small `key = value` text file
+/// (`$XDG_CONFIG_HOME/colourspace/config`, falling back to `~/.config/...`).
+/// Unknown keys and malformed lines are ignored so older/newer builds can share it.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    pub region: Option<PatchRegion>,
+    /// default server host[:port], used when neither the CLI nor the environment gives one
+    pub remote: Option<String>,
+    /// server last connected to; pre-fills the address dialog (unlike `remote`, which skips it)
+    pub last_host: Option<String>,
+    /// double-click window in ms; the CLI flag overrides it
+    pub double_click_ms: Option<u64>,
+    /// where the main window was when the client last exited
+    pub window: Option<WindowGeometry>,
+}
+
+/// Main window placement, saved on exit and restored on the next launch. `x`, `y`,
+/// `width` and `height` are the windowed rectangle in desktop coordinates (kept even
+/// when the window was fullscreen, so leaving fullscreen puts it back there).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowGeometry {
+    pub display: i32,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(base.join("colourspace").join("config"))
+    }
+
+    /// Load the config file, returning defaults if it is missing or unreadable.
+    pub fn load() -> Self {
+        Self::path().and_then(|p| fs::read_to_string(p).ok()).map(|text| Self::parse(&text)).unwrap_or_default()
+    }
+
+    /// The settings in a config file's text.
+    fn parse(text: &str) -> Self {
+        let mut config = Config::default();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let value = value.trim();
+            match key.trim() {
+                "region" => config.region = parse_region(value),
+                "remote" if !value.is_empty() => config.remote = Some(value.to_string()),
+                "last_host" if !value.is_empty() => config.last_host = Some(value.to_string()),
+                "double_click_ms" => config.double_click_ms = value.parse().ok(),
+                "window" => config.window = parse_window(value),
+                _ => {}
+            }
+        }
+        config
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+    }
+
+    /// The config file's text for these settings.
+    fn to_text(&self) -> String {
+        let mut out = String::from("# colourspace client settings\n");
+        if let Some(r) = self.region {
+            out.push_str(&format!("region = {},{},{},{}\n", r.x, r.y, r.width, r.height));
+        }
+        if let Some(remote) = &self.remote {
+            out.push_str(&format!("remote = {}\n", remote));
+        }
+        if let Some(host) = &self.last_host {
+            out.push_str(&format!("last_host = {}\n", host));
+        }
+        if let Some(ms) = self.double_click_ms {
+            out.push_str(&format!("double_click_ms = {}\n", ms));
+        }
+        if let Some(w) = self.window {
+            out.push_str(&format!("window = {}\n", format_window(w)));
+        }
+        out
+    }
+}
+
+fn parse_region(value: &str) -> Option<PatchRegion> {
+    let parts: Vec<f32> = value.split(',').map(|p| p.trim().parse::<f32>()).collect::<Result<_, _>>().ok()?;
+    match parts[..] {
+        [x, y, width, height] if width > 0.0 && height > 0.0 => Some(PatchRegion { x, y, width, height }),
+        _ => None,
+    }
+}
+
+/// `display,x,y,width,height,fullscreen` with fullscreen as 0 or 1.
+fn format_window(w: WindowGeometry) -> String {
+    format!("{},{},{},{},{},{}", w.display, w.x, w.y, w.width, w.height, w.fullscreen as u8)
+}
+
+fn parse_window(value: &str) -> Option<WindowGeometry> {
+    let parts: Vec<i64> = value.split(',').map(|p| p.trim().parse::<i64>()).collect::<Result<_, _>>().ok()?;
+    match parts[..] {
+        [display, x, y, width, height, fullscreen] if display >= 0 && width > 0 && height > 0 && (fullscreen == 0 || fullscreen == 1) => Some(WindowGeometry {
+            display: i32::try_from(display).ok()?,
+            x: i32::try_from(x).ok()?,
+            y: i32::try_from(y).ok()?,
+            width: u32::try_from(width).ok()?,
+            height: u32::try_from(height).ok()?,
+            fullscreen: fullscreen == 1,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region_is_written_and_read_back() {
+        let region = PatchRegion { x: 0.25, y: 0.1, width: 0.5, height: 0.75 };
+        let config = Config { region: Some(region), remote: Some("10.0.0.5:20002".to_string()), double_click_ms: Some(300), ..Config::default() };
+        let text = config.to_text();
+        assert!(text.contains("\nregion = 0.25,0.1,0.5,0.75\n"), "{}", text);
+        let back = Config::parse(&text);
+        assert_eq!((back.region, back.remote, back.double_click_ms), (Some(region), config.remote, Some(300)));
+
+        assert_eq!(parse_region(" 0, 0 ,1,1"), Some(PatchRegion { x: 0.0, y: 0.0, width: 1.0, height: 1.0 }));
+        for bad in ["0,0,0,1", "0,0,1", "0,0,1,1,1", "a,0,1,1"] {
+            assert_eq!(parse_region(bad), None, "{}", bad);
+        }
+        // a bad line is skipped, the rest still load
+        let config = Config::parse("# settings\nregion = 0,0,-1,1\nnonsense\nfuture_key = 1\nlast_host = meter\n");
+        assert_eq!((config.region, config.last_host.as_deref()), (None, Some("meter")));
+    }
+
+    #[test]
+    fn window_geometry_round_trips() {
+        for w in [
+            WindowGeometry { display: 0, x: 100, y: 80, width: 1280, height: 720, fullscreen: false },
+            // left of the primary display, fullscreen on the second one
+            WindowGeometry { display: 1, x: -1920, y: -40, width: 1920, height: 1080, fullscreen: true },
+        ] {
+            assert_eq!(parse_window(&format_window(w)), Some(w));
+            let back = Config::parse(&Config { window: Some(w), ..Config::default() }.to_text());
+            assert_eq!(back.window, Some(w));
+        }
+        assert_eq!(format_window(WindowGeometry { display: 1, x: -5, y: 7, width: 640, height: 480, fullscreen: true }), "1,-5,7,640,480,1");
+        for bad in ["0,0,0,640,480", "0,0,0,0,480,0", "-1,0,0,640,480,0", "0,0,0,640,480,2", "0,0,0,640,480,0,0", "0,0,0,640.5,480,0", "0,3000000000,0,640,480,0"] {
+            assert_eq!(parse_window(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::thread;
+
+use crate::lan::{ColorRGB, DEPTH_BITS, MeasurementResult, SharedState};
+
+// Line-based remote control. One command per line, one reply line each
+// ("ok" or "error: ..."):
+//
+//   colour R G B [BITS]        show a flat patch, overriding the server (BITS default 8);
+//                              `set` does the same
+//   clear                      back to whatever the server draws
+//   fullscreen [on|off|toggle] (default toggle)
+//   screenshot [PATH]          save the current frame as BMP (default screenshot.bmp);
+//                              `shot` does the same
+//   quit                       close the client
+//   history [N]                reply with the last N readings (default all kept, see
+//                              --history) as one line of JSON, oldest first
+//
+// The same commands can be piped into stdin with --stdin-commands (see spawn_stdin).
+
+/// Where `screenshot`/`shot` without a path saves.
+const DEFAULT_SCREENSHOT: &str = "screenshot.bmp";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    Colour(ColorRGB),
+    Clear,
+    Fullscreen(Option<bool>),
+    Screenshot(PathBuf),
+    Quit,
+}
+
+impl ControlCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let cmd = words.next().ok_or("empty command")?.to_ascii_lowercase();
+        let args: Vec<&str> = words.collect();
+        match (cmd.as_str(), &args[..]) {
+            ("colour" | "color" | "set", [r, g, b, rest @ ..]) if rest.len() <= 1 => {
+                let num = |v: &str| v.parse::<u16>().map_err(|_| format!("bad channel value '{}'", v));
+                let bits = rest.first().map(|v| v.parse::<u8>().map_err(|_| format!("bad bit depth '{}'", v))).transpose()?.unwrap_or(8);
+                if !DEPTH_BITS.contains(&bits) {
+                    return Err(format!("bit depth {} out of range {}-{}", bits, DEPTH_BITS.start(), DEPTH_BITS.end()));
+                }
+                let (r, g, b) = (num(r)?, num(g)?, num(b)?);
+                let max = ((1u32 << bits) - 1) as u16;
+                if r > max || g > max || b > max {
+                    return Err(format!("channel value above {} for {}-bit", max, bits));
+                }
+                Ok(ControlCommand::Colour(ColorRGB::from_components_u16(r, g, b, bits)))
+            }
+            ("clear", []) => Ok(ControlCommand::Clear),
+            ("fullscreen", []) | ("fullscreen", ["toggle"]) => Ok(ControlCommand::Fullscreen(None)),
+            ("fullscreen", ["on"]) => Ok(ControlCommand::Fullscreen(Some(true))),
+            ("fullscreen", ["off"]) => Ok(ControlCommand::Fullscreen(Some(false))),
+            ("screenshot" | "shot", []) => Ok(ControlCommand::Screenshot(PathBuf::from(DEFAULT_SCREENSHOT))),
+            ("screenshot" | "shot", [path]) => Ok(ControlCommand::Screenshot(PathBuf::from(path))),
+            ("quit", []) => Ok(ControlCommand::Quit),
+            _ => Err(format!("unknown or malformed command '{}'", line.trim())),
+        }
+    }
+}
+
+/// Listen on `addr` and forward parsed commands to the returned channel, which the
+/// render loop drains once per frame. Each client gets its own thread. Queries
+/// (`history`) are answered straight from `state`, None when running without a server.
+pub fn spawn_listener(addr: SocketAddr, state: Option<Arc<RwLock<SharedState>>>) -> io::Result<Receiver<ControlCommand>> {
+    let listener = TcpListener::bind(addr)?;
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let (tx, state) = (tx.clone(), state.clone());
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, &tx, state.as_deref()) {
+                            eprintln!("Control client error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Control listener error: {}", e),
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// `--stdin-commands`: read the same commands from stdin, one per line (e.g. piped from
+/// a script), on their own thread. Accepted commands print nothing; errors and
+/// unknown commands go to stderr, query replies to stdout. EOF only stops reading.
+pub fn spawn_stdin(state: Option<Arc<RwLock<SharedState>>>) -> Receiver<ControlCommand> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(reply) = query(&line, state.as_deref()) {
+                match reply {
+                    Ok(reply) => println!("{}", reply),
+                    Err(e) => eprintln!("stdin: {}", e),
+                }
+                continue;
+            }
+            match ControlCommand::parse(&line) {
+                Ok(cmd) => {
+                    if tx.send(cmd).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("stdin: {}", e),
+            }
+        }
+    });
+    rx
+}
+
+fn serve(stream: TcpStream, tx: &Sender<ControlCommand>, state: Option<&RwLock<SharedState>>) -> io::Result<()> {
+    let mut out = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(reply) = query(&line, state) {
+            writeln!(out, "{}", reply.unwrap_or_else(|e| format!("error: {}", e)))?;
+            continue;
+        }
+        let reply = match ControlCommand::parse(&line) {
+            // the render loop is gone: nothing left to control
+            Ok(cmd) => match tx.send(cmd) {
+                Ok(()) => "ok".to_string(),
+                Err(_) => return Ok(()),
+            },
+            Err(e) => format!("error: {}", e),
+        };
+        writeln!(out, "{}", reply)?;
+    }
+    Ok(())
+}
+
+/// Commands that only read state. None means `line` isn't a query.
+fn query(line: &str, state: Option<&RwLock<SharedState>>) -> Option<Result<String, String>> {
+    let mut words = line.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("history") {
+        return None;
+    }
+    let args: Vec<&str> = words.collect();
+    let limit = match &args[..] {
+        [] => None,
+        [n] => match n.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => return Some(Err(format!("bad count '{}'", n))),
+        },
+        _ => return Some(Err(format!("unknown or malformed command '{}'", line.trim()))),
+    };
+    let Some(state) = state else {
+        return Some(Err("no server connection, nothing measured".to_string()));
+    };
+    let r = state.read().unwrap_or_else(PoisonError::into_inner);
+    let skip = limit.map_or(0, |n| r.history.len().saturating_sub(n));
+    let entries: Vec<String> = r.history.iter().skip(skip).map(measurement_json).collect();
+    Some(Ok(format!("[{}]", entries.join(","))))
+}
+
+fn measurement_json(m: &MeasurementResult) -> String {
+    let num = |v: Option<f64>| v.filter(|v| v.is_finite()).map_or("null".to_string(), |v| v.to_string());
+    let label = m.label.as_deref().map_or("null".to_string(), json_string);
+    format!(
+        "{{\"red\":{},\"green\":{},\"blue\":{},\"x\":{},\"y\":{},\"Y\":{},\"label\":{}}}",
+        m.red, m.green, m.blue, num(m.x), num(m.y), num(m.y_lum), label
+    )
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(y_lum: f64, label: Option<&str>) -> MeasurementResult {
+        MeasurementResult {
+            red: 128, green: 128, blue: 128, x: Some(0.3127), y: Some(0.329), y_lum: Some(y_lum),
+            label: label.map(str::to_string), fullscreen: false, hide_markers: false, hold: None, shapes: Vec::new(), error: None,
+        }
+    }
+
+    #[test]
+    fn history_query_replies_with_the_latest_readings() {
+        let state = RwLock::new(SharedState::default());
+        state.write().unwrap().history.extend([reading(1.0, None), reading(2.0, Some("mid \"grey\"")), reading(3.0, None)]);
+        let json = |line: &str| query(line, Some(&state)).unwrap().unwrap();
+
+        assert_eq!(json("history").matches("\"red\"").count(), 3);
+        assert_eq!(
+            json("HISTORY 2"),
+            "[{\"red\":128,\"green\":128,\"blue\":128,\"x\":0.3127,\"y\":0.329,\"Y\":2,\"label\":\"mid \\\"grey\\\"\"},\
+             {\"red\":128,\"green\":128,\"blue\":128,\"x\":0.3127,\"y\":0.329,\"Y\":3,\"label\":null}]"
+        );
+        assert_eq!(json("history 0"), "[]");
+        assert_eq!(query("history two", Some(&state)), Some(Err("bad count 'two'".to_string())));
+        assert!(query("history", None).unwrap().is_err());
+        // anything else is a command, not a query
+        assert_eq!(query("clear", Some(&state)), None);
+    }
+
+    #[test]
+    fn command_parser() {
+        let parse = ControlCommand::parse;
+        assert_eq!(parse("set 128 128 128"), Ok(ControlCommand::Colour(ColorRGB { red: 128, green: 128, blue: 128, depth_bits: 8 })));
+        assert_eq!(parse("  COLOUR 1023 0 512 10 "), Ok(ControlCommand::Colour(ColorRGB { red: 1023, green: 0, blue: 512, depth_bits: 10 })));
+        assert_eq!(parse("color 1 2 3"), parse("colour 1 2 3"));
+        assert_eq!(parse("clear"), Ok(ControlCommand::Clear));
+        assert_eq!(parse("fullscreen"), Ok(ControlCommand::Fullscreen(None)));
+        assert_eq!(parse("fullscreen toggle"), Ok(ControlCommand::Fullscreen(None)));
+        assert_eq!(parse("Fullscreen on"), Ok(ControlCommand::Fullscreen(Some(true))));
+        assert_eq!(parse("fullscreen off"), Ok(ControlCommand::Fullscreen(Some(false))));
+        assert_eq!(parse("shot"), Ok(ControlCommand::Screenshot(PathBuf::from("screenshot.bmp"))));
+        assert_eq!(parse("screenshot out/frame.bmp"), Ok(ControlCommand::Screenshot(PathBuf::from("out/frame.bmp"))));
+        assert_eq!(parse("quit"), Ok(ControlCommand::Quit));
+
+        assert_eq!(parse("set 256 0 0"), Err("channel value above 255 for 8-bit".to_string()));
+        assert_eq!(parse("set 1 2 x"), Err("bad channel value 'x'".to_string()));
+        assert_eq!(parse("set 1 2 3 17"), Err("bit depth 17 out of range 1-16".to_string()));
+        assert_eq!(parse("   "), Err("empty command".to_string()));
+        for bad in ["set 1 2", "set 1 2 3 8 9", "fullscreen maybe", "quit now", "dance"] {
+            assert_eq!(parse(bad), Err(format!("unknown or malformed command '{}'", bad)));
+        }
+    }
+}
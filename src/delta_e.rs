@@ -0,0 +1,171 @@
+use std::str::FromStr;
+
+// Colour difference between the latest reading and a target (--target), for matching
+// by eye against the overlay.
+//
+// Both colours are taken as xyY, converted to XYZ and then to CIELAB against one
+// reference white: D65 chromaticity (x 0.3127, y 0.3290) at luminance `white_y`
+// (--reference-white-y, in the meter's units, normally cd/m2; 100 by default). Y above
+// the white's is fine, L* just goes past 100. Pick the white that the target was
+// specified against, or the ΔE is only good for relative comparisons.
+
+/// D65 white point chromaticity x, y.
+pub const D65: (f64, f64) = (0.3127, 0.3290);
+
+/// Which ΔE formula the overlay shows (`--delta-e`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Formula {
+    /// CIE76: plain Euclidean distance in Lab
+    Cie76,
+    /// CIEDE2000, weighting factors kL = kC = kH = 1
+    Cie2000,
+}
+
+impl FromStr for Formula {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "76" | "cie76" => Ok(Formula::Cie76),
+            "2000" | "ciede2000" | "de2000" => Ok(Formula::Cie2000),
+            other => Err(format!("unknown delta E formula '{}', expected: 76, 2000", other)),
+        }
+    }
+}
+
+impl Formula {
+    pub fn name(self) -> &'static str {
+        match self {
+            Formula::Cie76 => "dE76",
+            Formula::Cie2000 => "dE2000",
+        }
+    }
+
+    pub fn delta_e(self, a: [f64; 3], b: [f64; 3]) -> f64 {
+        match self {
+            Formula::Cie76 => delta_e76(a, b),
+            Formula::Cie2000 => delta_e2000(a, b),
+        }
+    }
+}
+
+/// xyY to XYZ. A chromaticity with y = 0 has no defined XYZ; it maps to black.
+pub fn xyy_to_xyz(x: f64, y: f64, big_y: f64) -> [f64; 3] {
+    if y == 0.0 {
+        return [0.0; 3];
+    }
+    [x * big_y / y, big_y, (1.0 - x - y) * big_y / y]
+}
+
+/// XYZ to CIELAB relative to `white` (XYZ of the reference white).
+pub fn xyz_to_lab(xyz: [f64; 3], white: [f64; 3]) -> [f64; 3] {
+    const EPSILON: f64 = 216.0 / 24389.0;
+    const KAPPA: f64 = 24389.0 / 27.0;
+    let f = |t: f64| if t > EPSILON { t.cbrt() } else { (KAPPA * t + 16.0) / 116.0 };
+    let (fx, fy, fz) = (f(xyz[0] / white[0]), f(xyz[1] / white[1]), f(xyz[2] / white[2]));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Lab of a measured xyY against the D65 white at luminance `white_y` (see above).
+pub fn xyy_to_lab(x: f64, y: f64, big_y: f64, white_y: f64) -> [f64; 3] {
+    xyz_to_lab(xyy_to_xyz(x, y, big_y), xyy_to_xyz(D65.0, D65.1, white_y))
+}
+
+pub fn delta_e76(a: [f64; 3], b: [f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// CIEDE2000 (Sharma, Wu and Dalal's formulation, angles in degrees).
+pub fn delta_e2000(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+    let c_bar = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let g = 0.5 * (1.0 - (c_bar.powi(7) / (c_bar.powi(7) + 25f64.powi(7))).sqrt());
+    let (a1p, a2p) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1p, c2p) = (a1p.hypot(b1), a2p.hypot(b2));
+    let hue = |b: f64, a: f64| if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) };
+    let (h1p, h2p) = (hue(b1, a1p), hue(b2, a2p));
+
+    let dl = l2 - l1;
+    let dc = c2p - c1p;
+    let dh = if c1p * c2p == 0.0 {
+        0.0
+    } else if (h2p - h1p).abs() <= 180.0 {
+        h2p - h1p
+    } else if h2p - h1p > 180.0 {
+        h2p - h1p - 360.0
+    } else {
+        h2p - h1p + 360.0
+    };
+    let dh_big = 2.0 * (c1p * c2p).sqrt() * (dh / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar_p = (c1p + c2p) / 2.0;
+    let h_bar = if c1p * c2p == 0.0 {
+        h1p + h2p
+    } else if (h1p - h2p).abs() <= 180.0 {
+        (h1p + h2p) / 2.0
+    } else if h1p + h2p < 360.0 {
+        (h1p + h2p + 360.0) / 2.0
+    } else {
+        (h1p + h2p - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos() + 0.24 * (2.0 * h_bar).to_radians().cos() + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+    - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_bar_p.powi(7) / (c_bar_p.powi(7) + 25f64.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar_p;
+    let s_h = 1.0 + 0.015 * c_bar_p * t;
+    let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+
+    let (tl, tc, th) = (dl / s_l, dc / s_c, dh_big / s_h);
+    (tl * tl + tc * tc + th * th + r_t * tc * th).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ciede2000_reference_pairs() {
+        // Sharma, Wu and Dalal's test data, pairs 1, 7, 13, 17, 21, 25 (hue wrap-around,
+        // achromatic and large differences included)
+        let pairs = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+            ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0009], 7.1792),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            ([50.0, 2.5, 0.0], [50.0, 3.1736, 0.5854], 1.0000),
+            ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+        ];
+        for (a, b, expected) in pairs {
+            assert!((delta_e2000(a, b) - expected).abs() < 5e-5, "{:?} {:?}: {} != {}", a, b, delta_e2000(a, b), expected);
+            assert!((delta_e2000(b, a) - expected).abs() < 5e-5, "{:?} {:?} swapped", a, b);
+        }
+        assert_eq!(delta_e2000([40.0, 10.0, -5.0], [40.0, 10.0, -5.0]), 0.0);
+    }
+
+    #[test]
+    fn cie76_is_lab_distance() {
+        assert_eq!(delta_e76([50.0, 0.0, 0.0], [53.0, 4.0, 0.0]), 5.0);
+        assert_eq!(Formula::Cie76.delta_e([50.0, 0.0, 0.0], [50.0, 0.0, -12.0]), 12.0);
+    }
+
+    #[test]
+    fn white_and_black_in_lab() {
+        let white = xyy_to_lab(D65.0, D65.1, 100.0, 100.0);
+        assert!((white[0] - 100.0).abs() < 1e-9 && white[1].abs() < 1e-9 && white[2].abs() < 1e-9, "{:?}", white);
+        // half the luminance of the white: L* 76.07
+        assert!((xyy_to_lab(D65.0, D65.1, 50.0, 100.0)[0] - 76.0693).abs() < 1e-4);
+        assert_eq!(xyy_to_lab(0.3, 0.0, 10.0, 100.0), [0.0; 3]);
+    }
+
+    #[test]
+    fn formula_names() {
+        assert_eq!("2000".parse(), Ok(Formula::Cie2000));
+        assert_eq!("CIE76".parse(), Ok(Formula::Cie76));
+        assert!("94".parse::<Formula>().is_err());
+    }
+}
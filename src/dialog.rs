@@ -0,0 +1,99 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tinyfiledialogs as tfd;
+
+// Desktop dialogs (tinyfiledialogs) with a terminal fallback. On stripped-down installs
+// without zenity/kdialog/etc. tinyfiledialogs has nothing to show a dialog with, so
+// prompts read from stdin and errors go to stderr instead. --no-dialogs forces that.
+
+static DIALOGS: AtomicBool = AtomicBool::new(true);
+
+/// Decide once at startup whether dialogs can be shown. With `no_dialogs`, or when
+/// tinyfiledialogs reports it would only have a console "dialog" to offer, the terminal
+/// is used. Returns whether desktop dialogs are on.
+pub fn init(no_dialogs: bool) -> bool {
+    // "tinyfd_query" as the title asks tinyfiledialogs which backend it would use,
+    // without showing anything: Ok means a graphical one
+    let graphical = use_dialogs(no_dialogs, || {
+        tfd::message_box_ok_cancel("tinyfd_query", "", tfd::MessageBoxIcon::Info, tfd::OkCancel::Cancel) == tfd::OkCancel::Ok
+    });
+    DIALOGS.store(graphical, Ordering::Relaxed);
+    graphical
+}
+
+/// The decision of `init`, with the backend query passed in. The query is not made
+/// under --no-dialogs.
+fn use_dialogs(no_dialogs: bool, query: impl FnOnce() -> bool) -> bool {
+    !no_dialogs && query()
+}
+
+/// Whether desktop dialogs are in use (see `init`).
+pub fn enabled() -> bool {
+    DIALOGS.load(Ordering::Relaxed)
+}
+
+/// Show an error. Blocks until dismissed when it is a dialog.
+pub fn error(title: &str, message: &str) {
+    if enabled() {
+        tfd::message_box_ok(title, message, tfd::MessageBoxIcon::Error);
+    } else {
+        eprintln!("{}: {}", title, message.replace("\n\n", "\n"));
+    }
+}
+
+/// Show some information. Blocks until dismissed when it is a dialog.
+pub fn info(title: &str, message: &str) {
+    if enabled() {
+        tfd::message_box_ok(title, message, tfd::MessageBoxIcon::Info);
+    } else {
+        eprintln!("{}:\n{}", title, message);
+    }
+}
+
+/// Ask for one line of text, pre-filled with `default`. None if the user cancels: an
+/// empty answer, or EOF on stdin (an empty line there takes the default, if any).
+pub fn input(title: &str, prompt: &str, default: &str) -> Option<String> {
+    if enabled() {
+        return tfd::input_box(title, prompt, default);
+    }
+    prompt_line(&mut io::stdin().lock(), &mut io::stderr(), prompt.trim(), default)
+}
+
+/// The terminal side of `input`, on any reader/writer.
+fn prompt_line(input: &mut impl BufRead, out: &mut impl Write, prompt: &str, default: &str) -> Option<String> {
+    let _ = if default.is_empty() { write!(out, "{} ", prompt) } else { write!(out, "{} [{}] ", prompt, default) };
+    let _ = out.flush();
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(if line.trim().is_empty() { default.to_string() } else { line.trim().to_string() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_dialog_backend_falls_back_to_the_terminal() {
+        assert!(use_dialogs(false, || true));
+        // tinyfiledialogs has no graphical backend: the query fails
+        assert!(!use_dialogs(false, || false));
+        assert!(!use_dialogs(true, || panic!("--no-dialogs must not query the backend")));
+    }
+
+    #[test]
+    fn terminal_prompt() {
+        let ask = |stdin: &str, default: &str| {
+            let mut out = Vec::new();
+            let answer = prompt_line(&mut io::Cursor::new(stdin.as_bytes()), &mut out, "Address:", default);
+            (answer, String::from_utf8(out).unwrap())
+        };
+        assert_eq!(ask(" 192.168.1.5 \n", ""), (Some("192.168.1.5".to_string()), "Address: ".to_string()));
+        assert_eq!(ask("\n", "localhost"), (Some("localhost".to_string()), "Address: [localhost] ".to_string()));
+        // EOF is a cancel, like closing the dialog
+        assert_eq!(ask("", "localhost").0, None);
+        assert_eq!(ask("\n", "").0, Some(String::new()));
+    }
+}
@@ -0,0 +1,704 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::lan::{self, ColorRGB, PatchRegion, ShapeInstruction};
+use crate::overlay;
+use crate::pattern;
+use crate::sweep::UniformityGrid;
+
+// Where patches land on the drawable, and drawing them there. Everything is laid out in
+// drawable pixels (see `hidpi_scale` in main.rs).
+
+/// Which of several drawn patches is the one being measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeasureTarget {
+    /// smallest area (the usual window-on-background layout)
+    Smallest,
+    Largest,
+    /// first shape in the document
+    First,
+}
+
+impl std::str::FromStr for MeasureTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "smallest" => Ok(MeasureTarget::Smallest),
+            "largest" => Ok(MeasureTarget::Largest),
+            "first" => Ok(MeasureTarget::First),
+            other => Err(format!("unknown measure target '{}', expected: smallest, largest, first", other)),
+        }
+    }
+}
+
+/// The patch picked by `target`, comparing sizes as drawn in `area` (so normalized
+/// and pixel geometry rank alike). Shapes with a NaN size are never picked by size;
+/// if every size is NaN the first shape is used.
+pub fn select_measure_shape(shapes: &[ShapeInstruction], target: MeasureTarget, area: Rect) -> Option<&ShapeInstruction> {
+    let sized = || {
+        shapes
+        .iter()
+        .filter(|shape| match shape {
+            ShapeInstruction::Rectangle(rect) => !rect.geometry.width.is_nan() && !rect.geometry.height.is_nan(),
+            ShapeInstruction::Circle(circle) => !circle.geometry.r.is_nan(),
+            ShapeInstruction::Ellipse(ellipse) => !ellipse.geometry.rx.is_nan() && !ellipse.geometry.ry.is_nan(),
+        })
+        .map(|shape| {
+            let drawn = shape_rect(area, shape, (0.0, 0.0));
+            let (w, h) = (drawn.width() as f32, drawn.height() as f32);
+            let pixels = match shape {
+                ShapeInstruction::Rectangle(rect) => {
+                    // rounded corners cut (4 - pi) r^2 off the box, r as `draw_shapes` rounds it
+                    let r = ((rect.corner_radius * w.min(h)).round() as u32).min(drawn.width() / 2).min(drawn.height() / 2) as f32;
+                    w * h - (4.0 - std::f32::consts::PI) * r * r
+                }
+                ShapeInstruction::Circle(_) | ShapeInstruction::Ellipse(_) => std::f32::consts::PI * w * h / 4.0,
+            };
+            (pixels, shape)
+        })
+    };
+    let first = || shapes.first();
+
+    match target {
+        MeasureTarget::First => first(),
+        MeasureTarget::Smallest => sized().min_by(|a, b| a.0.total_cmp(&b.0)).map(|(_, r)| r).or_else(first),
+        MeasureTarget::Largest => sized().max_by(|a, b| a.0.total_cmp(&b.0)).map(|(_, r)| r).or_else(first),
+    }
+}
+
+/// Colour of the patch picked by `target` (see `select_measure_shape`).
+pub fn select_measure_colour(shapes: &[ShapeInstruction], target: MeasureTarget, area: Rect) -> Option<ColorRGB> {
+    select_measure_shape(shapes, target, area).map(ShapeInstruction::color)
+}
+
+/// Display-only channel isolation (R/G/B keys, A restores). Applied to what is
+/// drawn, never to the colours requested from or reported to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelView {
+    All,
+    Red,
+    Green,
+    Blue,
+}
+
+impl ChannelView {
+    pub fn apply(self, c: ColorRGB) -> ColorRGB {
+        match self {
+            ChannelView::All => c,
+            ChannelView::Red => ColorRGB { green: 0, blue: 0, ..c },
+            ChannelView::Green => ColorRGB { red: 0, blue: 0, ..c },
+            ChannelView::Blue => ColorRGB { red: 0, green: 0, ..c },
+        }
+    }
+}
+
+/// Helper: convert a `ColorRGB` (u16 + depth_bits) into an 8-bit RGB tuple.
+///
+/// Note: this is intentionally kept out of `lan` so that module stays
+/// depth-agnostic. When you add a Vulkan 10-bit pipeline, replace or extend
+/// this helper to return higher-bit buffers or skip the conversion entirely.
+pub fn color_to_u8_tuple(color: ColorRGB) -> (u8, u8, u8) {
+    let bits = if color.depth_bits == 0 { 8 } else { color.depth_bits };
+    let max_in: u32 = if bits >= 16 {
+        0xFFFF
+    } else {
+        (1u32 << bits as u32) - 1
+    };
+
+    // avoid division by zero (defensive)
+    let max_in = if max_in == 0 { 255 } else { max_in };
+
+    let scale = |v: u16| ((v as u32 * 255 + max_in / 2) / max_in) as u8;
+    // greys (most sweeps) convert once
+    if color.is_grey() {
+        let v = scale(color.red);
+        return (v, v, v);
+    }
+    (scale(color.red), scale(color.green), scale(color.blue))
+}
+
+/// Pixel rectangle patches are laid out in: the selected region if any,
+/// otherwise the whole `w`x`h` drawable.
+pub fn region_to_pixels(region: Option<PatchRegion>, w: u32, h: u32) -> Rect {
+    match region {
+        Some(r) => {
+            let left = (r.x.clamp(0.0, 1.0) * w as f32).round() as i32;
+            let top = (r.y.clamp(0.0, 1.0) * h as f32).round() as i32;
+            let rw = (r.width.clamp(0.0, 1.0) * w as f32).round().max(1.0) as u32;
+            let rh = (r.height.clamp(0.0, 1.0) * h as f32).round().max(1.0) as u32;
+            Rect::new(left, top, rw, rh)
+        }
+        None => Rect::new(0, 0, w.max(1), h.max(1)),
+    }
+}
+
+/// The two "background" colours. They are easy to mix up, so kept apart:
+/// - `clear` is the canvas outside the active region: the bars around a user-drawn
+///   region (letterboxing) or whatever a patch offset uncovers. Never measured.
+/// - `surround` is the part of the region a patch doesn't cover: around a window
+///   pattern, or the other cells of a uniformity grid. It sets the picture level the
+///   panel sees around the patch, so it can change the reading.
+///
+/// A flat field fills its whole region, so it has no surround. Both default to black.
+#[derive(Debug, Clone, Copy)]
+pub struct Backdrop {
+    pub clear: Color,
+    pub surround: Color,
+}
+
+impl Backdrop {
+    /// `clear` over the whole canvas, then `surround` over `area`.
+    pub fn fill(self, canvas: &mut Canvas<Window>, area: Rect) -> Result<(), String> {
+        canvas.set_draw_color(self.clear);
+        canvas.fill_rect(None)?;
+        canvas.set_draw_color(self.surround);
+        canvas.fill_rect(area)
+    }
+}
+
+/// Flat field of `colour`: the whole canvas, or just the region (the backdrop's clear
+/// colour elsewhere).
+pub fn fill_field(
+    canvas: &mut Canvas<Window>,
+    colour: ColorRGB,
+    region: Option<PatchRegion>,
+    view: ChannelView,
+    backdrop: Backdrop,
+    w: u32,
+    h: u32,
+) -> Result<(), String> {
+    // downscale before giving to SDL using the helper
+    // (fill_rect(None) rather than clear(): clear() panics on a lost renderer)
+    let (r8, g8, b8) = color_to_u8_tuple(view.apply(colour));
+    if region.is_some() {
+        canvas.set_draw_color(backdrop.clear);
+        canvas.fill_rect(None)?;
+        canvas.set_draw_color(Color::RGB(r8, g8, b8));
+        canvas.fill_rect(region_to_pixels(region, w, h))
+    } else {
+        canvas.set_draw_color(Color::RGB(r8, g8, b8));
+        canvas.fill_rect(None)
+    }
+}
+
+/// Pixel rectangle of cell (row, col) of a uniformity grid laid over `area`.
+pub fn cell_to_pixels(area: Rect, (grid, row, col): (UniformityGrid, usize, usize)) -> Rect {
+    // cell edges are rounded independently so the cells tile the area exactly
+    let edge = |i: usize, n: usize, len: u32| (i as f32 / n as f32 * len as f32).round() as i32;
+    let (left, right) = (edge(col, grid.cols, area.width()), edge(col + 1, grid.cols, area.width()));
+    let (top, bottom) = (edge(row, grid.rows, area.height()), edge(row + 1, grid.rows, area.height()));
+    Rect::new(area.x() + left, area.y() + top, (right - left).max(1) as u32, (bottom - top).max(1) as u32)
+}
+
+/// Uniformity scan patch: `colour` in one grid cell over `area` (the region or whole
+/// drawable), the backdrop's surround in the other cells.
+pub fn fill_cell(
+    canvas: &mut Canvas<Window>,
+    colour: ColorRGB,
+    cell: (UniformityGrid, usize, usize),
+    area: Rect,
+    view: ChannelView,
+    backdrop: Backdrop,
+) -> Result<(), String> {
+    backdrop.fill(canvas, area)?;
+    let (r8, g8, b8) = color_to_u8_tuple(view.apply(colour));
+    canvas.set_draw_color(Color::RGB(r8, g8, b8));
+    canvas.fill_rect(cell_to_pixels(area, cell))
+}
+
+/// What the window shows before there is a server to follow: `colour` over everything,
+/// plus `text` (e.g. "Connecting to ...") in the corner when given.
+pub fn draw_idle(canvas: &mut Canvas<Window>, colour: Color, text: Option<&str>) -> Result<(), String> {
+    canvas.set_draw_color(colour);
+    canvas.fill_rect(None)?;
+    if let Some(text) = text {
+        let (_, ch) = canvas.output_size()?;
+        overlay::draw_panel(canvas, &[text.to_string()], overlay::scale_for(ch))?;
+    }
+    canvas.present();
+    Ok(())
+}
+
+/// Colour bars (`--pattern smpte/ebu`) stretched over `area`. Bar edges are rounded to
+/// whole pixels from the fractions, so neighbours always meet without gaps.
+pub fn draw_bars(canvas: &mut Canvas<Window>, bars: &[pattern::Bar], area: Rect, view: ChannelView, backdrop: Backdrop) -> Result<(), String> {
+    backdrop.fill(canvas, area)?;
+    let x = |f: f32| area.x() + (f * area.width() as f32).round() as i32;
+    let y = |f: f32| area.y() + (f * area.height() as f32).round() as i32;
+    for bar in bars {
+        let (left, top, right, bottom) = (x(bar.left), y(bar.top), x(bar.right), y(bar.bottom));
+        if right <= left || bottom <= top {
+            continue;
+        }
+        let (r8, g8, b8) = color_to_u8_tuple(view.apply(bar.colour));
+        canvas.set_draw_color(Color::RGB(r8, g8, b8));
+        canvas.fill_rect(Rect::new(left, top, (right - left) as u32, (bottom - top) as u32))?;
+    }
+    Ok(())
+}
+
+/// Pixel rectangle of a patch of size `geom` centred in `area`, moved by `offset`
+/// (fractions of the area, `--patch-offset`) but kept entirely inside the area.
+pub fn patch_rect(area: Rect, geom: lan::RectangleGeometry, offset: (f32, f32)) -> Rect {
+    let (aw, ah) = (area.width() as f32, area.height() as f32);
+    // normalized sizes are fractions of the area, pixel sizes are taken as-is;
+    // either way the patch stays inside the area and is at least 1 pixel
+    let (sx, sy) = match geom.units {
+        lan::GeometryUnits::Normalized => (aw, ah),
+        lan::GeometryUnits::Pixels => (1.0, 1.0),
+    };
+    let rw = (geom.width * sx).round().clamp(1.0, aw.max(1.0)) as u32;
+    let rh = (geom.height * sy).round().clamp(1.0, ah.max(1.0)) as u32;
+
+    let left = ((aw - rw as f32) / 2.0 + offset.0 * aw).round().clamp(0.0, aw - rw as f32) as i32;
+    let top = ((ah - rh as f32) / 2.0 + offset.1 * ah).round().clamp(0.0, ah - rh as f32) as i32;
+    Rect::new(area.x() + left, area.y() + top, rw, rh)
+}
+
+/// Draw the patches centred in the region. With `focus`, only that shape is drawn,
+/// filling the whole region so it can be measured on its own.
+pub fn draw_shapes(
+    canvas: &mut Canvas<Window>,
+    shapes: &[ShapeInstruction],
+    focus: Option<usize>,
+    area: Rect,
+    offset: (f32, f32),
+    view: ChannelView,
+    backdrop: Backdrop,
+) -> Result<(), String> {
+    // patches are sized and centred relative to `area` (the region), surround around
+    // them, clear colour outside the area
+    backdrop.fill(canvas, area)?;
+
+    if let Some(shape) = focus.and_then(|i| shapes.get(i)) {
+        let (r8, g8, b8) = color_to_u8_tuple(view.apply(shape.color()));
+        canvas.set_draw_color(Color::RGB(r8, g8, b8));
+        return canvas.fill_rect(area);
+    }
+
+    // lowest layer first; the sort is stable, so equal layers keep document order
+    let mut layered: Vec<&ShapeInstruction> = shapes.iter().collect();
+    layered.sort_by_key(|shape| shape.z());
+    for shape in layered {
+        match shape {
+            ShapeInstruction::Rectangle(rect) => {
+                let mut target = patch_rect(area, rect.geometry, offset);
+                let mut radius = (rect.corner_radius * target.width().min(target.height()) as f32).round() as u32;
+                // bordered patch: edge colour over the whole patch, fill inset by 5% of
+                // its shorter side (at least 1px)
+                if let Some(border) = rect.border {
+                    let (r8, g8, b8) = color_to_u8_tuple(view.apply(border));
+                    canvas.set_draw_color(Color::RGB(r8, g8, b8));
+                    fill_rounded(canvas, target, radius)?;
+                    let inset = (target.width().min(target.height()) / 20).max(1);
+                    if target.width() <= 2 * inset || target.height() <= 2 * inset {
+                        continue;
+                    }
+                    target = Rect::new(target.x() + inset as i32, target.y() + inset as i32, target.width() - 2 * inset, target.height() - 2 * inset);
+                    radius = radius.saturating_sub(inset);
+                }
+                let color = view.apply(rect.color);
+                // downscale from u16/depth to u8 here using local helper
+                let (r8, g8, b8) = color_to_u8_tuple(color);
+                canvas.set_draw_color(Color::RGB(r8, g8, b8));
+                fill_rounded(canvas, target, radius)?;
+            }
+            ShapeInstruction::Circle(_) | ShapeInstruction::Ellipse(_) => {
+                let (r8, g8, b8) = color_to_u8_tuple(view.apply(shape.color()));
+                canvas.set_draw_color(Color::RGB(r8, g8, b8));
+                fill_ellipse(canvas, shape_rect(area, shape, offset))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Bounding box in pixels of any shape: `patch_rect` for rectangles; for circles and
+/// ellipses the box around their centre (the region's centre unless given), moved by
+/// `offset` and kept inside the area the same way.
+pub fn shape_rect(area: Rect, shape: &ShapeInstruction, offset: (f32, f32)) -> Rect {
+    let (aw, ah) = (area.width() as f32, area.height() as f32);
+    let (cx, cy, rx, ry, units) = match shape {
+        ShapeInstruction::Rectangle(rect) => return patch_rect(area, rect.geometry, offset),
+        ShapeInstruction::Circle(c) => {
+            let g = c.geometry;
+            // a normalized radius is of the shorter side, so the circle stays round
+            let scale = if g.units == lan::GeometryUnits::Normalized { aw.min(ah) } else { 1.0 };
+            (g.cx, g.cy, g.r * scale, g.r * scale, g.units)
+        }
+        ShapeInstruction::Ellipse(e) => {
+            let g = e.geometry;
+            let (sx, sy) = if g.units == lan::GeometryUnits::Normalized { (aw, ah) } else { (1.0, 1.0) };
+            (g.cx, g.cy, g.rx * sx, g.ry * sy, g.units)
+        }
+    };
+    let (sx, sy) = match units {
+        lan::GeometryUnits::Normalized => (aw, ah),
+        lan::GeometryUnits::Pixels => (1.0, 1.0),
+    };
+    let w = (2.0 * rx).round().clamp(1.0, aw.max(1.0));
+    let h = (2.0 * ry).round().clamp(1.0, ah.max(1.0));
+    let centre_x = cx.map_or(aw / 2.0, |cx| cx * sx) + offset.0 * aw;
+    let centre_y = cy.map_or(ah / 2.0, |cy| cy * sy) + offset.1 * ah;
+    let left = (centre_x - w / 2.0).round().clamp(0.0, aw - w) as i32;
+    let top = (centre_y - h / 2.0).round().clamp(0.0, ah - h) as i32;
+    Rect::new(area.x() + left, area.y() + top, w as u32, h as u32)
+}
+
+/// Fill the ellipse inscribed in `rect` (a circle when it is square). SDL has no
+/// filled ellipse, so it is scan-converted with the midpoint test: a pixel is in when
+/// its centre is, checked in integer arithmetic on doubled coordinates (so even sizes,
+/// whose centre falls between pixels, are exact). Rows go outward from the middle and
+/// each row's first column only moves right, so every span is found by stepping on from
+/// the last; each is drawn with its mirror row.
+fn fill_ellipse(canvas: &mut Canvas<Window>, rect: Rect) -> Result<(), String> {
+    let (w, h) = (rect.width() as i64, rect.height() as i64);
+    // F(x, y) <= 0 inside, with x, y the pixel centre's doubled offset from the middle
+    let inside = |col: i64, row: i64| {
+        let (x, y) = (2 * col + 1 - w, 2 * row + 1 - h);
+        x * x * h * h + y * y * w * w <= w * w * h * h
+    };
+    let mut first = 0;
+    for row in h / 2..h {
+        while first < w - first && !inside(first, row) {
+            first += 1;
+        }
+        if first >= w - first {
+            break;
+        }
+        let span = (w - 2 * first) as u32;
+        canvas.fill_rect(Rect::new(rect.x() + first as i32, rect.y() + row as i32, span, 1))?;
+        let mirror = h - 1 - row;
+        if mirror != row {
+            canvas.fill_rect(Rect::new(rect.x() + first as i32, rect.y() + mirror as i32, span, 1))?;
+        }
+    }
+    Ok(())
+}
+
+/// Fill `rect` with its corners rounded to `radius` pixels; 0 is a plain `fill_rect`.
+/// Rounded: the straight middle band in one go, then a line per pixel row of the
+/// top and bottom arcs, inset to where the circle crosses that row's centre.
+fn fill_rounded(canvas: &mut Canvas<Window>, rect: Rect, radius: u32) -> Result<(), String> {
+    let r = radius.min(rect.width() / 2).min(rect.height() / 2);
+    if r == 0 {
+        return canvas.fill_rect(rect);
+    }
+    if rect.height() > 2 * r {
+        canvas.fill_rect(Rect::new(rect.x(), rect.y() + r as i32, rect.width(), rect.height() - 2 * r))?;
+    }
+    for i in 0..r {
+        let dy = r as f32 - i as f32 - 0.5;
+        let inset = r - ((r * r) as f32 - dy * dy).max(0.0).sqrt().round() as u32;
+        let w = rect.width() - 2 * inset;
+        if w == 0 {
+            continue;
+        }
+        canvas.fill_rect(Rect::new(rect.x() + inset as i32, rect.y() + i as i32, w, 1))?;
+        canvas.fill_rect(Rect::new(rect.x() + inset as i32, rect.bottom() - 1 - i as i32, w, 1))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::lan::{CircleGeometry, CircleShape, Clamped, GeometryUnits, RectangleGeometry, RectangleShape};
+    use sdl2::pixels::PixelFormatEnum;
+    use std::sync::mpsc;
+    use std::sync::{Mutex, OnceLock};
+
+    type Job = Box<dyn FnOnce(&sdl2::VideoSubsystem) + Send>;
+
+    /// Run `job` against SDL's video subsystem (the dummy driver, no display needed) and
+    /// return what it returns. SDL may only ever be initialised from one thread and tests
+    /// run on many, so every job runs on one thread kept for that.
+    pub(crate) fn with_video<T: Send + 'static>(job: impl FnOnce(&sdl2::VideoSubsystem) -> T + Send + 'static) -> T {
+        static SDL: OnceLock<Mutex<mpsc::Sender<Job>>> = OnceLock::new();
+        let jobs = SDL.get_or_init(|| {
+            let (tx, rx) = mpsc::channel::<Job>();
+            std::thread::spawn(move || {
+                sdl2::hint::set("SDL_VIDEODRIVER", "dummy");
+                let sdl = sdl2::init().unwrap();
+                let video = sdl.video().unwrap();
+                for job in rx { job(&video); }
+            });
+            Mutex::new(tx)
+        });
+        let (tx, rx) = mpsc::channel();
+        jobs.lock().unwrap().send(Box::new(move |video: &sdl2::VideoSubsystem| { let _ = tx.send(job(video)); })).unwrap();
+        rx.recv().expect("test job on the SDL thread panicked")
+    }
+
+    /// A hidden `w`x`h` window with a software canvas.
+    pub(crate) fn software_canvas(video: &sdl2::VideoSubsystem, w: u32, h: u32) -> Canvas<Window> {
+        video.window("test", w, h).hidden().build().unwrap().into_canvas().software().build().unwrap()
+    }
+
+    /// Run `draw` on a `w`x`h` software canvas and return what it returns.
+    pub(crate) fn with_canvas<T: Send + 'static>(w: u32, h: u32, draw: impl FnOnce(&mut Canvas<Window>) -> T + Send + 'static) -> T {
+        with_video(move |video| draw(&mut software_canvas(video, w, h)))
+    }
+
+    /// The canvas as rows of RGB pixels.
+    pub(crate) fn pixels(canvas: &Canvas<Window>) -> Vec<Vec<(u8, u8, u8)>> {
+        let (w, _) = canvas.output_size().unwrap();
+        let bytes = canvas.read_pixels(None, PixelFormatEnum::RGB24).unwrap();
+        bytes.chunks(w as usize * 3).map(|row| row.chunks(3).map(|p| (p[0], p[1], p[2])).collect()).collect()
+    }
+
+    fn canvas() -> Rect { Rect::new(0, 0, 1920, 1080) }
+
+    fn rectangle_shape(width: f32, height: f32, units: GeometryUnits, red: u16) -> RectangleShape {
+        RectangleShape {
+            color: ColorRGB { red, green: 0, blue: 0, depth_bits: 8 },
+            geometry: RectangleGeometry { width, height, units },
+            ycbcr: None, xyz: None, clamped: Clamped::default(), border: None, corner_radius: 0.0, z: 0,
+        }
+    }
+
+    fn rectangle(width: f32, height: f32, units: GeometryUnits, red: u16) -> ShapeInstruction {
+        ShapeInstruction::Rectangle(rectangle_shape(width, height, units, red))
+    }
+
+    #[test]
+    fn patch_sizes_follow_their_units() {
+        let normalized = RectangleGeometry { width: 0.1, height: 0.1, units: GeometryUnits::Normalized };
+        let pixels = RectangleGeometry { width: 200.0, height: 200.0, units: GeometryUnits::Pixels };
+        assert_eq!(patch_rect(canvas(), normalized, (0.0, 0.0)), Rect::new(864, 486, 192, 108));
+        assert_eq!(patch_rect(canvas(), pixels, (0.0, 0.0)), Rect::new(860, 440, 200, 200));
+        // pixel sizes are capped at the area, not at 1.0
+        let huge = RectangleGeometry { width: 5000.0, height: 50.0, units: GeometryUnits::Pixels };
+        assert_eq!(patch_rect(canvas(), huge, (0.0, 0.0)), Rect::new(0, 515, 1920, 50));
+    }
+
+    #[test]
+    fn measure_target_compares_drawn_pixels_across_units() {
+        // 0.25 of 1920x1080 is 480x270 px, larger than the 200x200 px patch
+        let shapes = [rectangle(0.25, 0.25, GeometryUnits::Normalized, 1), rectangle(200.0, 200.0, GeometryUnits::Pixels, 2)];
+        assert_eq!(select_measure_colour(&shapes, MeasureTarget::Smallest, canvas()).map(|c| c.red), Some(2));
+        assert_eq!(select_measure_colour(&shapes, MeasureTarget::Largest, canvas()).map(|c| c.red), Some(1));
+        // on a 400x400 region the normalized patch is the 100x100 one
+        let small = Rect::new(0, 0, 400, 400);
+        assert_eq!(select_measure_colour(&shapes, MeasureTarget::Smallest, small).map(|c| c.red), Some(1));
+    }
+
+    #[test]
+    fn ellipse_fill_covers_the_pixels_whose_centres_are_inside() {
+        for (w, h) in [(9, 5), (8, 8), (7, 12), (1, 1), (2, 3)] {
+            let drawn = with_canvas(w, h, move |canvas| {
+                canvas.set_draw_color(Color::RGB(0, 0, 0));
+                canvas.fill_rect(None).unwrap();
+                canvas.set_draw_color(Color::RGB(255, 255, 255));
+                fill_ellipse(canvas, Rect::new(0, 0, w, h)).unwrap();
+                pixels(canvas)
+            });
+            let (a, b) = (w as f64 / 2.0, h as f64 / 2.0);
+            for (row, line) in drawn.iter().enumerate() {
+                for (col, &p) in line.iter().enumerate() {
+                    let (dx, dy) = ((col as f64 + 0.5 - a) / a, (row as f64 + 0.5 - b) / b);
+                    assert_eq!(p == (255, 255, 255), dx * dx + dy * dy <= 1.0, "{}x{} pixel {},{}", w, h, col, row);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn circles_rank_by_their_drawn_area() {
+        let circle = |r: f32, units: GeometryUnits, red: u16| ShapeInstruction::Circle(CircleShape {
+            color: ColorRGB { red, green: 0, blue: 0, depth_bits: 8 },
+            geometry: CircleGeometry { cx: None, cy: None, r, units },
+            clamped: Clamped::default(), z: 0,
+        });
+        // r 0.1 of the 1080 px side: a 216 px circle (~36600 px) against 200x200 = 40000 px
+        let shapes = [rectangle(200.0, 200.0, GeometryUnits::Pixels, 1), circle(0.1, GeometryUnits::Normalized, 2)];
+        assert_eq!(select_measure_colour(&shapes, MeasureTarget::Smallest, canvas()).map(|c| c.red), Some(2));
+        let shapes = [rectangle(200.0, 200.0, GeometryUnits::Pixels, 1), circle(120.0, GeometryUnits::Pixels, 2)];
+        assert_eq!(select_measure_colour(&shapes, MeasureTarget::Smallest, canvas()).map(|c| c.red), Some(1));
+    }
+
+    #[test]
+    fn higher_layer_is_drawn_on_top_whatever_the_document_order() {
+        let small = |z: &str| format!("<rectangle{}><color red=\"255\" green=\"0\" blue=\"0\"/><geometry cx=\"0.2\" cy=\"0.2\"/></rectangle>", z);
+        let large = |z: &str| format!("<rectangle{}><color red=\"0\" green=\"255\" blue=\"0\"/><geometry cx=\"0.6\" cy=\"0.6\"/></rectangle>", z);
+        // colour at the centre, where the two overlap
+        let centre = |shapes: String| {
+            let xml = format!("<CS_RMC version=1><shapes>{}</shapes></CS_RMC>", shapes);
+            let shapes = crate::lan::parse_measurement_from_xml(&xml, 0, 0, 0).unwrap().remove(0).shapes;
+            with_canvas(100, 100, move |canvas| {
+                let backdrop = Backdrop { clear: Color::RGB(0, 0, 0), surround: Color::RGB(0, 0, 0) };
+                draw_shapes(canvas, &shapes, None, Rect::new(0, 0, 100, 100), (0.0, 0.0), ChannelView::All, backdrop).unwrap();
+                pixels(canvas)[50][50]
+            })
+        };
+        assert_eq!(centre(small(" z=\"2\"") + &large(" z=\"1\"")), (255, 0, 0));
+        assert_eq!(centre(large(" z=\"1\"") + &small(" z=\"2\"")), (255, 0, 0));
+        assert_eq!(centre(large(" layer=\"5\"") + &small("")), (0, 255, 0));
+        // no layers, or equal ones: document order
+        assert_eq!(centre(small("") + &large("")), (0, 255, 0));
+        assert_eq!(centre(large(" z=\"3\"") + &small(" z=\"3\"")), (255, 0, 0));
+    }
+
+    #[test]
+    fn codes_scale_to_8_bits_from_any_depth() {
+        let c = |red, green, blue, depth_bits| ColorRGB { red, green, blue, depth_bits };
+        assert_eq!(color_to_u8_tuple(c(128, 128, 128, 8)), (128, 128, 128));
+        assert_eq!(color_to_u8_tuple(c(1023, 512, 0, 10)), (255, 128, 0));
+        assert_eq!(color_to_u8_tuple(c(4095, 4095, 4095, 12)), (255, 255, 255));
+        assert_eq!(color_to_u8_tuple(c(65535, 32768, 257, 16)), (255, 128, 1));
+        // depth 0 is taken as 8 bits
+        assert_eq!(color_to_u8_tuple(c(7, 8, 9, 0)), (7, 8, 9));
+        // the grey path gives what the per-channel one would
+        for depth in [8, 10, 12, 16] {
+            for v in [0u16, 1, 100, 254, 511, 1000, 4000, 40000].into_iter().filter(|&v| (v as u32) < (1 << depth) - 1) {
+                let grey = color_to_u8_tuple(c(v, v, v, depth));
+                let near = color_to_u8_tuple(c(v, v, v.saturating_add(1), depth));
+                assert_eq!((grey.0, grey.1), (near.0, near.1), "{} at {} bits", v, depth);
+                assert_eq!((grey.0, grey.1), (grey.2, grey.2));
+            }
+        }
+    }
+
+    #[test]
+    fn colour_bars_land_at_their_x_positions() {
+        let draw = |pattern: pattern::Pattern, level: f64, w: u32, h: u32| with_canvas(w, h, move |canvas| {
+            let backdrop = Backdrop { clear: Color::RGB(1, 2, 3), surround: Color::RGB(1, 2, 3) };
+            draw_bars(canvas, &pattern::colour_bars(pattern, level, 8), Rect::new(0, 0, w, h), ChannelView::All, backdrop).unwrap();
+            pixels(canvas)
+        });
+
+        // EBU at 75%: eight 20 px bars, white kept at 100%
+        let ebu = draw(pattern::Pattern::Ebu, 0.75, 160, 40);
+        let expected = [(255, 255, 255), (191, 191, 0), (0, 191, 191), (0, 191, 0), (191, 0, 191), (191, 0, 0), (0, 0, 191), (0, 0, 0)];
+        for (i, colour) in expected.into_iter().enumerate() {
+            for x in [i * 20, i * 20 + 19] {
+                assert_eq!((ebu[0][x], ebu[39][x]), (colour, colour), "EBU bar {} at x {}", i, x);
+            }
+        }
+
+        // SMPTE at 100%: 100 px bars on a 700x90 canvas
+        let smpte = draw(pattern::Pattern::Smpte, 1.0, 700, 90);
+        let at = |y: usize, xs: &[usize]| xs.iter().map(|&x| smpte[y][x]).collect::<Vec<_>>();
+        assert_eq!(at(10, &[0, 99, 100, 250, 350, 450, 550, 699]), [
+            (255, 255, 255), (255, 255, 255), (255, 255, 0), (0, 255, 255), (0, 255, 0), (255, 0, 255), (255, 0, 0), (0, 0, 255),
+        ]);
+        // reverse-blue strip, rows 60..67
+        assert_eq!(at(63, &[50, 150, 250, 350, 450, 550, 650]), [(0, 0, 255), (0, 0, 0), (255, 0, 255), (0, 0, 0), (0, 255, 255), (0, 0, 0), (255, 255, 255)]);
+        // -I, white, +Q and black chips 125 px wide, then the PLUGE's +4% step at 567..600
+        assert_eq!(at(80, &[60, 124, 125, 300, 450, 550, 566, 580, 599, 600, 699]), [
+            (0, 33, 76), (0, 33, 76), (255, 255, 255), (50, 0, 106), (0, 0, 0), (0, 0, 0), (0, 0, 0), (10, 10, 10), (10, 10, 10), (0, 0, 0), (0, 0, 0),
+        ]);
+    }
+
+    #[test]
+    fn nan_sized_shapes_are_never_picked_by_size() {
+        let nan = rectangle(f32::NAN, 0.5, GeometryUnits::Normalized, 9);
+        let shapes = [nan.clone(), rectangle(0.5, 0.5, GeometryUnits::Normalized, 1), rectangle(0.1, 0.1, GeometryUnits::Normalized, 2)];
+        assert_eq!(select_measure_colour(&shapes, MeasureTarget::Smallest, canvas()).map(|c| c.red), Some(2));
+        assert_eq!(select_measure_colour(&shapes, MeasureTarget::Largest, canvas()).map(|c| c.red), Some(1));
+        // picking by position doesn't look at the size
+        assert_eq!(select_measure_colour(&shapes, MeasureTarget::First, canvas()).map(|c| c.red), Some(9));
+        // with nothing to rank, the first shape is still measured
+        let all_nan = [nan, rectangle(0.5, f32::NAN, GeometryUnits::Pixels, 3)];
+        assert_eq!(select_measure_colour(&all_nan, MeasureTarget::Smallest, canvas()).map(|c| c.red), Some(9));
+        assert_eq!(select_measure_colour(&[], MeasureTarget::Largest, canvas()), None);
+    }
+
+    #[test]
+    fn patch_offset_shifts_the_drawn_centre() {
+        let geom = RectangleGeometry { width: 0.1, height: 0.1, units: GeometryUnits::Normalized };
+        let centre = |r: Rect| (r.center().x(), r.center().y());
+        // 0.05 of 1920 is 96 px right, -0.1 of 1080 is 108 px up
+        assert_eq!(centre(patch_rect(canvas(), geom, (0.0, 0.0))), (960, 540));
+        assert_eq!(centre(patch_rect(canvas(), geom, (0.05, -0.1))), (960 + 96, 540 - 108));
+        // pushed past the edge, the patch stops against it
+        assert_eq!(patch_rect(canvas(), geom, (1.0, 1.0)), Rect::new(1920 - 192, 1080 - 108, 192, 108));
+
+        // and that is where the pixels end up
+        let drawn = with_canvas(200, 100, |canvas| {
+            let shapes = [rectangle(20.0, 10.0, GeometryUnits::Pixels, 255)];
+            let backdrop = Backdrop { clear: Color::RGB(0, 0, 0), surround: Color::RGB(0, 0, 0) };
+            draw_shapes(canvas, &shapes, None, Rect::new(0, 0, 200, 100), (0.1, 0.2), ChannelView::All, backdrop).unwrap();
+            pixels(canvas)
+        });
+        let red: Vec<(usize, usize)> = drawn.iter().enumerate()
+        .flat_map(|(y, row)| row.iter().enumerate().filter(|(_, p)| **p == (255, 0, 0)).map(move |(x, _)| (x, y)))
+        .collect();
+        assert_eq!(red.len(), 20 * 10);
+        // centred at (100, 50) plus (20, 20)
+        assert_eq!((red.first(), red.last()), (Some(&(110, 65)), Some(&(129, 74))));
+    }
+
+    #[test]
+    fn mirror_canvases_get_the_same_picture() {
+        // the main window and a mirror on a display of another size, drawn the way the
+        // render loop fans out: same shapes, each laid out on its own drawable
+        let [main, mirror] = with_video(|video| {
+            let shapes = [rectangle(0.5, 0.5, GeometryUnits::Normalized, 200)];
+            let backdrop = Backdrop { clear: Color::RGB(0, 0, 0), surround: Color::RGB(0, 0, 40) };
+            [(160, 90), (64, 64)].map(|(w, h)| {
+                let mut canvas = software_canvas(video, w, h);
+                draw_shapes(&mut canvas, &shapes, None, Rect::new(0, 0, w, h), (0.0, 0.0), ChannelView::All, backdrop).unwrap();
+                canvas.present();
+                pixels(&canvas)
+            })
+        });
+        assert_eq!((main[0].len(), main.len(), mirror[0].len(), mirror.len()), (160, 90, 64, 64));
+        for (drawn, (w, h)) in [(&main, (160, 90)), (&mirror, (64, 64))] {
+            let count = |colour| drawn.iter().flatten().filter(|&&p| p == colour).count();
+            assert_eq!(count((200, 0, 0)), (w / 2) * (h / 2));
+            assert_eq!(count((0, 0, 40)), w * h - (w / 2) * (h / 2));
+            assert_eq!(drawn[h / 2][w / 2], (200, 0, 0));
+        }
+    }
+
+    #[test]
+    fn letterbox_surround_and_patch_keep_their_own_colours() {
+        // a 50% window in a region letterboxed to the middle half of the width
+        let region = PatchRegion { x: 0.25, y: 0.0, width: 0.5, height: 1.0 };
+        let drawn = with_canvas(200, 100, move |canvas| {
+            let shapes = [rectangle(0.5, 0.5, GeometryUnits::Normalized, 255)];
+            let backdrop = Backdrop { clear: Color::RGB(0, 0, 0), surround: Color::RGB(128, 128, 128) };
+            draw_shapes(canvas, &shapes, None, region_to_pixels(Some(region), 200, 100), (0.0, 0.0), ChannelView::All, backdrop).unwrap();
+            pixels(canvas)
+        });
+        let expected = |x: usize, y: usize| match (x, y) {
+            // the region is x 50..150; the window its middle 50x50
+            (75..125, 25..75) => (255, 0, 0),
+            (50..150, _) => (128, 128, 128),
+            _ => (0, 0, 0),
+        };
+        for (y, row) in drawn.iter().enumerate() {
+            for (x, &p) in row.iter().enumerate() {
+                assert_eq!(p, expected(x, y), "pixel {},{}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn bordered_patch_insets_its_fill() {
+        let drawn = with_canvas(100, 100, |canvas| {
+            let shape = ShapeInstruction::Rectangle(RectangleShape {
+                border: Some(ColorRGB { red: 255, green: 255, blue: 255, depth_bits: 8 }),
+                ..rectangle_shape(0.5, 0.5, GeometryUnits::Normalized, 200)
+            });
+            let backdrop = Backdrop { clear: Color::RGB(0, 0, 0), surround: Color::RGB(0, 0, 0) };
+            draw_shapes(canvas, &[shape], None, Rect::new(0, 0, 100, 100), (0.0, 0.0), ChannelView::All, backdrop).unwrap();
+            pixels(canvas)
+        });
+        // the 50x50 patch at 25,25: a border 5% of its side (2 px), fill inside it
+        let expected = |x: usize, y: usize| match (x, y) {
+            (27..73, 27..73) => (200, 0, 0),
+            (25..75, 25..75) => (255, 255, 255),
+            _ => (0, 0, 0),
+        };
+        for (y, row) in drawn.iter().enumerate() {
+            for (x, &p) in row.iter().enumerate() {
+                assert_eq!(p, expected(x, y), "pixel {},{}", x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::Instant;
+
+use crate::lan::ColorRGB;
+
+// Alternate the patch between two colours for pixel response / persistence checks
+// (--flash A,B --flash-hz N). Purely local: the colour is picked from the render loop's
+// clock each frame, and the renderer runs with vsync while flashing so every frame is
+// a whole refresh of one colour. A full cycle (A then B) takes 1/N s; rates above half
+// the display's refresh rate can't be shown and frames will alias.
+
+/// Two colours swapped every half period, counted from `start`.
+#[derive(Debug, Clone, Copy)]
+pub struct Flash {
+    pub a: ColorRGB,
+    pub b: ColorRGB,
+    pub hz: f64,
+    start: Instant,
+}
+
+impl Flash {
+    pub fn new(a: ColorRGB, b: ColorRGB, hz: f64, start: Instant) -> Self {
+        Self { a, b, hz, start }
+    }
+
+    /// Half periods completed by `now` (0 before `start`).
+    pub fn half_periods(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.start).as_secs_f64() * 2.0 * self.hz).floor() as u64
+    }
+
+    /// A for the first half of each cycle, B for the second.
+    pub fn colour_at(&self, now: Instant) -> ColorRGB {
+        if self.half_periods(now).is_multiple_of(2) { self.a } else { self.b }
+    }
+
+    /// With --sync-divisor: A on even content frames, B on odd ones.
+    pub fn colour_for_frame(&self, frame: u64) -> ColorRGB {
+        if frame.is_multiple_of(2) { self.a } else { self.b }
+    }
+}
+
+/// One --flash colour as r, g, b code values.
+pub type Codes = (u16, u16, u16);
+
+/// Parse `A,B` for --flash, each side a grey code value (`0,255`) or `r/g/b` (`255/0/0,0/0/0`).
+pub fn parse_pair(value: &str) -> Result<(Codes, Codes), String> {
+    let bad = || format!("bad flash colours '{}', expected A,B as grey levels or r/g/b code values, e.g. 0,255", value);
+    let side = |s: &str| -> Option<Codes> {
+        let parts: Vec<u16> = s.split('/').map(|p| p.trim().parse::<u16>().ok()).collect::<Option<_>>()?;
+        match parts[..] {
+            [v] => Some((v, v, v)),
+            [r, g, b] => Some((r, g, b)),
+            _ => None,
+        }
+    };
+    match value.split(',').collect::<Vec<_>>()[..] {
+        [a, b] => Ok((side(a).ok_or_else(bad)?, side(b).ok_or_else(bad)?)),
+        _ => Err(bad()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn grey(v: u16) -> ColorRGB {
+        ColorRGB { red: v, green: v, blue: v, depth_bits: 8 }
+    }
+
+    #[test]
+    fn colours_alternate_every_half_period() {
+        let start = Instant::now();
+        let flash = Flash::new(grey(0), grey(255), 2.0, start);
+        let at = |ms: u64| flash.colour_at(start + Duration::from_millis(ms));
+        // 2 Hz: A for 250 ms, then B for 250 ms
+        assert_eq!([at(0), at(249), at(250), at(499), at(500), at(740)], [grey(0), grey(0), grey(255), grey(255), grey(0), grey(0)]);
+        assert_eq!(flash.half_periods(start + Duration::from_millis(1250)), 5);
+        // before the start, still on A
+        let later = Flash::new(grey(0), grey(255), 2.0, start + Duration::from_millis(300));
+        assert_eq!((later.half_periods(start), later.colour_at(start)), (0, grey(0)));
+        assert_eq!([flash.colour_for_frame(0), flash.colour_for_frame(1), flash.colour_for_frame(6)], [grey(0), grey(255), grey(0)]);
+    }
+
+    #[test]
+    fn flash_pairs() {
+        assert_eq!(parse_pair("0,255"), Ok(((0, 0, 0), (255, 255, 255))));
+        assert_eq!(parse_pair("255/0/0, 0/0/0"), Ok(((255, 0, 0), (0, 0, 0))));
+        assert!(parse_pair("0").is_err());
+        assert!(parse_pair("1/2,3").is_err());
+        assert!(parse_pair("0,255,128").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use std::process::{Child, Command};
+
+use crate::lan::ColorRGB;
+
+// Something to do the moment a new patch is on screen, e.g. trigger an external
+// meter over GPIO/serial (--on-present).
+//
+// Timing: `presented` is called right after `canvas.present()` returns for the main
+// window, before any mirrors are drawn, in the same loop iteration. The renderer runs
+// without vsync, so present() returns as soon as the frame is handed to the
+// driver/compositor: the patch reaches the panel at the next refresh after that (up to
+// one frame, ~17 ms at 60 Hz, more under a compositor) plus the panel's own processing
+// delay. The callback itself is called synchronously; for `command`, starting the
+// process typically adds around a millisecond and the command is not waited for.
+// Meters that need the patch settled should still add their own delay.
+
+/// Calls a callback once per distinct patch, after it has been presented.
+pub struct PresentHook {
+    last: Option<ColorRGB>,
+    callback: Box<dyn FnMut(ColorRGB)>,
+}
+
+impl PresentHook {
+    pub fn new(callback: impl FnMut(ColorRGB) + 'static) -> Self {
+        Self { last: None, callback: Box::new(callback) }
+    }
+
+    /// Run `command` through `sh -c` for each new patch, with the colour in
+    /// `COLOURSPACE_RED`, `_GREEN`, `_BLUE` (code values) and `_BITS`. Finished
+    /// commands are reaped on later calls; a command that fails to start is logged.
+    pub fn command(command: &str) -> Self {
+        let command = command.to_string();
+        let mut running: Vec<Child> = Vec::new();
+        Self::new(move |colour| {
+            running.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+            let started = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .env("COLOURSPACE_RED", colour.red.to_string())
+            .env("COLOURSPACE_GREEN", colour.green.to_string())
+            .env("COLOURSPACE_BLUE", colour.blue.to_string())
+            .env("COLOURSPACE_BITS", colour.depth_bits.to_string())
+            .spawn();
+            match started {
+                Ok(child) => running.push(child),
+                Err(e) => eprintln!("--on-present: failed to run '{}': {}", command, e),
+            }
+        })
+    }
+
+    /// Report the patch just presented; the callback fires if it differs from the
+    /// previous one (and for the very first patch). Returns whether it fired.
+    pub fn presented(&mut self, colour: ColorRGB) -> bool {
+        if self.last.replace(colour) == Some(colour) {
+            return false;
+        }
+        (self.callback)(colour);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    fn grey(v: u16) -> ColorRGB {
+        ColorRGB { red: v, green: v, blue: v, depth_bits: 8 }
+    }
+
+    #[test]
+    fn fires_once_per_distinct_patch() {
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        let mut hook = PresentHook::new({
+            let fired = fired.clone();
+            move |colour| fired.borrow_mut().push(colour.red)
+        });
+        // frames as presented: a patch stays up for several frames, and may come back
+        let frames = [0, 0, 0, 128, 128, 255, 128, 128];
+        let results: Vec<bool> = frames.iter().map(|&v| hook.presented(grey(v))).collect();
+        assert_eq!(results, [true, false, false, true, false, true, true, false]);
+        assert_eq!(*fired.borrow(), [0, 128, 255, 128]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_gets_the_colour_in_its_environment() {
+        let out = std::env::temp_dir().join(format!("colourspace-hook-{}", std::process::id()));
+        let mut hook = PresentHook::command(&format!("echo $COLOURSPACE_RED $COLOURSPACE_GREEN $COLOURSPACE_BLUE $COLOURSPACE_BITS > {}", out.display()));
+        assert!(hook.presented(ColorRGB { red: 940, green: 64, blue: 512, depth_bits: 10 }));
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut written = String::new();
+        while !written.ends_with('\n') && Instant::now() < deadline {
+            written = std::fs::read_to_string(&out).unwrap_or_default();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let _ = std::fs::remove_file(&out);
+        assert_eq!(written, "940 64 512 10\n");
+    }
+}
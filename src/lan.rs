@@ -1,14 +1,147 @@
-use std::collections::HashSet;
-use std::io::{Read, Write};
-use std::net::{TcpStream, ToSocketAddrs};
-use std::sync::{Arc, Mutex, RwLock};
+use std::collections::{HashSet, VecDeque};
+use std::io::{BufRead, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use quick_xml::Reader;
+use quick_xml::escape::escape;
 use quick_xml::events::Event;
 use quick_xml::events::BytesStart;
 
+use crate::xyz::{Primaries, XyzColour};
+use crate::ycbcr::{Ycbcr, YcbcrMatrix};
+
+/// Protocol version this client speaks (`<CS_RMC version=1>`).
+pub const SUPPORTED_VERSION: &str = "1";
+
+/// Why a payload could not be turned into measurements. Everything but
+/// `UnsupportedVersion` is specific to one frame, so the worker skips it and carries on.
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("xml parse error at byte {position}: {source}")]
+    Xml { position: usize, source: quick_xml::Error },
+    #[error("shape missing its colour")]
+    MissingColor,
+    #[error("bad colour: {0}")]
+    BadColor(String),
+    #[error("bad shape geometry: {0}")]
+    BadGeometry(String),
+    #[error("unsupported protocol version {0} (expected {SUPPORTED_VERSION})")]
+    UnsupportedVersion(String),
+    #[error("command '{0}' repeated within one document")]
+    DuplicateCommand(String),
+}
+
+/// What the parser made of a payload besides the results, for robustness checks (fuzzing,
+/// odd servers): how much of the input it actually used. See `parse_measurement_with_stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseStats {
+    /// start and empty elements read
+    pub elements: usize,
+    /// elements the parser doesn't act on, by (lowercased) name, in document order
+    pub unknown_elements: Vec<String>,
+    /// attributes ignored: unknown keys on the elements the parser reads, and any on the rest
+    pub attributes_skipped: usize,
+    /// text values that didn't parse as the number they should be (result channels, readings, hold_ms)
+    pub values_dropped: usize,
+    /// values changed to fit: out-of-gamut or out-of-range colours, corner radii over 0.5,
+    /// normalized sizes over 1 (drawn clipped to the region)
+    pub clamps: usize,
+    /// defaults filled in: rectangles without a width or height (full size), documents
+    /// with neither shapes nor a result colour (the caller's r,g,b)
+    pub fallbacks: usize,
+}
+
+/// Elements `parse_measurement_from_reader` acts on, with the attributes it reads on each.
+/// (Commands under the root and parameters under them are free-form and logged.)
+const KNOWN_ELEMENTS: &[(&str, &[&str])] = &[
+    ("cs_rmc", &["version"]),
+    ("rectangle", &["z", "layer"]),
+    ("circle", &["z", "layer", UNITS_ATTR, "cx", "cy", "r"]),
+    ("ellipse", &["z", "layer", UNITS_ATTR, "cx", "cy", "r", "rx", "ry"]),
+    ("color", &["role", "bits", "depth", "bitDepth", "red", "green", "blue"]),
+    ("colex", &["role", "bits", "depth", "bitDepth", "red", "green", "blue"]),
+    ("colorf", &["bits", "depth", "bitDepth", "red", "green", "blue"]),
+    ("geometry", &[UNITS_ATTR, "cx", "cy", "x", "y", "corner_radius"]),
+    ("ycbcr", &["y", "cb", "cr", "bits", "depth", "bitDepth", "matrix", "range"]),
+    ("xyy", &["x", "y", "Y", "bits", "depth", "bitDepth", "primaries"]),
+    ("xyz", &["X", "Y", "Z", "bits", "depth", "bitDepth", "primaries"]),
+    ("fullscreen", &[]),
+    ("measuring", &[]),
+    ("hide_markers", &[]),
+    ("shapes", &[]),
+    ("command", &[]),
+    ("result", &[]),
+    ("name", &[]),
+    ("label", &[]),
+    ("error", &[]),
+    ("hold_ms", &[]),
+    ("red", &[]),
+    ("green", &[]),
+    ("blue", &[]),
+    ("x", &[]),
+    ("y", &[]),
+    ("Y", &[]),
+];
+
+/// Count `element` into `stats`: unknown names and the attributes nobody reads.
+fn note_element(stats: &mut ParseStats, name: &str, element: &BytesStart) {
+    stats.elements += 1;
+    let mut attrs = element.html_attributes();
+    let present: Vec<Vec<u8>> = attrs.with_checks(false).flatten().map(|a| a.key.as_ref().to_vec()).collect();
+    match KNOWN_ELEMENTS.iter().find(|(known, _)| *known == name) {
+        Some((_, keys)) => stats.attributes_skipped += present.iter().filter(|p| !keys.iter().any(|k| k.as_bytes() == &p[..])).count(),
+        None => {
+            stats.unknown_elements.push(name.to_string());
+            stats.attributes_skipped += present.len();
+        }
+    }
+}
+
+/// Transport-level failures on the server connection.
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("server closed the connection")]
+    Closed,
+    #[error("no frame within {}ms", .0.as_millis())]
+    Timeout(Duration),
+    /// TCP is up but the application never answered the handshake
+    #[error("connected, but no reply to the handshake within {}ms", .0.as_millis())]
+    HandshakeTimeout(Duration),
+    #[error("frame of {len} bytes exceeds max payload of {max} bytes")]
+    FrameTooLarge { len: usize, max: usize },
+    #[error("payload is not valid UTF-8")]
+    InvalidUtf8,
+    /// the server sent an `<error>` and then hung up; the error is the useful part
+    #[error("server closed the connection after reporting: {0}")]
+    ServerError(String),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+}
+
+impl ProtocolError {
+    /// Reconnecting won't help: the server speaks something we don't understand.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, ProtocolError::Parse(ParseError::UnsupportedVersion(_)))
+    }
+}
+
+/// One parsed document. What ends up on screen:
+/// - `fullscreen` set (`<fullscreen/>`): a flat field of the measured colour (the
+///   target rectangle's, else red/green/blue) over the whole drawable, ignoring
+///   rectangle geometry and any user-drawn patch region.
+/// - rectangles: drawn as patches sized relative to the region (or the whole drawable);
+///   a 1.0 x 1.0 rectangle is therefore a patch that happens to fill the region.
+/// - circles and ellipses: drawn the same way, about their own centre (the region's
+///   unless `cx`/`cy` say otherwise).
+/// - neither: nothing was asked for; the client keeps showing the document's colour
+///   as a fallback over the region.
 #[derive(Debug, Clone)]
 pub struct MeasurementResult {
     // store as u16 so we can carry 10/12/16-bit values
@@ -18,9 +151,23 @@ pub struct MeasurementResult {
     pub x: Option<f64>,
     pub y: Option<f64>,
     pub y_lum: Option<f64>,
+    /// patch name/label from a `<name>` or `<label>` element, if the server sent one
+    pub label: Option<String>,
+    /// explicit full-screen flat field requested (see above)
+    pub fullscreen: bool,
+    /// `<measuring/>` or `<hide_markers/>`: the server wants a clean screen for this reading,
+    /// so the client draws no marker or overlay over the patch while it is up
+    pub hide_markers: bool,
+    /// minimum time to keep this patch on screen, from `<hold_ms>` (None = client default)
+    pub hold: Option<Duration>,
     pub shapes: Vec<ShapeInstruction>,
+    /// text of an `<error>` element: the server reporting a failure (often just before it closes)
+    pub error: Option<String>,
 }
 
+/// Bit depths a colour can be given in (control port, `<colorf bits>`).
+pub const DEPTH_BITS: std::ops::RangeInclusive<u8> = 1..=16;
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub struct ColorRGB {
     // allow storage up to 16-bit per channel
@@ -47,252 +194,2446 @@ impl ColorRGB {
         let bits = if bits == 0 { 8 } else { bits };
         Self { red, green, blue, depth_bits: bits }
     }
+
+    /// From normalized 0.0..=1.0 components: clamped (NaN counts as 0), scaled to the
+    /// code range of `bits` and rounded to nearest, so 1.0 is always the max code value.
+    pub fn from_normalized(red: f64, green: f64, blue: f64, bits: u8) -> Self {
+        let bits = if bits == 0 { 8 } else { bits.min(16) };
+        let max = ((1u32 << bits) - 1) as f64;
+        let code = |v: f64| if v.is_nan() { 0 } else { (v.clamp(0.0, 1.0) * max).round() as u16 };
+        Self { red: code(red), green: code(green), blue: code(blue), depth_bits: bits }
+    }
+
+    /// R = G = B.
+    pub fn is_grey(self) -> bool {
+        self.is_near_grey(0.0)
+    }
+
+    /// The channels differ by at most `tolerance` of full scale (0.01 = 1%, about 2.5
+    /// 8-bit codes or 10 10-bit codes), so the answer doesn't depend on bit depth.
+    pub fn is_near_grey(self, tolerance: f64) -> bool {
+        let bits = if self.depth_bits == 0 { 8 } else { self.depth_bits.min(16) };
+        let max = ((1u32 << bits) - 1) as f64;
+        let spread = self.red.max(self.green).max(self.blue) - self.red.min(self.green).min(self.blue);
+        spread as f64 <= tolerance * max
+    }
     // to_u8_tuple intentionally removed — consumer should perform downscale.
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct RectangleGeometry { pub width: f32, pub height: f32 }
+/// Channels a conversion into RGB (`<ycbcr>`, `<xyY>`/`<XYZ>`) had to clamp to the code
+/// range, i.e. the requested colour is outside what the display can show and the patch
+/// drawn is not the one asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Clamped { pub red: bool, pub green: bool, pub blue: bool }
 
-#[derive(Debug, Clone)]
-pub struct RectangleShape { pub color: ColorRGB, pub geometry: RectangleGeometry }
+impl Clamped {
+    /// Whether a linear/normalised value outside `0..=1` (give or take `epsilon`) clamps.
+    pub fn from_normalized(rgb: [f64; 3], epsilon: f64) -> Self {
+        let out = |v: f64| !(-epsilon..=1.0 + epsilon).contains(&v);
+        Self { red: out(rgb[0]), green: out(rgb[1]), blue: out(rgb[2]) }
+    }
 
-#[derive(Debug, Clone)]
-pub enum ShapeInstruction { Rectangle(RectangleShape) }
+    pub fn any(self) -> bool {
+        self.red || self.green || self.blue
+    }
+}
 
-/// Parse XML string into a MeasurementResult. The `r,g,b` parameters are the
-/// requested components that will be used as fallback initial values in the
-/// result (keeps previous behavior). These are now u16 to allow >8-bit defaults.
-fn parse_measurement_from_xml(xml: &str, r: u16, g: u16, b: u16) -> Result<MeasurementResult, String> {
-    let mut reader = Reader::from_str(xml);
+impl std::fmt::Display for Clamped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = [(self.red, "red"), (self.green, "green"), (self.blue, "blue")].iter().filter(|(c, _)| *c).map(|(_, n)| *n).collect();
+        f.write_str(&names.join(", "))
+    }
+}
+
+/// What a geometry's width/height are measured in (`<geometry units="...">`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GeometryUnits {
+    /// fractions of the region, 0..1 (the default)
+    #[default]
+    Normalized,
+    /// absolute pixels, capped at the region size
+    Pixels,
+}
+
+/// The attribute that sets `GeometryUnits` on `<geometry>`, `<circle>` and `<ellipse>`.
+const UNITS_ATTR: &str = "units";
+
+/// A `UNITS_ATTR` value, in any case.
+fn parse_units(value: &str) -> Result<GeometryUnits, ParseError> {
+    match value.to_ascii_lowercase().as_str() {
+        "normalized" | "normalised" => Ok(GeometryUnits::Normalized),
+        "pixels" | "px" => Ok(GeometryUnits::Pixels),
+        _ => Err(ParseError::BadGeometry(format!("{}=\"{}\" (expected normalized or pixels)", UNITS_ATTR, value))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RectangleGeometry { pub width: f32, pub height: f32, pub units: GeometryUnits }
+
+/// `ycbcr` is set when the patch was requested as `<ycbcr>`, `xyz` when it came as `<xyY>`/`<XYZ>`;
+/// `color` is then its RGB conversion.
+/// `border` is the edge colour from a `<color role="border" .../>`, if the rectangle had one.
+/// `corner_radius` rounds the corners, as a fraction of the patch's shorter side (0 = square, 0.5 = fully round).
+/// `clamped` says which channels of that conversion were out of gamut and clamped.
+/// `z` is the layer from `<rectangle z="..">` (or `layer=`): higher draws on top, equal layers in document order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RectangleShape { pub color: ColorRGB, pub geometry: RectangleGeometry, pub ycbcr: Option<Ycbcr>, pub xyz: Option<XyzColour>, pub clamped: Clamped, pub border: Option<ColorRGB>, pub corner_radius: f32, pub z: i32 }
+
+/// Where a `<circle>` sits and how big it is. `cx`/`cy` are the centre (None centres it in
+/// the region, like a rectangle). Normalized: the centre in fractions of the region's
+/// width and height, `r` in fractions of its shorter side (so 0.5 just touches it).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircleGeometry { pub cx: Option<f32>, pub cy: Option<f32>, pub r: f32, pub units: GeometryUnits }
+
+/// Centre as for `CircleGeometry`; normalized `rx`/`ry` are fractions of the region's width and height.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EllipseGeometry { pub cx: Option<f32>, pub cy: Option<f32>, pub rx: f32, pub ry: f32, pub units: GeometryUnits }
+
+/// A round patch from `<circle cx=".." cy=".." r="..">`, coloured by the same child elements as a rectangle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircleShape { pub color: ColorRGB, pub geometry: CircleGeometry, pub clamped: Clamped, pub z: i32 }
+
+/// An elliptical patch from `<ellipse cx=".." cy=".." rx=".." ry="..">`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EllipseShape { pub color: ColorRGB, pub geometry: EllipseGeometry, pub clamped: Clamped, pub z: i32 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShapeInstruction { Rectangle(RectangleShape), Circle(CircleShape), Ellipse(EllipseShape) }
+
+impl ShapeInstruction {
+    /// Element names of the shapes the parser understands (for `--capabilities`).
+    pub const KINDS: &[&str] = &["rectangle", "circle", "ellipse"];
+
+    /// Drawing layer (0 unless the document gave one).
+    pub fn z(&self) -> i32 {
+        match self { ShapeInstruction::Rectangle(r) => r.z, ShapeInstruction::Circle(c) => c.z, ShapeInstruction::Ellipse(e) => e.z }
+    }
+
+    /// Fill colour.
+    pub fn color(&self) -> ColorRGB {
+        match self { ShapeInstruction::Rectangle(r) => r.color, ShapeInstruction::Circle(c) => c.color, ShapeInstruction::Ellipse(e) => e.color }
+    }
+
+    /// Channels of a converted colour that were clamped (see `RectangleShape`).
+    pub fn clamped(&self) -> Clamped {
+        match self { ShapeInstruction::Rectangle(r) => r.clamped, ShapeInstruction::Circle(c) => c.clamped, ShapeInstruction::Ellipse(e) => e.clamped }
+    }
+
+    /// The overlay's out-of-gamut line for this shape, if its colour had to be clamped.
+    pub fn gamut_warning(&self) -> Option<String> {
+        let (clamped, c) = (self.clamped(), self.color());
+        clamped.any().then(|| format!("OUT OF GAMUT: {} clamped, showing {},{},{}", clamped, c.red, c.green, c.blue))
+    }
+}
+
+/// Normalized (0..1) sub-rectangle of the window that patches are constrained to,
+/// e.g. a region drawn with the mouse to dodge projector hotspots.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PatchRegion { pub x: f32, pub y: f32, pub width: f32, pub height: f32 }
+
+/// Element name as the parser matches it. Names are case-insensitive (`<Rectangle>`,
+/// `<Color>` and `<COLOR>` all work; commands too, so `<Command>` after `<command>`
+/// is a duplicate) and are lowercased here. Single-letter
+/// names keep their case: they are readings, and `y` (chromaticity) and `Y`
+/// (luminance) are different values. Attribute names stay case-sensitive.
+fn element_name(raw: &[u8]) -> String {
+    let name = String::from_utf8_lossy(raw);
+    if name.len() == 1 { name.into_owned() } else { name.to_ascii_lowercase() }
+}
+
+/// Parse a framed XML payload into one MeasurementResult per top-level
+/// `<CS_RMC>` document. Servers normally send one document per frame, but a
+/// frame carrying several back to back is handled too (results in document order).
+/// The `r,g,b` parameters are the requested components that will be used as
+/// fallback initial values in each result (keeps previous behavior). These are
+/// now u16 to allow >8-bit defaults. An empty payload yields a single fallback result.
+#[allow(dead_code)] // for embedding and tests; the worker parses off the socket (`FrameReader::read_and_parse`)
+pub fn parse_measurement_from_xml(xml: &str, r: u16, g: u16, b: u16) -> Result<Vec<MeasurementResult>, ParseError> {
+    parse_measurement_from_reader(xml.as_bytes(), r, g, b)
+}
+
+/// Same as `parse_measurement_from_xml`, but pulls the XML from `input` as it goes
+/// instead of needing it all in memory. The worker feeds frames straight off the
+/// socket through this; I/O errors surface as `ParseError::Xml` with an `Io` source.
+pub fn parse_measurement_from_reader<B: BufRead>(input: B, r: u16, g: u16, b: u16) -> Result<Vec<MeasurementResult>, ParseError> {
+    parse_measurement_counted(input, r, g, b, &mut ParseStats::default())
+}
+
+/// `parse_measurement_from_xml`, also returning what the parser skipped, dropped,
+/// clamped or defaulted on the way (see `ParseStats`).
+pub fn parse_measurement_with_stats(xml: &str, r: u16, g: u16, b: u16) -> Result<(Vec<MeasurementResult>, ParseStats), ParseError> {
+    let mut stats = ParseStats::default();
+    let results = parse_measurement_counted(xml.as_bytes(), r, g, b, &mut stats)?;
+    Ok((results, stats))
+}
+
+fn parse_measurement_counted<B: BufRead>(input: B, r: u16, g: u16, b: u16, stats: &mut ParseStats) -> Result<Vec<MeasurementResult>, ParseError> {
+    let mut reader = Reader::from_reader(input);
     reader.trim_text(true);
     let mut buf = Vec::new();
-    let mut in_result = false;
-    let mut cur_elem = String::new();
-    let mut res = MeasurementResult { red: r, green: g, blue: b, x: None, y: None, y_lum: None, shapes: Vec::new() };
+    let new_result = || MeasurementResult { red: r, green: g, blue: b, x: None, y: None, y_lum: None, label: None, fullscreen: false, hide_markers: false, hold: None, shapes: Vec::new(), error: None };
+    let mut res = new_result();
+    let mut results: Vec<MeasurementResult> = Vec::new();
     let mut element_stack: Vec<String> = Vec::new();
     let mut reported_commands: HashSet<String> = HashSet::new();
     let mut parsed_shapes: Vec<ShapeInstruction> = Vec::new();
+    // whether anything has been read since the last completed document
+    let mut doc_open = false;
+    // whether the document's <result> gave a colour (else the caller's r,g,b stand)
+    let mut doc_colour = false;
 
+    #[derive(Default, PartialEq)]
+    enum Outline { #[default] Rectangle, Circle, Ellipse }
+    // One shape element being read. Rectangles size from a <geometry> child; circles and
+    // ellipses from their own attributes (centre `cx`/`cy`, radii `r` or `rx`/`ry`).
     #[derive(Default)]
-    struct RectangleBuilder { color: Option<ColorRGB>, width: Option<f32>, height: Option<f32> }
-    impl RectangleBuilder {
-        fn build(self) -> Option<RectangleShape> {
-            let color = self.color?;
-            let width = self.width.unwrap_or(1.0);
-            let height = self.height.unwrap_or(1.0);
-            Some(RectangleShape { color, geometry: RectangleGeometry { width, height } })
+    struct ShapeBuilder { outline: Outline, color: Option<ColorRGB>, border: Option<ColorRGB>, width: Option<f32>, height: Option<f32>, centre: (Option<f32>, Option<f32>), radii: (Option<f32>, Option<f32>), units: GeometryUnits, ycbcr: Option<Ycbcr>, xyz: Option<XyzColour>, clamped: Clamped, corner_radius: f32, z: i32 }
+    impl ShapeBuilder {
+        fn build(self) -> Result<ShapeInstruction, ParseError> {
+            let color = self.color.ok_or(ParseError::MissingColor)?;
+            let (cx, cy) = self.centre;
+            // a missing radius fills the region (like a rectangle's missing size)
+            let (rx, ry) = (self.radii.0.unwrap_or(0.5), self.radii.1.unwrap_or(0.5));
+            Ok(match self.outline {
+                Outline::Rectangle => {
+                    let width = self.width.unwrap_or(1.0);
+                    let height = self.height.unwrap_or(1.0);
+                    ShapeInstruction::Rectangle(RectangleShape { color, geometry: RectangleGeometry { width, height, units: self.units }, ycbcr: self.ycbcr, xyz: self.xyz, clamped: self.clamped, border: self.border, corner_radius: self.corner_radius.min(0.5), z: self.z })
+                }
+                Outline::Circle => ShapeInstruction::Circle(CircleShape { color, geometry: CircleGeometry { cx, cy, r: rx, units: self.units }, clamped: self.clamped, z: self.z }),
+                Outline::Ellipse => ShapeInstruction::Ellipse(EllipseShape { color, geometry: EllipseGeometry { cx, cy, rx, ry, units: self.units }, clamped: self.clamped, z: self.z }),
+            })
         }
     }
-    let mut rect_builder: Option<RectangleBuilder> = None;
+    // what a finished shape adds to the clamp and fallback counts
+    let note_shape = |stats: &mut ParseStats, builder: &ShapeBuilder| {
+        let normalized = builder.units == GeometryUnits::Normalized;
+        stats.clamps += builder.clamped.any() as usize;
+        if builder.outline == Outline::Rectangle {
+            stats.clamps += (builder.corner_radius > 0.5) as usize
+            + (normalized && builder.width.is_some_and(|w| w > 1.0)) as usize + (normalized && builder.height.is_some_and(|h| h > 1.0)) as usize;
+            stats.fallbacks += (builder.width.is_none() || builder.height.is_none()) as usize;
+        } else {
+            stats.fallbacks += (builder.radii.0.is_none() || (builder.outline == Outline::Ellipse && builder.radii.1.is_none())) as usize;
+        }
+    };
+    let mut shape_builder: Option<ShapeBuilder> = None;
+    // a <color> without channel attributes, waiting for its text: border?, colour so far (bits)
+    let mut text_colour: Option<(bool, ColorRGB)> = None;
 
-    // apply_color now understands "bits" attribute and larger numeric values
-    let apply_color = |reader: &Reader<&[u8]>, element: &BytesStart, builder: &mut RectangleBuilder| {
-        let mut colour = builder.color.unwrap_or_default();
+    // apply_color now understands "bits" attribute and larger numeric values.
+    // role="border" sets the edge colour instead of the fill; no role (or role="fill") is the fill.
+    // Without red/green/blue attributes the channels may come as text (see apply_color_text):
+    // that returns the role and what the attributes did give (the bit depth) to finish with.
+    let apply_color = |reader: &Reader<B>, element: &BytesStart, builder: &mut ShapeBuilder| -> Result<Option<(bool, ColorRGB)>, ParseError> {
+        let border = match element.try_get_attribute("role").ok().flatten().map(|a| a.decode_and_unescape_value(reader).map(|v| v.to_ascii_lowercase())) {
+            None => false,
+            Some(Ok(role)) if role == "fill" => false,
+            Some(Ok(role)) if role == "border" => true,
+            Some(Ok(role)) => return Err(ParseError::BadColor(format!("color role=\"{}\" (expected fill or border)", role))),
+            Some(Err(_)) => return Err(ParseError::BadColor("color role is not valid text".to_string())),
+        };
+        let slot = if border { &mut builder.border } else { &mut builder.color };
+        let mut colour = slot.unwrap_or_default();
         let mut updated = false;
-        for attr in element.attributes().with_checks(false) {
-            if let Ok(attr) = attr {
-                if let Ok(value) = attr.decode_and_unescape_value(reader) {
-                    match attr.key.as_ref() {
-                        b"bits" | b"depth" | b"bitDepth" => { if let Ok(v) = value.parse::<u8>() { colour.depth_bits = v; } }
-                        b"red" => { if let Ok(v) = value.parse::<u16>() { colour.red = v; updated = true; } else if let Ok(v8) = value.parse::<u8>() { colour.red = v8 as u16; updated = true; } }
-                        b"green" => { if let Ok(v) = value.parse::<u16>() { colour.green = v; updated = true; } else if let Ok(v8) = value.parse::<u8>() { colour.green = v8 as u16; updated = true; } }
-                        b"blue" => { if let Ok(v) = value.parse::<u16>() { colour.blue = v; updated = true; } else if let Ok(v8) = value.parse::<u8>() { colour.blue = v8 as u16; updated = true; } }
-                        _ => {}
-                    }
-                }
+        for attr in element.attributes().with_checks(false).flatten() {
+            let Ok(value) = attr.decode_and_unescape_value(reader) else { continue };
+            match attr.key.as_ref() {
+                b"bits" | b"depth" | b"bitDepth" => { if let Ok(v) = value.parse::<u8>() { colour.depth_bits = v; } }
+                b"red" => { if let Ok(v) = value.parse::<u16>() { colour.red = v; updated = true; } else if let Ok(v8) = value.parse::<u8>() { colour.red = v8 as u16; updated = true; } }
+                b"green" => { if let Ok(v) = value.parse::<u16>() { colour.green = v; updated = true; } else if let Ok(v8) = value.parse::<u8>() { colour.green = v8 as u16; updated = true; } }
+                b"blue" => { if let Ok(v) = value.parse::<u16>() { colour.blue = v; updated = true; } else if let Ok(v8) = value.parse::<u8>() { colour.blue = v8 as u16; updated = true; } }
+                _ => {}
             }
         }
-        if updated { builder.color = Some(colour); }
+        if updated { *slot = Some(colour); }
+        Ok((!updated).then_some((border, colour)))
     };
 
-    let apply_geometry = |reader: &Reader<&[u8]>, element: &BytesStart, builder: &mut RectangleBuilder| {
-        for attr in element.attributes().with_checks(false) {
-            if let Ok(attr) = attr {
-                if let Ok(value) = attr.decode_and_unescape_value(reader) {
-                    match attr.key.as_ref() {
-                        b"cx" => { if let Ok(v) = value.parse::<f32>() { builder.width = Some(v); } }
-                        b"cy" => { if let Ok(v) = value.parse::<f32>() { builder.height = Some(v); } }
-                        b"x" => { if builder.width.is_none() { if let Ok(v) = value.parse::<f32>() { builder.width = Some(v); } } }
-                        b"y" => { if builder.height.is_none() { if let Ok(v) = value.parse::<f32>() { builder.height = Some(v); } } }
-                        _ => {}
-                    }
-                }
+    // <color bits="10">512 512 512</color>: the three channels as text, separated by
+    // whitespace and/or commas, in code values at the declared depth.
+    let apply_color_text = |text: &str, (border, mut colour): (bool, ColorRGB), builder: &mut ShapeBuilder| -> Result<(), ParseError> {
+        let bad = || ParseError::BadColor(format!("color text '{}' (expected red green blue code values)", text));
+        let max = ((1u32 << colour.depth_bits.clamp(1, 16)) - 1) as u16;
+        let values: Vec<u16> = text.split(|c: char| c.is_whitespace() || c == ',').filter(|v| !v.is_empty())
+        .map(|v| v.parse::<u16>().ok().filter(|&v| v <= max).ok_or_else(bad)).collect::<Result<_, _>>()?;
+        let [red, green, blue] = values[..] else { return Err(bad()) };
+        (colour.red, colour.green, colour.blue) = (red, green, blue);
+        if border { builder.border = Some(colour); } else { builder.color = Some(colour); }
+        Ok(())
+    };
+
+    // <colorf red="0.5" green="0.5" blue="0.5" bits="10"/>: normalized floats, converted to
+    // code values at `bits` (default 8). Out-of-range values are clamped, not rejected.
+    let apply_colorf = |reader: &Reader<B>, element: &BytesStart, builder: &mut ShapeBuilder| -> Result<(), ParseError> {
+        let (mut rgb, mut bits) = ([None; 3], 8u8);
+        for attr in element.attributes().with_checks(false).flatten() {
+            let Ok(value) = attr.decode_and_unescape_value(reader) else { continue };
+            let bad = || ParseError::BadColor(format!("colorf {}=\"{}\"", String::from_utf8_lossy(attr.key.as_ref()), value));
+            let channel = match attr.key.as_ref() {
+                b"red" => 0, b"green" => 1, b"blue" => 2,
+                b"bits" | b"depth" | b"bitDepth" => { bits = value.parse::<u8>().ok().filter(|b| DEPTH_BITS.contains(b)).ok_or_else(bad)?; continue; }
+                _ => continue,
+            };
+            rgb[channel] = Some(value.parse::<f64>().ok().filter(|v| !v.is_nan()).ok_or_else(bad)?);
+        }
+        let [Some(r), Some(g), Some(b)] = rgb else { return Err(ParseError::BadColor("colorf needs red, green and blue".to_string())) };
+        builder.color = Some(ColorRGB::from_normalized(r, g, b, bits));
+        builder.clamped = Clamped::from_normalized([r, g, b], 0.0);
+        Ok(())
+    };
+
+    // <ycbcr y=".." cb=".." cr=".." bits="10" matrix="709" range="limited"/>: converted to RGB
+    // for drawing, source kept on the shape. bits defaults to 8, matrix to 709, range to limited.
+    let apply_ycbcr = |reader: &Reader<B>, element: &BytesStart, builder: &mut ShapeBuilder| -> Result<(), ParseError> {
+        let (mut y, mut cb, mut cr) = (None, None, None);
+        let (mut bits, mut matrix, mut full_range) = (8u8, YcbcrMatrix::Bt709, false);
+        for attr in element.attributes().with_checks(false).flatten() {
+            let Ok(value) = attr.decode_and_unescape_value(reader) else { continue };
+            let bad = || ParseError::BadColor(format!("ycbcr {}=\"{}\"", String::from_utf8_lossy(attr.key.as_ref()), value));
+            match attr.key.as_ref() {
+                b"y" => y = Some(value.parse::<u16>().map_err(|_| bad())?),
+                b"cb" => cb = Some(value.parse::<u16>().map_err(|_| bad())?),
+                b"cr" => cr = Some(value.parse::<u16>().map_err(|_| bad())?),
+                b"bits" | b"depth" | b"bitDepth" => bits = value.parse::<u8>().ok().filter(|b| (8..=16).contains(b)).ok_or_else(bad)?,
+                b"matrix" => matrix = value.parse().map_err(|_| bad())?,
+                b"range" => full_range = match value.as_ref() { "full" => true, "limited" | "video" => false, _ => return Err(bad()) },
+                _ => {}
             }
         }
+        let (Some(y), Some(cb), Some(cr)) = (y, cb, cr) else { return Err(ParseError::BadColor("ycbcr needs y, cb and cr".to_string())) };
+        let source = Ycbcr { y, cb, cr, depth_bits: bits, matrix, full_range };
+        let (rgb, clamped) = source.to_rgb();
+        if clamped.any() { eprintln!("Out of gamut: ycbcr {},{},{} needs {} clamped, drawing {},{},{}", y, cb, cr, clamped, rgb.red, rgb.green, rgb.blue); }
+        builder.color = Some(rgb);
+        builder.ycbcr = Some(source);
+        builder.clamped = clamped;
+        Ok(())
+    };
+
+    // <xyY x="0.3127" y="0.329" Y="1"/> or <XYZ X=".." Y=".." Z=".."/>, relative to display white
+    // (Y = 1), converted for `primaries` (default srgb, see xyz.rs) at `bits` (default 8).
+    // Out-of-gamut colours are clamped, with a warning.
+    let apply_xyz = |reader: &Reader<B>, element: &BytesStart, builder: &mut ShapeBuilder, name: &str| -> Result<(), ParseError> {
+        let (mut values, mut bits, mut primaries) = ([None; 3], 8u8, Primaries::Srgb);
+        let keys: [&[u8]; 3] = if name == "xyy" { [b"x", b"y", b"Y"] } else { [b"X", b"Y", b"Z"] };
+        for attr in element.attributes().with_checks(false).flatten() {
+            let Ok(value) = attr.decode_and_unescape_value(reader) else { continue };
+            let bad = || ParseError::BadColor(format!("{} {}=\"{}\"", name, String::from_utf8_lossy(attr.key.as_ref()), value));
+            match attr.key.as_ref() {
+                b"bits" | b"depth" | b"bitDepth" => bits = value.parse::<u8>().ok().filter(|b| DEPTH_BITS.contains(b)).ok_or_else(bad)?,
+                b"primaries" => primaries = value.parse().map_err(|_| bad())?,
+                key => if let Some(i) = keys.iter().position(|k| *k == key) {
+                    values[i] = Some(value.parse::<f64>().ok().filter(|v| v.is_finite() && *v >= 0.0).ok_or_else(bad)?);
+                },
+            }
+        }
+        let [Some(a), Some(b), Some(c)] = values else {
+            return Err(ParseError::BadColor(format!("{} needs {}", name, if name == "xyy" { "x, y and Y" } else { "X, Y and Z" })));
+        };
+        let source = if name == "xyy" { XyzColour::from_xyy(a, b, c, bits, primaries) } else { XyzColour { xyz: [a, b, c], depth_bits: bits, primaries } };
+        let (rgb, clamped) = source.to_rgb();
+        if clamped.any() { eprintln!("Out of gamut: {} {:?} is outside {} (or above white), {} clamped, drawing {},{},{}", name, [a, b, c], primaries, clamped, rgb.red, rgb.green, rgb.blue); }
+        builder.color = Some(rgb);
+        builder.xyz = Some(source);
+        builder.clamped = clamped;
+        Ok(())
+    };
+
+    // layer of the rectangle itself, from its z (or layer) attribute
+    let apply_layer = |reader: &Reader<B>, element: &BytesStart, builder: &mut ShapeBuilder| -> Result<(), ParseError> {
+        for attr in element.attributes().with_checks(false).flatten() {
+            let key = attr.key.as_ref();
+            if !matches!(key, b"z" | b"layer") { continue; }
+            let value = attr.decode_and_unescape_value(reader).unwrap_or_default();
+            builder.z = value.trim().parse::<i32>().map_err(|_| ParseError::BadGeometry(format!("{}=\"{}\" (expected a whole number)", String::from_utf8_lossy(key), value)))?;
+        }
+        Ok(())
+    };
+
+    // <circle>/<ellipse> attributes: centre and radii, same rules as <geometry> sizes
+    let apply_outline = |reader: &Reader<B>, element: &BytesStart, builder: &mut ShapeBuilder| -> Result<(), ParseError> {
+        for attr in element.attributes().with_checks(false).flatten() {
+            let Ok(value) = attr.decode_and_unescape_value(reader) else { continue };
+            let key = attr.key.as_ref();
+            if key == UNITS_ATTR.as_bytes() { builder.units = parse_units(&value)?; continue; }
+            let ellipse = builder.outline == Outline::Ellipse;
+            let wanted = matches!(key, b"cx" | b"cy" | b"r") || (ellipse && matches!(key, b"rx" | b"ry"));
+            if !wanted { continue; }
+            let v = value.parse::<f32>().ok().filter(|v| v.is_finite() && *v >= 0.0)
+            .ok_or_else(|| ParseError::BadGeometry(format!("{}=\"{}\"", String::from_utf8_lossy(key), value)))?;
+            match key {
+                b"cx" => builder.centre.0 = Some(v),
+                b"cy" => builder.centre.1 = Some(v),
+                // on an ellipse, r is both radii unless rx/ry say otherwise
+                b"r" => { builder.radii.0.get_or_insert(v); if ellipse { builder.radii.1.get_or_insert(v); } }
+                b"rx" => builder.radii.0 = Some(v),
+                b"ry" => builder.radii.1 = Some(v),
+                _ => {}
+            }
+        }
+        Ok(())
+    };
+
+    // sizes must be finite, non-negative numbers; anything else means the frame is garbage
+    let apply_geometry = |reader: &Reader<B>, element: &BytesStart, builder: &mut ShapeBuilder| -> Result<(), ParseError> {
+        for attr in element.attributes().with_checks(false).flatten() {
+            let Ok(value) = attr.decode_and_unescape_value(reader) else { continue };
+            let key = attr.key.as_ref();
+            if key == UNITS_ATTR.as_bytes() { builder.units = parse_units(&value)?; continue; }
+            if !matches!(key, b"cx" | b"cy" | b"x" | b"y" | b"corner_radius") { continue; }
+            let v = value.parse::<f32>().ok().filter(|v| v.is_finite() && *v >= 0.0)
+            .ok_or_else(|| ParseError::BadGeometry(format!("{}=\"{}\"", String::from_utf8_lossy(key), value)))?;
+            match key {
+                b"cx" => builder.width = Some(v),
+                b"cy" => builder.height = Some(v),
+                b"x" if builder.width.is_none() => builder.width = Some(v),
+                b"y" if builder.height.is_none() => builder.height = Some(v),
+                b"corner_radius" => builder.corner_radius = v,
+                _ => {}
+            }
+        }
+        Ok(())
+    };
+
+    // Close out one document: flush any unterminated shape and log what was received.
+    let finish_document = |mut res: MeasurementResult, mut shapes: Vec<ShapeInstruction>, builder: Option<ShapeBuilder>| -> Result<MeasurementResult, ParseError> {
+        if let Some(builder) = builder { shapes.push(builder.build()?); }
+        res.shapes = shapes;
+
+        // Debug output for received command: prefer the first parsed shape's color if available
+        let (bit_depth, r_val, g_val, b_val) = if let Some(shape) = res.shapes.first() {
+            let c = shape.color();
+            ( c.depth_bits, c.red, c.green, c.blue )
+        } else { (8u8, res.red, res.green, res.blue) };
+
+        println!("Bit depth = {} , R = {} , G = {} , B = {}", bit_depth, r_val, g_val, b_val);
+        if let Some(ShapeInstruction::Rectangle(RectangleShape { ycbcr: Some(src), .. })) = res.shapes.first() {
+            println!("  from YCbCr {:?} {} : Y = {} , Cb = {} , Cr = {}", src.matrix, if src.full_range { "full" } else { "limited" }, src.y, src.cb, src.cr);
+        }
+        if let Some(ShapeInstruction::Rectangle(RectangleShape { xyz: Some(src), .. })) = res.shapes.first() {
+            println!("  from XYZ ({}) : X = {:.4} , Y = {:.4} , Z = {:.4}", src.primaries, src.xyz[0], src.xyz[1], src.xyz[2]);
+        }
+        if let Some(clamped) = res.shapes.first().map(ShapeInstruction::clamped) && clamped.any() {
+            println!("  out of gamut : {} clamped", clamped);
+        }
+        Ok(res)
     };
 
     loop {
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(e)) => {
-                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                doc_open = true;
+                let name = element_name(e.name().as_ref());
+                note_element(stats, &name, &e);
                 element_stack.push(name.clone());
+                if element_stack.len() == 1 { check_version(&e)?; }
                 if element_stack.len() == 2 {
                     let command = element_stack[1].clone();
                     if !reported_commands.insert(command.clone()) {
-                        panic!("Received command: {} was not satisfied", command);
+                        return Err(ParseError::DuplicateCommand(command));
                     }
                 }
-                cur_elem = name.clone();
-                if name == "result" { in_result = true; }
-                if name == "rectangle" { rect_builder = Some(RectangleBuilder::default()); }
-                else if name == "color" || name == "colex" { if let Some(builder) = rect_builder.as_mut() { apply_color(&reader, &e, builder); } }
-                else if name == "geometry" { if let Some(builder) = rect_builder.as_mut() { apply_geometry(&reader, &e, builder); } }
+                if name == "fullscreen" { res.fullscreen = true; }
+                if name == "measuring" || name == "hide_markers" { res.hide_markers = true; }
+                if name == "rectangle" || name == "circle" || name == "ellipse" {
+                    let outline = match name.as_str() { "circle" => Outline::Circle, "ellipse" => Outline::Ellipse, _ => Outline::Rectangle };
+                    let mut builder = ShapeBuilder { outline, ..ShapeBuilder::default() };
+                    apply_layer(&reader, &e, &mut builder)?;
+                    if builder.outline != Outline::Rectangle { apply_outline(&reader, &e, &mut builder)?; }
+                    shape_builder = Some(builder);
+                }
+                else if name == "color" || name == "colex" { if let Some(builder) = shape_builder.as_mut() { text_colour = apply_color(&reader, &e, builder)?; } }
+                else if name == "geometry" && let Some(builder) = shape_builder.as_mut() { apply_geometry(&reader, &e, builder)?; }
+                else if name == "ycbcr" && let Some(builder) = shape_builder.as_mut() { apply_ycbcr(&reader, &e, builder)?; }
+                else if (name == "xyy" || name == "xyz") && let Some(builder) = shape_builder.as_mut() { apply_xyz(&reader, &e, builder, &name)?; }
+                else if name == "colorf" && let Some(builder) = shape_builder.as_mut() { apply_colorf(&reader, &e, builder)?; }
             }
             Ok(Event::End(e)) => {
-                if let Ok(end_name) = std::str::from_utf8(e.name().as_ref()) {
-                    if end_name == "result" { in_result = false; }
-                    if end_name == "rectangle" {
-                        if let Some(builder) = rect_builder.take() {
-                            if let Some(rect) = builder.build() { parsed_shapes.push(ShapeInstruction::Rectangle(rect)); }
-                            else { panic!("Received rectangle command missing required attributes"); }
-                        }
-                    }
+                let end_name = element_name(e.name().as_ref());
+                if end_name == "color" || end_name == "colex" { text_colour = None; }
+                if matches!(end_name.as_str(), "rectangle" | "circle" | "ellipse") && let Some(builder) = shape_builder.take() {
+                    note_shape(stats, &builder);
+                    parsed_shapes.push(builder.build()?);
                 }
                 element_stack.pop();
+
+                // Back at the top level: that was a whole document, start a fresh one.
+                if element_stack.is_empty() {
+                    if let Some(builder) = shape_builder.as_ref() { note_shape(stats, builder); }
+                    stats.fallbacks += (parsed_shapes.is_empty() && shape_builder.is_none() && !doc_colour) as usize;
+                    doc_colour = false;
+                    results.push(finish_document(std::mem::replace(&mut res, new_result()), std::mem::take(&mut parsed_shapes), shape_builder.take())?);
+                    reported_commands.clear();
+                    doc_open = false;
+                }
             }
             Ok(Event::Empty(e)) => {
-                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                if name == "color" || name == "colex" { if let Some(builder) = rect_builder.as_mut() { apply_color(&reader, &e, builder); } }
-                else if name == "geometry" { if let Some(builder) = rect_builder.as_mut() { apply_geometry(&reader, &e, builder); } }
+                doc_open = true;
+                let name = element_name(e.name().as_ref());
+                note_element(stats, &name, &e);
+                if name == "fullscreen" { res.fullscreen = true; }
+                if name == "measuring" || name == "hide_markers" { res.hide_markers = true; }
+                if name == "color" || name == "colex" { if let Some(builder) = shape_builder.as_mut() { apply_color(&reader, &e, builder)?; } }
+                else if name == "geometry" && let Some(builder) = shape_builder.as_mut() { apply_geometry(&reader, &e, builder)?; }
+                else if name == "ycbcr" && let Some(builder) = shape_builder.as_mut() { apply_ycbcr(&reader, &e, builder)?; }
+                else if (name == "xyy" || name == "xyz") && let Some(builder) = shape_builder.as_mut() { apply_xyz(&reader, &e, builder, &name)?; }
+                else if name == "colorf" && let Some(builder) = shape_builder.as_mut() { apply_colorf(&reader, &e, builder)?; }
             }
             Ok(Event::Text(e)) => {
                 let raw_txt = e.unescape().unwrap_or_default().into_owned();
                 let txt_trimmed = raw_txt.trim();
                 if txt_trimmed.is_empty() { continue; }
-                if let Some(command) = element_stack.get(1) {
-                    if let Some(param) = element_stack.last() { if command != param { println!("  {} = {}", param, txt_trimmed); } }
+                if let Some(command) = element_stack.get(1) && let Some(param) = element_stack.last() && command != param { println!("  {} = {}", param, txt_trimmed); }
+                // Go by the open-element path, not fixed depths: readings count anywhere under a
+                // <result>, however deeply it is wrapped (<CS_RMC><response><result>...).
+                let cur_elem = element_stack.last().map_or("", String::as_str);
+                if matches!(cur_elem, "name" | "label") { res.label = Some(txt_trimmed.to_string()); }
+                if cur_elem == "error" { res.error = Some(txt_trimmed.to_string()); }
+                if matches!(cur_elem, "color" | "colex") && let Some(pending) = text_colour.take() && let Some(builder) = shape_builder.as_mut() {
+                    apply_color_text(txt_trimmed, pending, builder)?;
                 }
-                if !in_result { continue; }
-                match cur_elem.as_str() {
-                    "red" => { if let Ok(v) = txt_trimmed.parse::<u16>() { res.red = v } else if let Ok(v8) = txt_trimmed.parse::<u8>() { res.red = v8 as u16; } }
-                    "green" => { if let Ok(v) = txt_trimmed.parse::<u16>() { res.green = v } else if let Ok(v8) = txt_trimmed.parse::<u8>() { res.green = v8 as u16; } }
-                    "blue" => { if let Ok(v) = txt_trimmed.parse::<u16>() { res.blue = v } else if let Ok(v8) = txt_trimmed.parse::<u8>() { res.blue = v8 as u16; } }
-                    "x" => { if let Ok(v) = txt_trimmed.parse::<f64>() { res.x = Some(v) } }
-                    "y" => { if let Ok(v) = txt_trimmed.parse::<f64>() { res.y = Some(v) } }
-                    "Y" => { if let Ok(v) = txt_trimmed.parse::<f64>() { res.y_lum = Some(v) } }
-                    _ => {}
+                if cur_elem == "hold_ms" {
+                    match txt_trimmed.parse::<u64>() { Ok(ms) => res.hold = Some(Duration::from_millis(ms)), Err(_) => stats.values_dropped += 1 }
                 }
+                if !element_stack.iter().any(|e| e == "result") { continue; }
+                let parsed = match cur_elem {
+                    "red" => { if let Ok(v) = txt_trimmed.parse::<u16>() { res.red = v; doc_colour = true; true } else if let Ok(v8) = txt_trimmed.parse::<u8>() { res.red = v8 as u16; doc_colour = true; true } else { false } }
+                    "green" => { if let Ok(v) = txt_trimmed.parse::<u16>() { res.green = v; doc_colour = true; true } else if let Ok(v8) = txt_trimmed.parse::<u8>() { res.green = v8 as u16; doc_colour = true; true } else { false } }
+                    "blue" => { if let Ok(v) = txt_trimmed.parse::<u16>() { res.blue = v; doc_colour = true; true } else if let Ok(v8) = txt_trimmed.parse::<u8>() { res.blue = v8 as u16; doc_colour = true; true } else { false } }
+                    "x" => { if let Ok(v) = txt_trimmed.parse::<f64>() { res.x = Some(v); true } else { false } }
+                    "y" => { if let Ok(v) = txt_trimmed.parse::<f64>() { res.y = Some(v); true } else { false } }
+                    "Y" => { if let Ok(v) = txt_trimmed.parse::<f64>() { res.y_lum = Some(v); true } else { false } }
+                    _ => true,
+                };
+                if !parsed { stats.values_dropped += 1; }
             }
             Ok(Event::Eof) => break,
-            Err(e) => { return Err(format!("xml parse error: {}", e)); }
+            Err(e) => { return Err(ParseError::Xml { position: reader.buffer_position(), source: e }); }
             _ => {}
         }
         buf.clear();
     }
 
-    if let Some(builder) = rect_builder {
-        if let Some(rect) = builder.build() { parsed_shapes.push(ShapeInstruction::Rectangle(rect)); }
-        else { panic!("Received rectangle command missing required attributes"); }
+    // A trailing unterminated document (or an empty payload) still produces a result.
+    if doc_open || results.is_empty() {
+        if let Some(builder) = shape_builder.as_ref() { note_shape(stats, builder); }
+        stats.fallbacks += (parsed_shapes.is_empty() && shape_builder.is_none() && !doc_colour) as usize;
+        results.push(finish_document(res, parsed_shapes, shape_builder)?);
     }
 
-    res.shapes = parsed_shapes;
+    Ok(results)
+}
 
-    // Debug output for received command: prefer the first parsed shape's color if available
-    let (bit_depth, r_val, g_val, b_val) = if let Some(shape) = res.shapes.get(0) {
-        match shape { ShapeInstruction::Rectangle(rsh) => ( rsh.color.depth_bits, rsh.color.red, rsh.color.green, rsh.color.blue ) }
-    } else { (8u8, res.red, res.green, res.blue) };
+/// The root may carry an (unquoted) version attribute; reject versions we don't speak.
+fn check_version(element: &BytesStart) -> Result<(), ParseError> {
+    for attr in element.html_attributes().with_checks(false).flatten() {
+        if attr.key.as_ref() == b"version" {
+            let version = String::from_utf8_lossy(&attr.value).trim().to_string();
+            if version != SUPPORTED_VERSION { return Err(ParseError::UnsupportedVersion(version)); }
+        }
+    }
+    Ok(())
+}
 
-    println!("Bit depth = {} , R = {} , G = {} , B = {}", bit_depth, r_val, g_val, b_val);
+/// Command asking the server which calibration profiles it has (`--list-profiles`).
+pub const LIST_PROFILES_COMMAND: &str = "list profiles";
 
-    Ok(res)
+/// Parse the reply to `LIST_PROFILES_COMMAND`: a `<profiles>` element holding one
+/// `<profile>` per entry, named by its text or a `name` attribute, anywhere in the
+/// document(s):
+/// ```text
+/// <CS_RMC version=1><profiles><profile>Rec.709 SDR</profile><profile name="P3 D65"/></profiles></CS_RMC>
+/// ```
+/// None when the frame has no `<profiles>` at all (some other reply), so a caller can
+/// keep waiting; `<profiles/>` is an empty list. Names come back in document order.
+pub fn parse_profile_list(xml: &str) -> Result<Option<Vec<String>>, ParseError> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut element_stack: Vec<String> = Vec::new();
+    let mut profiles = None;
+    // inside a <profile> without a name attribute: its text is the name
+    let mut awaiting_text = false;
+    let named = |reader: &Reader<&[u8]>, e: &BytesStart| e.try_get_attribute("name").ok().flatten().and_then(|a| a.decode_and_unescape_value(reader).ok()).map(|v| v.trim().to_string());
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                let name = element_name(e.name().as_ref());
+                if element_stack.is_empty() { check_version(&e)?; }
+                if name == "profiles" { profiles.get_or_insert_with(Vec::new); }
+                if name == "profile" && let Some(list) = profiles.as_mut() {
+                    match named(&reader, &e) { Some(n) if !n.is_empty() => list.push(n), _ => awaiting_text = true }
+                }
+                element_stack.push(name);
+            }
+            Ok(Event::Empty(e)) => {
+                let name = element_name(e.name().as_ref());
+                if name == "profiles" { profiles.get_or_insert_with(Vec::new); }
+                if name == "profile" && let (Some(list), Some(n)) = (profiles.as_mut(), named(&reader, &e)) && !n.is_empty() { list.push(n); }
+            }
+            Ok(Event::End(_)) => { element_stack.pop(); awaiting_text = false; }
+            Ok(Event::Text(e)) => {
+                let text = e.unescape().unwrap_or_default();
+                if awaiting_text && let Some(list) = profiles.as_mut() && !text.trim().is_empty() {
+                    list.push(text.trim().to_string());
+                    awaiting_text = false;
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(ParseError::Xml { position: reader.buffer_position(), source: e }),
+            _ => {}
+        }
+    }
+    Ok(profiles)
 }
 
-/// Read a length-prefixed message from the blocking TCP stream.
-/// Header is a 4-byte big-endian signed i32. Negative means disconnect.
-/// Returns Ok(Some(string)) for a payload, Ok(None) for negative header, Err on io.
-fn read_message_from_stream(stream: &mut TcpStream) -> std::io::Result<Option<String>> {
-    let mut header = [0u8; 4];
-    stream.read_exact(&mut header)?;
-    let signed_len = i32::from_be_bytes(header);
-    if signed_len < 0 { return Ok(None); }
-    let len = signed_len as usize;
-    if len == 0 { return Ok(Some(String::new())); }
-    let mut payload = vec![0u8; len];
-    stream.read_exact(&mut payload)?;
-    String::from_utf8(payload).map(Some).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid utf8 payload"))
+/// Byte order of the 4-byte frame length prefix. ColourSpace uses big-endian;
+/// some home-grown servers/bridges send little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little,
 }
 
-/// Connect to an address string like "192.168.168.11:20002" with a short timeout.
-/// Tries all resolved socket addrs and returns the first successful TcpStream.
-fn connect_with_timeout(addr_str: &str, timeout: Duration) -> std::io::Result<TcpStream> {
-    let addrs = addr_str.to_socket_addrs()?;
+impl std::str::FromStr for Endianness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "big" | "be" => Ok(Endianness::Big),
+            "little" | "le" => Ok(Endianness::Little),
+            other => Err(format!("unknown endianness '{}', expected: big, little", other)),
+        }
+    }
+}
+
+impl Endianness {
+    fn encode(self, len: i32) -> [u8; 4] {
+        match self { Endianness::Big => len.to_be_bytes(), Endianness::Little => len.to_le_bytes() }
+    }
+
+    fn decode(self, header: [u8; 4]) -> i32 {
+        match self { Endianness::Big => i32::from_be_bytes(header), Endianness::Little => i32::from_le_bytes(header) }
+    }
+}
+
+/// Largest frame accepted by default (see `WorkerConfig::max_payload`).
+const DEFAULT_MAX_PAYLOAD: usize = 16 * 1024 * 1024;
+
+/// Read a length-prefixed message from a blocking stream (TCP or TLS).
+/// Header is a 4-byte signed i32 in `endian` order (big-endian for ColourSpace).
+/// Negative means disconnect (`Closed`, same as EOF). Frames longer than
+/// `max_payload` are rejected before allocating.
+/// `timeout` is only used to report a `Timeout`; set it on the socket itself.
+/// A one-off read: whatever arrived of a frame that timed out is dropped with it. Keep a
+/// `FrameReader` to carry on after a timeout instead.
+pub fn read_message_from_stream<R: Read>(stream: &mut R, max_payload: usize, endian: Endianness, timeout: Option<Duration>) -> Result<String, ProtocolError> {
+    FrameReader::new(max_payload, endian).read_frame(stream, timeout)
+}
+
+/// How far a `FrameReader` is into the frame on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameState { AwaitingHeader { got: usize }, AwaitingPayload { got: usize, need: usize } }
+
+/// Reassembles length-prefixed frames (framing as in `read_message_from_stream`) across
+/// reads that time out part way. `read_exact` loses what it had read when the socket's
+/// read timeout fires; this keeps it, so after a `ProtocolError::Timeout` the next
+/// `read_frame` resumes the same header or payload rather than taking the middle of a
+/// payload for a fresh length prefix. Keep one per connection, and read it either with
+/// `read_frame` or with `read_and_parse`, not both.
+pub struct FrameReader { state: FrameState, header: [u8; 4], payload: Vec<u8>, max_payload: usize, endian: Endianness }
+
+impl FrameReader {
+    pub fn new(max_payload: usize, endian: Endianness) -> Self {
+        Self { state: FrameState::AwaitingHeader { got: 0 }, header: [0; 4], payload: Vec::new(), max_payload, endian }
+    }
+
+    /// Bytes of the current, unfinished frame read so far (header included).
+    pub fn pending(&self) -> usize {
+        match self.state { FrameState::AwaitingHeader { got } => got, FrameState::AwaitingPayload { got, .. } => 4 + got }
+    }
+
+    /// Read until a whole frame is in. Timeouts (`WouldBlock`/`TimedOut`) keep the progress;
+    /// any other error leaves the stream unusable anyway.
+    pub fn read_frame<R: Read>(&mut self, stream: &mut R, timeout: Option<Duration>) -> Result<String, ProtocolError> {
+        if let FrameState::AwaitingHeader { .. } = self.state {
+            let len = self.read_header(stream, timeout)?;
+            self.payload = vec![0u8; len];
+            self.state = FrameState::AwaitingPayload { got: 0, need: len };
+        }
+        while let FrameState::AwaitingPayload { got, need } = self.state && got < need {
+            match stream.read(&mut self.payload[got..need]) {
+                Ok(0) => return Err(ProtocolError::Closed),
+                Ok(n) => self.state = FrameState::AwaitingPayload { got: got + n, need },
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(io_to_protocol(e, timeout)),
+            }
+        }
+        self.state = FrameState::AwaitingHeader { got: 0 };
+        String::from_utf8(std::mem::take(&mut self.payload)).map_err(|_| ProtocolError::InvalidUtf8)
+    }
+
+    /// Parse the next frame straight off the stream, `FRAME_CHUNK` bytes at a time, so a
+    /// large payload is never held whole. A partial length prefix is kept across timeouts
+    /// as in `read_frame`; a payload, once started, is read to its end in this call (the
+    /// parser can't be put aside half way), riding out read timeouts for as long as it
+    /// keeps coming (see `Patient`). The outer error is a link failure; the inner result
+    /// is the frame's parse outcome (the rest of a frame that fails to parse is skipped).
+    /// With `raw`, the first `RAW_CAPTURE_MAX` bytes of the payload are copied into it.
+    pub fn read_and_parse<R: Read>(&mut self, stream: &mut R, timeout: Option<Duration>, (r, g, b): (u16, u16, u16), raw: Option<&mut Vec<u8>>) -> Result<Result<Vec<MeasurementResult>, ParseError>, ProtocolError> {
+        let len = self.read_header(stream, timeout)?;
+        // the header just came in, so the payload starts with a full wait
+        let recorder = Recorder { inner: Patient { inner: stream, progressed: true }.take(len as u64), copy: raw };
+        let mut frame = std::io::BufReader::with_capacity(FRAME_CHUNK.min(len.max(1)), recorder);
+        let parsed = parse_measurement_from_reader(&mut frame, r, g, b);
+
+        // A socket failure mid-frame is the link's problem, not the frame's.
+        if let Err(ParseError::Xml { source: quick_xml::Error::Io(e), .. }) = &parsed {
+            return Err(io_to_protocol(std::io::Error::new(e.kind(), e.to_string()), timeout));
+        }
+        // Whatever the parser left unread still belongs to this frame.
+        std::io::copy(&mut frame, &mut std::io::sink()).map_err(|e| io_to_protocol(e, timeout))?;
+        if frame.into_inner().inner.limit() > 0 { return Err(ProtocolError::Closed); }
+        Ok(parsed)
+    }
+
+    /// Read (or carry on reading) a frame's length prefix and check it.
+    fn read_header<R: Read>(&mut self, stream: &mut R, timeout: Option<Duration>) -> Result<usize, ProtocolError> {
+        while let FrameState::AwaitingHeader { got } = self.state && got < 4 {
+            match stream.read(&mut self.header[got..]) {
+                Ok(0) => return Err(ProtocolError::Closed),
+                Ok(n) => self.state = FrameState::AwaitingHeader { got: got + n },
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(io_to_protocol(e, timeout)),
+            }
+        }
+        self.state = FrameState::AwaitingHeader { got: 0 };
+        let signed_len = self.endian.decode(self.header);
+        if signed_len < 0 { return Err(ProtocolError::Closed); }
+        let len = signed_len as usize;
+        if len > self.max_payload { return Err(ProtocolError::FrameTooLarge { len, max: self.max_payload }); }
+        Ok(len)
+    }
+}
+
+fn io_to_protocol(e: std::io::Error, timeout: Option<Duration>) -> ProtocolError {
+    match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => ProtocolError::Closed,
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => ProtocolError::Timeout(timeout.unwrap_or_default()),
+        _ => ProtocolError::Io(e),
+    }
+}
+
+/// Read buffer for parsing frames straight off the socket.
+const FRAME_CHUNK: usize = 64 * 1024;
+
+/// A payload being parsed as it arrives: a read timeout is waited out again as long as
+/// something arrived since the previous one, and only a whole timeout with nothing at
+/// all ends the frame (and with it the link, which is then mid-frame).
+struct Patient<R> { inner: R, progressed: bool }
+
+impl<R: Read> Read for Patient<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            match self.inner.read(buf) {
+                Ok(n) => { self.progressed |= n > 0; return Ok(n); }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) && self.progressed => self.progressed = false,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Most of a raw frame kept for `keep_raw`/`dump_raw`; longer frames are cut here.
+const RAW_CAPTURE_MAX: usize = 256 * 1024;
+
+/// Passes reads through, copying up to `RAW_CAPTURE_MAX` bytes into `copy`.
+struct Recorder<'a, R> { inner: R, copy: Option<&'a mut Vec<u8>> }
+
+impl<R: Read> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(copy) = self.copy.as_mut() {
+            let room = RAW_CAPTURE_MAX.saturating_sub(copy.len());
+            copy.extend_from_slice(&buf[..n.min(room)]);
+        }
+        Ok(n)
+    }
+}
+
+/// Write one length-prefixed message (same framing as `read_message_from_stream`).
+pub fn send_message_to_stream<W: Write>(stream: &mut W, msg: &str, endian: Endianness) -> std::io::Result<()> {
+    let len = i32::try_from(msg.len()).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "message too large to frame"))?;
+    stream.write_all(&endian.encode(len))?;
+    stream.write_all(msg.as_bytes())?;
+    stream.flush()
+}
+
+/// Build an outgoing `<CS_RMC>` command, optionally with one empty element carrying
+/// attributes. The command text and every attribute value go through quick-xml's
+/// `escape`, so values with `<`, `&` or quotes can't corrupt the document. Element and
+/// attribute names are taken as-is and must be plain XML names.
+pub fn command_xml(command: &str, element: Option<(&str, &[(&str, String)])>) -> String {
+    let mut xml = format!("<?xml version=\"1.0\" encoding=\"UTF-8\" ?><CS_RMC version={}><command>{}</command>", SUPPORTED_VERSION, escape(command));
+    if let Some((name, attrs)) = element {
+        xml.push('<');
+        xml.push_str(name);
+        for (key, value) in attrs { xml.push_str(&format!(" {}=\"{}\"", key, escape(value))); }
+        xml.push_str("/>");
+    }
+    xml.push_str("</CS_RMC>");
+    xml
+}
+
+/// Build the command asking the server to measure the patch currently on screen.
+/// The colour is echoed so the server can match the reading to the patch.
+pub fn measure_request_xml(colour: ColorRGB) -> String {
+    let attrs = [
+        ("red", colour.red.to_string()),
+        ("green", colour.green.to_string()),
+        ("blue", colour.blue.to_string()),
+        ("bits", colour.depth_bits.to_string()),
+    ];
+    command_xml("measure", Some(("color", &attrs)))
+}
+
+/// The send half of the worker's connection, shared between `send_command` callers.
+pub type SharedWriter = Arc<Mutex<Box<dyn Write + Send + Sync>>>;
+
+/// Send a command upstream on the worker's connection. The state lock is only held to
+/// pick up the writer; the send itself is bounded by `WorkerConfig::write_timeout`.
+pub fn send_command(state: &RwLock<SharedState>, xml: &str) -> std::io::Result<()> {
+    let (writer, endian) = {
+        let r = state.read().unwrap_or_else(PoisonError::into_inner);
+        (r.writer.clone(), r.endianness)
+    };
+    match writer {
+        Some(stream) => send_message_to_stream(&mut *stream.lock().unwrap_or_else(PoisonError::into_inner), xml, endian),
+        None => Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "worker has no connection")),
+    }
+}
+
+/// Ask the server to measure `colour`. `measuring` is raised (and the send time noted
+/// for `last_rtt`) before sending so even an instant reply clears it, and dropped again
+/// if the send fails. `colour` becomes `request_colour`, which the patch shows until
+/// the reading arrives (see `SharedState::pending_request`).
+pub fn request_measurement(state: &RwLock<SharedState>, colour: ColorRGB) -> std::io::Result<()> {
+    { let mut w = state.write().unwrap_or_else(PoisonError::into_inner); w.measuring = true; w.request_colour = colour; w.request_sent_at = Some(Instant::now()); }
+    let sent = send_command(state, &measure_request_xml(colour));
+    if sent.is_err() { let mut w = state.write().unwrap_or_else(PoisonError::into_inner); w.measuring = false; w.request_sent_at = None; }
+    sent
+}
+
+/// One-off handshake the server expects right after connecting.
+const INIT_PROFILE_XML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" ?><CS_RMC version=1><command>init profile</command></CS_RMC>";
+
+/// A server dialect: what to send on connect and how frames are framed (documents are
+/// checked against `SUPPORTED_VERSION`). `WorkerConfig::for_protocol` turns one into
+/// the matching `init_command`/`endianness`, which stay overridable one by one
+/// (`--no-handshake`, `--frame-endian`). A new dialect is a variant here, not a new knob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    /// ColourSpace remote control, version 1: big-endian length prefixes, `init profile`
+    /// handshake written unframed right after connecting
+    #[default]
+    CsRmcV1,
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cs-rmc-v1" | "csrmc1" | "v1" => Ok(Protocol::CsRmcV1),
+            _ => Err(format!("unknown protocol '{}', expected: cs-rmc-v1", s)),
+        }
+    }
+}
+
+impl Protocol {
+    /// Bytes written as-is right after connecting, if the dialect has a handshake.
+    pub fn handshake(self) -> Option<String> {
+        match self { Protocol::CsRmcV1 => Some(INIT_PROFILE_XML.to_string()) }
+    }
+
+    /// Byte order of frame length prefixes, both directions.
+    pub fn endianness(self) -> Endianness {
+        match self { Protocol::CsRmcV1 => Endianness::Big }
+    }
+}
+
+/// Connectivity check: connect, send the handshake and wait for the first frame.
+/// Returns (connect time, handshake-to-first-frame round trip). Nothing is parsed.
+/// Uses the connect timeout, local bind, TLS, framing and handshake (`init_command`,
+/// None = just wait for a frame) of `config`; nothing else.
+pub fn ping(addr: &str, config: &WorkerConfig, frame_timeout: Duration) -> Result<(Duration, Duration), ProtocolError> {
+    let started = Instant::now();
+    let mut conn = connect(addr, config)?;
+    let connected = started.elapsed();
+
+    conn.socket.set_read_timeout(Some(frame_timeout))?;
+    let sent = Instant::now();
+    if let Some(init) = config.init_command.as_ref() {
+        conn.writer.write_all(init.as_bytes())?;
+        conn.writer.flush()?;
+    }
+
+    read_message_from_stream(&mut conn.reader, config.max_payload, config.endianness, Some(frame_timeout))?;
+    Ok((connected, sent.elapsed()))
+}
+
+/// One reading without the worker, threads or shared state: connect, handshake, ask for
+/// `colour`, wait for a frame with a reading in it and close. `timeout` bounds the
+/// connect and then, separately, everything after it. Frames without a reading (the
+/// handshake reply, patches) and frames that don't parse are skipped.
+#[allow(dead_code)] // for embedding; the binary goes through `measure_once_with`
+pub fn measure_once(addr: &str, colour: ColorRGB, timeout: Duration) -> Result<MeasurementResult, ProtocolError> {
+    measure_once_with(addr, colour, timeout, &WorkerConfig::default())
+}
+
+/// `measure_once` over the link, framing and handshake `config` describes (its connect
+/// timeout is replaced by `timeout`).
+pub fn measure_once_with(addr: &str, colour: ColorRGB, timeout: Duration, config: &WorkerConfig) -> Result<MeasurementResult, ProtocolError> {
+    let config = WorkerConfig { connect_timeout: timeout, ..config.clone() };
+    let mut conn = connect(addr, &config)?;
+    let deadline = Instant::now() + timeout;
+    let mut frames = FrameReader::new(config.max_payload, config.endianness);
+    let mut next_frame = |conn: &mut Connection| -> Result<Vec<MeasurementResult>, ProtocolError> {
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() { return Err(ProtocolError::Timeout(timeout)); }
+            conn.socket.set_read_timeout(Some(left))?;
+            match frames.read_and_parse(&mut conn.reader, Some(timeout), (colour.red, colour.green, colour.blue), None)? {
+                Ok(docs) => return Ok(docs),
+                Err(e @ ParseError::UnsupportedVersion(_)) => return Err(e.into()),
+                Err(_) => continue,
+            }
+        }
+    };
+
+    if let Some(init) = config.init_command.as_ref() {
+        conn.writer.write_all(init.as_bytes())?;
+        conn.writer.flush()?;
+        next_frame(&mut conn)?;
+    }
+    send_message_to_stream(&mut conn.writer, &measure_request_xml(colour), config.endianness)?;
+    let reading = loop {
+        if let Some(reading) = next_frame(&mut conn)?.into_iter().find(|m| m.x.is_some() || m.y.is_some() || m.y_lum.is_some()) { break reading; }
+    };
+    let _ = conn.socket.shutdown(Shutdown::Both);
+    Ok(reading)
+}
+
+/// Ask the server for its profile list (see `parse_profile_list`): connect, handshake,
+/// send `LIST_PROFILES_COMMAND` and wait for a frame with a `<profiles>` in it. Like
+/// `measure_once_with`, `timeout` bounds the connect and then everything after it;
+/// frames that aren't the list are skipped.
+pub fn list_profiles(addr: &str, timeout: Duration, config: &WorkerConfig) -> Result<Vec<String>, ProtocolError> {
+    let config = WorkerConfig { connect_timeout: timeout, ..config.clone() };
+    let mut conn = connect(addr, &config)?;
+    let deadline = Instant::now() + timeout;
+    let next_frame = |conn: &mut Connection| -> Result<String, ProtocolError> {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() { return Err(ProtocolError::Timeout(timeout)); }
+        conn.socket.set_read_timeout(Some(left))?;
+        read_message_from_stream(&mut conn.reader, config.max_payload, config.endianness, Some(timeout))
+    };
+
+    if let Some(init) = config.init_command.as_ref() {
+        conn.writer.write_all(init.as_bytes())?;
+        conn.writer.flush()?;
+        next_frame(&mut conn)?;
+    }
+    send_message_to_stream(&mut conn.writer, &command_xml(LIST_PROFILES_COMMAND, None), config.endianness)?;
+    let profiles = loop {
+        match parse_profile_list(&next_frame(&mut conn)?) {
+            Ok(Some(profiles)) => break profiles,
+            Err(e @ ParseError::UnsupportedVersion(_)) => return Err(e.into()),
+            Ok(None) | Err(_) => continue,
+        }
+    };
+    let _ = conn.socket.shutdown(Shutdown::Both);
+    Ok(profiles)
+}
+
+/// Wire-level debugging (`--hexdump`): connect, send the handshake, then read `frames`
+/// frames through `read_message_from_stream` and write the exact bytes of each, length
+/// prefix included, to `out`. A frame that fails to read (bad length, closed mid-frame)
+/// is dumped as far as it got and ends the dump with that error.
+///
+/// Output, one frame after another:
+/// ```text
+/// # frame 1: 46 bytes (header 4 + payload 42)
+/// 00000000  00 00 00 2a 3c 3f 78 6d  6c 20 76 65 72 73 69 6f  |...*<?xml versio|
+/// ...
+/// # frame 1 ok
+/// ```
+/// Dump lines are `hexdump -C` style; lines starting with `#` are comments.
+pub fn hexdump(addr: &str, config: &WorkerConfig, frames: usize, frame_timeout: Duration, out: &mut impl Write) -> Result<(), ProtocolError> {
+    let mut conn = connect(addr, config)?;
+    conn.socket.set_read_timeout(Some(frame_timeout))?;
+    if let Some(init) = config.init_command.as_ref() {
+        conn.writer.write_all(init.as_bytes())?;
+        conn.writer.flush()?;
+    }
+
+    for n in 1..=frames {
+        let mut bytes = Vec::new();
+        let read = read_message_from_stream(&mut Recorder { inner: &mut conn.reader, copy: Some(&mut bytes) }, config.max_payload, config.endianness, Some(frame_timeout));
+        writeln!(out, "# frame {}: {} bytes (header {} + payload {})", n, bytes.len(), bytes.len().min(4), bytes.len().saturating_sub(4))?;
+        for line in hex_lines(&bytes) { writeln!(out, "{}", line)?; }
+        match read {
+            Ok(_) if bytes.len() >= RAW_CAPTURE_MAX => writeln!(out, "# frame {} ok (dump truncated at {} bytes)", n, RAW_CAPTURE_MAX)?,
+            Ok(_) => writeln!(out, "# frame {} ok", n)?,
+            Err(e) => { writeln!(out, "# frame {} failed: {}", n, e)?; return Err(e); }
+        }
+    }
+    let _ = conn.socket.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+/// `hexdump -C` style lines: offset, 16 bytes in two groups of 8, printable ASCII.
+fn hex_lines(bytes: &[u8]) -> Vec<String> {
+    bytes.chunks(16).enumerate().map(|(i, chunk)| {
+        let hex: Vec<String> = (0..16).map(|j| chunk.get(j).map_or("  ".to_string(), |b| format!("{:02x}", b))).collect();
+        let ascii: String = chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+        format!("{:08x}  {}  {}  |{}|", i * 16, hex[..8].join(" "), hex[8..].join(" "), ascii)
+    }).collect()
+}
+
+/// Open the link `config` asks for: the Unix socket at `config.socket` if set (`addr`
+/// is then only a name for logs), else TCP, optionally with TLS, to `addr`.
+pub fn connect(addr: &str, config: &WorkerConfig) -> std::io::Result<Connection> {
+    connect_cached(&mut Resolver::new(addr, None), config)
+}
+
+/// `connect` with the TCP address taken from `resolver`, so repeated connects (the
+/// worker's reconnects) reuse one lookup.
+pub fn connect_cached(resolver: &mut Resolver, config: &WorkerConfig) -> std::io::Result<Connection> {
+    match config.socket.as_deref() {
+        #[cfg(unix)]
+        Some(path) => {
+            let unix = UnixStream::connect(path).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            Ok(Connection { reader: Box::new(unix.try_clone()?), writer: Box::new(unix.try_clone()?), socket: Socket::Unix(unix) })
+        }
+        #[cfg(not(unix))]
+        Some(_) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Unix domain sockets are not available on this platform")),
+        None => connect_with_timeout(resolver, config.connect_timeout, config.bind, config.tls.as_ref()),
+    }
+}
+
+/// A server address ("host:port") resolved once and reused, so reconnects don't each
+/// do a DNS lookup (and don't fail when the resolver is flaky). With `refresh`, a lookup
+/// older than that is redone on next use, to follow DHCP/DNS changes; if that lookup
+/// fails the previous addresses are kept.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    name: String,
+    refresh: Option<Duration>,
+    cached: Option<(Instant, Vec<SocketAddr>)>,
+}
+
+impl Resolver {
+    pub fn new(name: &str, refresh: Option<Duration>) -> Self {
+        Self { name: name.to_string(), refresh, cached: None }
+    }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    /// The addresses to try, looking them up if there are none yet or they are stale.
+    pub fn addrs(&mut self) -> std::io::Result<Vec<SocketAddr>> {
+        self.addrs_with(Instant::now(), |name| name.to_socket_addrs().map(Iterator::collect))
+    }
+
+    fn addrs_with(&mut self, now: Instant, lookup: impl FnOnce(&str) -> std::io::Result<Vec<SocketAddr>>) -> std::io::Result<Vec<SocketAddr>> {
+        if let Some((at, addrs)) = &self.cached
+        && self.refresh.is_none_or(|refresh| now.saturating_duration_since(*at) < refresh) {
+            return Ok(addrs.clone());
+        }
+        match (lookup(&self.name), self.cached.as_mut()) {
+            // nothing found isn't worth keeping; connecting reports it
+            (Ok(addrs), _) if addrs.is_empty() => Ok(addrs),
+            (Ok(addrs), old) => {
+                if let Some((_, old)) = old && *old != addrs { eprintln!("{} now resolves to {:?} (was {:?})", self.name, addrs, old); }
+                self.cached = Some((now, addrs.clone()));
+                Ok(addrs)
+            }
+            (Err(e), Some((at, old))) => {
+                eprintln!("Re-resolving {} failed ({}), keeping {:?}", self.name, e, old);
+                *at = now;
+                Ok(old.clone())
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+}
+
+/// Connect to `resolver`'s address (like "192.168.168.11:20002") with a short timeout.
+/// Tries all resolved socket addrs and keeps the first successful TcpStream.
+/// With `bind`, the socket is bound to that local address first so the connection
+/// leaves through its interface; only remote addresses of the same family are tried.
+/// With `tls`, the TLS handshake is done here too (same timeout).
+pub fn connect_with_timeout(resolver: &mut Resolver, timeout: Duration, bind: Option<IpAddr>, tls: Option<&TlsOptions>) -> std::io::Result<Connection> {
+    let addr_str = resolver.name().to_string();
+    let tcp = connect_tcp(&addr_str, resolver.addrs()?, timeout, bind)?;
+    match tls {
+        None => Ok(Connection { reader: Box::new(tcp.try_clone()?), writer: Box::new(tcp.try_clone()?), socket: Socket::Tcp(tcp) }),
+        #[cfg(feature = "tls")]
+        Some(opts) => {
+            // host part of host:port, without the brackets of an IPv6 literal
+            let host = addr_str.rsplit_once(':').map_or(addr_str.as_str(), |(host, _)| host).trim_start_matches('[').trim_end_matches(']');
+            let (reader, writer) = crate::tls::wrap(&tcp, host, opts, timeout)?;
+            Ok(Connection { socket: Socket::Tcp(tcp), reader, writer })
+        }
+        #[cfg(not(feature = "tls"))]
+        Some(_) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "TLS requested but this build has no TLS support (rebuild with --features tls)")),
+    }
+}
+
+fn connect_tcp(addr_str: &str, addrs: Vec<SocketAddr>, timeout: Duration, bind: Option<IpAddr>) -> std::io::Result<TcpStream> {
     let mut last_err: Option<std::io::Error> = None;
 
     for addr in addrs {
-        match TcpStream::connect_timeout(&addr, timeout) {
+        let res = match bind {
+            None => TcpStream::connect_timeout(&addr, timeout),
+            Some(local) if local.is_ipv4() != addr.is_ipv4() => continue,
+            Some(local) => connect_bound(addr, local, timeout),
+        };
+        match res {
             Ok(stream) => { return Ok(stream); }
             Err(e) => { last_err = Some(e); }
         }
     }
 
-    Err(last_err.unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "no socket addresses found")))
+    Err(last_err.unwrap_or_else(|| match bind {
+        Some(local) => std::io::Error::other(format!("no {} address found for {} to match local address {}", if local.is_ipv4() { "IPv4" } else { "IPv6" }, addr_str, local)),
+        None => std::io::Error::other("no socket addresses found"),
+    }))
+}
+
+/// An open link to the server. `reader` and `writer` are independent halves (plain
+/// socket clones, or the two sides of a TLS session) so sending never waits on a
+/// blocked read. Timeouts set on `socket` apply to both.
+pub struct Connection {
+    pub socket: Socket,
+    pub reader: Box<dyn Read + Send>,
+    pub writer: Box<dyn Write + Send + Sync>,
+}
+
+/// The stream under a `Connection`: TCP, or a Unix domain socket (`--socket`).
+/// Only what the worker and bridge need besides reading and writing.
+pub enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self { Socket::Tcp(s) => s.set_read_timeout(timeout), #[cfg(unix)] Socket::Unix(s) => s.set_read_timeout(timeout) }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self { Socket::Tcp(s) => s.set_write_timeout(timeout), #[cfg(unix)] Socket::Unix(s) => s.set_write_timeout(timeout) }
+    }
+
+    pub fn read_timeout(&self) -> std::io::Result<Option<Duration>> {
+        match self { Socket::Tcp(s) => s.read_timeout(), #[cfg(unix)] Socket::Unix(s) => s.read_timeout() }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        match self { Socket::Tcp(s) => s.shutdown(how), #[cfg(unix)] Socket::Unix(s) => s.shutdown(how) }
+    }
+
+    pub fn try_clone(&self) -> std::io::Result<Socket> {
+        match self { Socket::Tcp(s) => s.try_clone().map(Socket::Tcp), #[cfg(unix)] Socket::Unix(s) => s.try_clone().map(Socket::Unix) }
+    }
+}
+
+/// TLS settings for `--tls`. Needs the `tls` feature; without it connecting fails.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+pub struct TlsOptions {
+    /// trust only this PEM bundle instead of the built-in web roots
+    pub ca: Option<std::path::PathBuf>,
+    /// skip certificate verification altogether
+    pub insecure: bool,
+}
+
+fn connect_bound(addr: SocketAddr, local: IpAddr, timeout: Duration) -> std::io::Result<TcpStream> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket
+    .bind(&SocketAddr::new(local, 0).into())
+    .map_err(|e| std::io::Error::new(e.kind(), format!("cannot bind to local address {}: {}", local, e)))?;
+    socket.connect_timeout(&addr.into(), timeout)?;
+    Ok(socket.into())
 }
 
 /// Shared state between drawing and network threads.
-pub struct SharedState { pub connected: bool, pub shapes: Vec<ShapeInstruction>, pub current_measure_colour: ColorRGB, pub request_colour: ColorRGB }
+/// `region` is owned by the drawing side; the worker never touches it.
+/// `last_measurement` holds the most recent document that carried x/y/Y, and
+/// `measurements_received` counts them so callers can tell a fresh reading from a stale one;
+/// `documents_received` counts every applied document (new patches included).
+/// `averaged_measurement` is published by the drawing side once a patch's repeated
+/// readings (`--average`) have been averaged.
+/// `label` is the name the server gave the patch currently on screen, if any.
+/// `fullscreen_field` mirrors the latest document's `<fullscreen/>` flag, `hold` its `<hold_ms>`,
+/// `hide_markers` its `<measuring/>`/`<hide_markers/>` hint.
+/// `reconnects` and `parse_errors` are running totals kept by the worker (for metrics).
+/// `focused_shape` is the shape picked with Tab on the drawing side (shown alone); the
+/// worker clears it whenever new shapes arrive.
+/// `worker_restarts` counts supervisor restarts; `last_worker_error` is why the worker last exited.
+/// `disconnect_reason` is why the last connection ended (the server's own `<error>` if
+/// it reported one just before closing); it is cleared once a connection comes up again.
+/// `last_raw` is the latest frame's XML as received (only with `WorkerConfig::keep_raw`).
+/// `history` holds the last `WorkerConfig::history` readings, oldest first.
+/// `measuring` is set while a `request_measurement` is waiting for its reading, which is
+/// the next reading to arrive; `request_colour` is then the colour asked for.
+/// `last_measurement_at` is when the latest reading's frame was received; `request_sent_at`
+/// is when the outstanding `request_measurement` went out, and `last_rtt` the time from
+/// such a request to the reading that answered it (readings nobody asked for don't count).
+/// `writer` is the send half of the worker's connection used by `send_command`, framed per
+/// `endianness`; it has its own lock so a slow send never holds up the state.
+/// `patch_seq` goes up by one every time a document changes the patch (colour or shapes).
+/// The state only ever holds the latest patch, so a renderer that reads it once per frame
+/// always ends on the last one of a burst; a jump of more than one tells it how many it
+/// never got to show.
+/// A worker that panics part way through an update poisons the lock; the supervisor
+/// clears that when it restarts it, and until then other users read through the poison
+/// (`unwrap_or_else(PoisonError::into_inner)`) rather than panic with it.
+#[derive(Default)]
+pub struct SharedState {
+    pub connected: bool, pub shapes: Vec<ShapeInstruction>, pub current_measure_colour: ColorRGB, pub request_colour: ColorRGB, pub region: Option<PatchRegion>,
+    pub last_measurement: Option<MeasurementResult>, pub measurements_received: u64, pub averaged_measurement: Option<MeasurementResult>, pub label: Option<String>, pub fullscreen_field: bool, pub hold: Option<Duration>, pub writer: Option<SharedWriter>, pub endianness: Endianness,
+    pub reconnects: u64, pub parse_errors: u64, pub worker_restarts: u64, pub last_worker_error: Option<String>,
+    pub focused_shape: Option<usize>, pub documents_received: u64, pub last_raw: Option<String>, pub measuring: bool, pub history: VecDeque<MeasurementResult>,
+    pub last_measurement_at: Option<Instant>, pub request_sent_at: Option<Instant>, pub last_rtt: Option<Duration>,
+    pub disconnect_reason: Option<String>, pub patch_seq: u64, pub hide_markers: bool,
+}
+
+impl SharedState {
+    /// The colour of an outstanding `request_measurement`. It takes precedence over
+    /// whatever the server pushes (echoed shapes included) until its reading arrives;
+    /// from then on the server's shapes decide again. One rule for every frame, so the
+    /// patch never alternates between the two while the request is in flight.
+    pub fn pending_request(&self) -> Option<ColorRGB> {
+        self.measuring.then_some(self.request_colour)
+    }
+
+    /// What the render loop needs of the state for one frame, copied out under one read.
+    pub fn snapshot(&self) -> FrameSnapshot {
+        FrameSnapshot {
+            connected: self.connected, shapes: self.shapes.clone(), current_measure_colour: self.current_measure_colour, region: self.region,
+            measurements_received: self.measurements_received, last_measurement: self.last_measurement.clone(), label: self.label.clone(),
+            fullscreen_field: self.fullscreen_field, focused_shape: self.focused_shape, documents_received: self.documents_received, hold: self.hold,
+            pending_request: self.pending_request(), patch_seq: self.patch_seq, hide_markers: self.hide_markers,
+            disconnect_reason: self.disconnect_reason.clone(), last_rtt: self.last_rtt,
+        }
+    }
+}
+
+/// `SharedState::snapshot`: the fields of the same names, with `pending_request` as the
+/// method gives it. The default is what a frame without a worker (offline) sees.
+#[derive(Debug, Clone, Default)]
+pub struct FrameSnapshot {
+    pub connected: bool, pub shapes: Vec<ShapeInstruction>, pub current_measure_colour: ColorRGB, pub region: Option<PatchRegion>,
+    pub measurements_received: u64, pub last_measurement: Option<MeasurementResult>, pub label: Option<String>,
+    pub fullscreen_field: bool, pub focused_shape: Option<usize>, pub documents_received: u64, pub hold: Option<Duration>,
+    pub pending_request: Option<ColorRGB>, pub patch_seq: u64, pub hide_markers: bool,
+    pub disconnect_reason: Option<String>, pub last_rtt: Option<Duration>,
+}
+
+/// Options for `spawn_worker`. Build it with struct-update syntax over `Default`
+/// so new options can be added without touching existing callers:
+///
+/// ```text
+/// let config = WorkerConfig {
+///     read_timeout: Some(Duration::from_secs(30)),
+///     reconnect: None,
+///     ..WorkerConfig::default()
+/// };
+/// let state = spawn_worker("192.168.1.10:20002", &config)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    /// TCP connect timeout, per attempt
+    pub connect_timeout: Duration,
+    /// treat the link as dead if nothing arrives for this long (None = wait forever); a
+    /// frame that stalls part way is resumed as long as each wait brings more of it
+    pub read_timeout: Option<Duration>,
+    /// give up on a send (the handshake, `send_command`) that can't get out for this long
+    /// (None = wait forever)
+    pub write_timeout: Option<Duration>,
+    /// delay between reconnect attempts once the link drops (None = stay disconnected)
+    pub reconnect: Option<Duration>,
+    /// handshake written right after connecting (None = send nothing)
+    pub init_command: Option<String>,
+    /// drop the connection if no valid frame follows the handshake within this long
+    /// (None = wait forever). Only applies when `init_command` is sent.
+    pub handshake_timeout: Option<Duration>,
+    /// frames whose length prefix exceeds this are rejected as a protocol error
+    pub max_payload: usize,
+    /// byte order of frame length prefixes, both directions
+    pub endianness: Endianness,
+    /// local address to connect from (None = let routing pick the interface)
+    pub bind: Option<IpAddr>,
+    /// speak TLS to the server (None = plain TCP)
+    pub tls: Option<TlsOptions>,
+    /// connect to this Unix domain socket instead of TCP (`bind` and `tls` don't apply)
+    pub socket: Option<std::path::PathBuf>,
+    /// the worker resolves the server address once and reconnects to the same addresses;
+    /// with this set it looks the name up again once the last lookup is this old
+    pub re_resolve: Option<Duration>,
+    /// keep each frame's raw XML in `SharedState::last_raw` (costs a copy per frame)
+    pub keep_raw: bool,
+    /// write each frame's raw XML to this file, replacing the previous one
+    pub dump_raw: Option<std::path::PathBuf>,
+    /// how many recent readings to keep in `SharedState::history` (0 = none)
+    pub history: usize,
+    /// restart the worker if it panics or fails (a fatal protocol error), after this delay
+    /// (doubling per failed attempt, capped at a minute). None = leave it stopped. A worker
+    /// that stops as configured (the link dropped with `reconnect` off) is never restarted.
+    pub restart: Option<Duration>,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        let protocol = Protocol::default();
+        Self {
+            connect_timeout: Duration::from_millis(500),
+            read_timeout: None,
+            write_timeout: Some(Duration::from_secs(2)),
+            reconnect: Some(Duration::from_secs(2)),
+            init_command: protocol.handshake(),
+            handshake_timeout: Some(Duration::from_secs(5)),
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            endianness: protocol.endianness(),
+            bind: None,
+            tls: None,
+            socket: None,
+            re_resolve: None,
+            keep_raw: false,
+            dump_raw: None,
+            history: 100,
+            restart: Some(Duration::from_secs(1)),
+        }
+    }
+}
 
-impl Default for SharedState {
-    fn default() -> Self { Self { connected: false, shapes: Vec::new(), current_measure_colour: ColorRGB::default(), request_colour: ColorRGB::default() } }
+impl WorkerConfig {
+    /// Defaults, with the handshake and framing of `protocol`.
+    pub fn for_protocol(protocol: Protocol) -> Self {
+        Self { init_command: protocol.handshake(), endianness: protocol.endianness(), ..Self::default() }
+    }
 }
 
 /// Spawn a background worker thread that keeps a connection and performs measurements.
 /// Returns an Arc<RwLock<SharedState>> that the caller (drawing thread) can use to read
-/// the current shapes and measured colour.
-pub fn spawn_worker(addr: &str, _pretty_print: bool) -> std::io::Result<Arc<RwLock<SharedState>>> {
+/// the current shapes and measured colour. The thread reconnects per `config.reconnect`
+/// and exits once the caller has dropped every handle to the state.
+///
+/// The worker runs under a supervisor: if it fails or panics while the caller still
+/// holds the state, it is started again (fresh connect + handshake) per `config.restart`.
+pub fn spawn_worker(addr: &str, config: &WorkerConfig) -> std::io::Result<Arc<RwLock<SharedState>>> {
     let addr = addr.to_owned();
+    let config = config.clone();
 
-    const CONNECT_TIMEOUT_MS: u64 = 500;
-    let stream_res = connect_with_timeout(&addr, Duration::from_millis(CONNECT_TIMEOUT_MS));
+    // one lookup shared by the first connect, reconnects and restarts
+    let resolver = Arc::new(Mutex::new(Resolver::new(&addr, config.re_resolve)));
+    let stream_res = connect_cached(&mut resolver.lock().unwrap(), &config);
     let stream = match stream_res { Ok(s) => Some(s), Err(e) => { eprintln!("Failed to connect to {}: {}", addr, e); None } };
 
-    let state = Arc::new(RwLock::new(SharedState::default()));
+    let state = Arc::new(RwLock::new(SharedState { endianness: config.endianness, ..SharedState::default() }));
 
-    // If connection succeeded, spawn ONLY the receiving thread.
-    if let Some(s) = stream {
-        let stream_arc = Arc::new(Mutex::new(s));
-        let state_recv = state.clone();
-        let stream_recv = stream_arc.clone();
+    // Without reconnect there is nothing for a thread to do if the first attempt failed.
+    if stream.is_none() && config.reconnect.is_none() { return Ok(state); }
 
-        thread::spawn(move || {
-            // Send init profile (one-off mandatory handshake) without helper function.
-            if let Ok(mut guard) = stream_recv.lock() {
-                let _ = guard.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\" ?><CS_RMC version=1><command>init profile</command></CS_RMC>");
-                let _ = guard.flush();
-            }
+    let state_sup = state.clone();
+    thread::spawn(move || {
+        let mut first = stream;
+        let mut backoff = config.restart.unwrap_or_default();
+        loop {
+            let next = first.take();
+            let (a, res, st, cfg) = (addr.clone(), resolver.clone(), state_sup.clone(), config.clone());
+            let started = Instant::now();
+            let reason = match thread::spawn(move || run_worker(&a, &res, next, &st, &cfg)).join() {
+                // Stopped as configured (reconnect off, link gone): nothing to restart.
+                Ok(None) => { eprintln!("Worker for {} stopped", addr); return; }
+                Ok(Some(err)) => err.to_string(),
+                Err(panic) => format!("worker panicked: {}", panic.downcast_ref::<&str>().copied().or(panic.downcast_ref::<String>().map(String::as_str)).unwrap_or("?")),
+            };
+
+            // A panic mid-update poisons the state; what's in it is still the best we have.
+            state_sup.clear_poison();
+            if Arc::strong_count(&state_sup) == 1 { return; }
+            { let mut w = state_sup.write().unwrap(); w.connected = false; w.writer = None; w.measuring = false; w.request_sent_at = None; w.last_worker_error = Some(reason.clone()); }
+            let Some(initial) = config.restart else { eprintln!("Worker for {} exited: {}", addr, reason); return };
+            // a worker that stayed up a while starts the backoff from scratch
+            if started.elapsed() >= MAX_RESTART_BACKOFF { backoff = initial; }
 
+            // Back off, doubling up to MAX_RESTART_BACKOFF, until a fresh connection comes up.
             loop {
-                let mut guard = match stream_recv.lock() { Ok(g) => g, Err(poison) => poison.into_inner() };
-
-                let msg_opt_res = read_message_from_stream(&mut *guard);
-
-                match msg_opt_res {
-                    Ok(Some(msg)) => {
-                        let (r, g, b) = { let rguard = state_recv.read().unwrap(); ( rguard.request_colour.red, rguard.request_colour.green, rguard.request_colour.blue ) };
-
-                        match parse_measurement_from_xml(&msg, r, g, b) {
-                            Ok(meas) => {
-                                let mut w = state_recv.write().unwrap();
-                                w.connected = true;
-
-                                if !meas.shapes.is_empty() {
-                                    w.current_measure_colour = meas.shapes.get(0).map(|s| match s { ShapeInstruction::Rectangle(r) => r.color }).unwrap_or(ColorRGB::from_components_u16(meas.red, meas.green, meas.blue, 8));
-                                    w.shapes = meas.shapes;
-                                } else {
-                                    w.current_measure_colour = ColorRGB::from_components_u16(meas.red, meas.green, meas.blue, 8);
-                                    w.shapes.clear();
-                                }
-                            }
-                            Err(e) => panic!("Failed to parse measurement xml: {}", e),
+                eprintln!("Worker for {} exited ({}), restarting in {:?}", addr, reason, backoff);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+                if Arc::strong_count(&state_sup) == 1 { return; }
+                // a worker that panicked mid-connect poisons the resolver; its cache is still fine
+                resolver.clear_poison();
+                match connect_cached(&mut resolver.lock().unwrap(), &config) {
+                    Ok(s) => { first = Some(s); break; }
+                    Err(e) => eprintln!("Restart connect to {} failed: {}", addr, e),
+                }
+            }
+            state_sup.write().unwrap().worker_restarts += 1;
+        }
+    }); // end supervisor
+
+    Ok(state)
+}
+
+/// Cap on the doubling delay between worker restarts.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The worker proper: run connections, reconnecting per `config.reconnect`. Returns the
+/// error that ended it, or None when the caller went away or reconnect is off.
+fn run_worker(addr: &str, resolver: &Mutex<Resolver>, stream: Option<Connection>, state_recv: &Arc<RwLock<SharedState>>, config: &WorkerConfig) -> Option<ProtocolError> {
+    let mut next = stream;
+    loop {
+        let s = match next.take() {
+            Some(s) => s,
+            None => {
+                let delay = config.reconnect?;
+                thread::sleep(delay);
+                // Only we (and the supervisor) hold the state: the drawing side gave up on this worker.
+                if Arc::strong_count(state_recv) <= 2 { return None; }
+                let conn = connect_cached(&mut resolver.lock().unwrap(), config);
+                match conn {
+                    Ok(s) => { eprintln!("Reconnected to {}", addr); state_recv.write().unwrap().reconnects += 1; s }
+                    Err(e) => { eprintln!("Reconnect to {} failed: {}", addr, e); continue; }
+                }
+            }
+        };
+
+        let err = run_connection(s, state_recv, config);
+        eprintln!("Connection to {} lost: {}", addr, err);
+
+        let mut w = state_recv.write().unwrap();
+        w.connected = false;
+        w.writer = None;
+        w.measuring = false;
+        w.request_sent_at = None;
+        w.disconnect_reason = Some(err.to_string());
+        drop(w);
+        if err.is_fatal() { return Some(err); }
+        config.reconnect?;
+    }
+}
+
+/// Handshake, then receive and apply frames on one connection until it drops.
+/// Returns what ended it; frames that fail to parse are skipped unless fatal.
+fn run_connection(conn: Connection, state_recv: &RwLock<SharedState>, config: &WorkerConfig) -> ProtocolError {
+    let Connection { socket, mut reader, mut writer } = conn;
+    if let Err(e) = socket.set_read_timeout(config.read_timeout) { eprintln!("Failed to set read timeout: {}", e); }
+    if let Err(e) = socket.set_write_timeout(config.write_timeout) { eprintln!("Failed to set write timeout: {}", e); }
+
+    // Send the handshake (init profile unless configured otherwise). If it can't go out
+    // the link is already dead: give up on it here rather than wait on a read.
+    let mut handshake_deadline = None;
+    if let Some(init) = config.init_command.as_ref() {
+        if let Err(e) = writer.write_all(init.as_bytes()).and_then(|()| writer.flush()) {
+            return ProtocolError::Io(std::io::Error::new(e.kind(), format!("handshake not sent: {}", e)));
+        }
+        handshake_deadline = config.handshake_timeout.map(|limit| (Instant::now() + limit, limit));
+    }
+    // The send half goes to `send_command`; only this loop reads, so the read half needs no
+    // lock. A panic mid-read unwinds out of the worker with the stream, and the supervisor
+    // respawns it on a fresh connection rather than resuming a desynced one.
+    state_recv.write().unwrap().writer = Some(Arc::new(Mutex::new(writer)));
+    // One per connection, so a read timeout part way into a length prefix resumes it next
+    // time round; payloads are parsed as they stream in.
+    let mut frames = FrameReader::new(config.max_payload, config.endianness);
+    // the `<error>` in the last frame, if it had one: reported instead of a bare close
+    let mut server_error: Option<String> = None;
+
+    loop {
+        // Until the handshake is answered, only wait for what is left of its timeout.
+        if let Some((deadline, limit)) = handshake_deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() { return ProtocolError::HandshakeTimeout(limit); }
+            let _ = socket.set_read_timeout(Some(left));
+        }
+
+        let (r, g, b) = { let rguard = state_recv.read().unwrap(); ( rguard.request_colour.red, rguard.request_colour.green, rguard.request_colour.blue ) };
+
+        // Raw copy for debugging, kept whether or not the frame parses: only taken when asked for.
+        let mut raw = (config.keep_raw || config.dump_raw.is_some()).then(Vec::new);
+        let before = frames.pending();
+        let parsed = match frames.read_and_parse(&mut reader, socket.read_timeout().ok().flatten(), (r, g, b), raw.as_mut()) {
+            Ok(parsed) => parsed,
+            // Part of a length prefix came in before the timeout: the link is alive, carry on with it.
+            Err(ProtocolError::Timeout(_)) if frames.pending() > before => continue,
+            Err(e) => return match (e, handshake_deadline) {
+                (ProtocolError::Closed | ProtocolError::Io(_), _) if let Some(reported) = server_error => ProtocolError::ServerError(reported),
+                (ProtocolError::Timeout(_), Some((_, limit))) => ProtocolError::HandshakeTimeout(limit),
+                (e, _) => e,
+            },
+        };
+
+        if let Some(bytes) = raw {
+            let mut text = String::from_utf8_lossy(&bytes).into_owned();
+            if bytes.len() >= RAW_CAPTURE_MAX { text.push_str(&format!("\n<!-- truncated at {} bytes -->", RAW_CAPTURE_MAX)); }
+            if let Some(path) = config.dump_raw.as_ref() && let Err(e) = std::fs::write(path, &text) { eprintln!("Failed to dump frame to {}: {}", path.display(), e); }
+            if config.keep_raw { state_recv.write().unwrap().last_raw = Some(text); }
+        }
+
+        match parsed {
+            Ok(measurements) => {
+                let received_at = Instant::now();
+                // Handshake answered: from here on the link may idle per `read_timeout`.
+                if handshake_deadline.take().is_some() { let _ = socket.set_read_timeout(config.read_timeout); }
+
+                server_error = measurements.iter().rev().find_map(|m| m.error.clone());
+                if let Some(reported) = server_error.as_ref() { eprintln!("Server reported an error: {}", reported); }
+
+                let mut w = state_recv.write().unwrap();
+                w.connected = true;
+                w.disconnect_reason = None;
+
+                // Apply documents in order so the last one in the frame wins.
+                for meas in measurements {
+                    w.documents_received += 1;
+                    if meas.x.is_some() || meas.y.is_some() || meas.y_lum.is_some() {
+                        w.last_measurement = Some(meas.clone());
+                        w.measurements_received += 1;
+                        w.measuring = false;
+                        w.last_measurement_at = Some(received_at);
+                        if let Some(sent) = w.request_sent_at.take() {
+                            let rtt = received_at.saturating_duration_since(sent);
+                            w.last_rtt = Some(rtt);
+                            eprintln!("Reading {} arrived {:.1} ms after its request", w.measurements_received, rtt.as_secs_f64() * 1000.0);
+                        }
+                        if config.history > 0 {
+                            if w.history.len() == config.history { w.history.pop_front(); }
+                            w.history.push_back(meas.clone());
                         }
                     }
+                    // a new patch (or an explicit label) replaces the label; bare readings keep it
+                    if meas.label.is_some() || !meas.shapes.is_empty() {
+                        w.label = meas.label.clone();
+                    }
+                    w.fullscreen_field = meas.fullscreen;
+                    w.hide_markers = meas.hide_markers;
+                    w.hold = meas.hold;
+                    let (colour, shapes) = match meas.shapes.first() {
+                        Some(shape) => (shape.color(), meas.shapes),
+                        None => (ColorRGB::from_components_u16(meas.red, meas.green, meas.blue, 8), Vec::new()),
+                    };
+                    if colour != w.current_measure_colour || shapes != w.shapes { w.patch_seq += 1; }
+                    w.current_measure_colour = colour;
+                    w.shapes = shapes;
+                    w.focused_shape = None;
+                }
+            }
+            Err(e @ ParseError::UnsupportedVersion(_)) => return e.into(),
+            // one bad frame: keep what is on screen and wait for the next
+            Err(e) => { server_error = None; eprintln!("Skipping frame: {}", e); state_recv.write().unwrap().parse_errors += 1; }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// A connected pair over loopback: (client side, server side).
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    /// Poll `done` until it holds or two seconds have passed; whether it held.
+    fn wait_until(mut done: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !done() {
+            if Instant::now() > deadline { return false; }
+            thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+
+    /// A worker for `worker_with_mock`: no handshake, and one connection only.
+    fn mock_config() -> WorkerConfig {
+        WorkerConfig { init_command: None, reconnect: None, restart: None, ..WorkerConfig::default() }
+    }
+
+    /// A worker on `config` connected to a mock server: (state, server side).
+    /// Returns once the worker can send (any handshake is on its way).
+    fn worker_with_mock(config: WorkerConfig) -> (Arc<RwLock<SharedState>>, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let state = spawn_worker(&listener.local_addr().unwrap().to_string(), &config).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        wait_until(|| state.read().unwrap().writer.is_some());
+        (state, server)
+    }
+
+    #[test]
+    fn frame_reader_resumes_a_payload_after_a_timeout() {
+        let (mut client, mut server) = socket_pair();
+        let timeout = Duration::from_millis(50);
+        client.set_read_timeout(Some(timeout)).unwrap();
+        let payload = "<CS_RMC version=1><result><x>0.3127</x></result></CS_RMC>";
+
+        let sender = thread::spawn(move || {
+            server.write_all(&(payload.len() as i32).to_be_bytes()).unwrap();
+            server.write_all(&payload.as_bytes()[..10]).unwrap();
+            thread::sleep(Duration::from_millis(200));
+            server.write_all(&payload.as_bytes()[10..]).unwrap();
+            server
+        });
+
+        let mut frames = FrameReader::new(DEFAULT_MAX_PAYLOAD, Endianness::Big);
+        let mut timeouts = 0;
+        let frame = loop {
+            match frames.read_frame(&mut client, Some(timeout)) {
+                Ok(frame) => break frame,
+                Err(ProtocolError::Timeout(_)) => { assert!(frames.pending() >= 4, "timed out before the header"); timeouts += 1; }
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        };
+        assert!(timeouts > 0, "the pause never outlasted the read timeout");
+        assert_eq!(frame, payload);
+        assert_eq!(frames.pending(), 0);
+        drop(sender.join().unwrap());
+    }
+
+    #[test]
+    fn worker_keeps_a_link_whose_frame_stalls_past_the_read_timeout() {
+        // the pause outlasts one read timeout but not two: the frame has to survive the first
+        let (state, mut server) = worker_with_mock(WorkerConfig { read_timeout: Some(Duration::from_millis(100)), ..mock_config() });
+        let payload = "<CS_RMC version=1><result><x>0.3127</x><y>0.3290</y><Y>100</Y></result></CS_RMC>";
+        server.write_all(&(payload.len() as i32).to_be_bytes()).unwrap();
+        server.write_all(&payload.as_bytes()[..20]).unwrap();
+        thread::sleep(Duration::from_millis(150));
+        server.write_all(&payload.as_bytes()[20..]).unwrap();
+
+        wait_until(|| state.read().unwrap().measurements_received > 0);
+        let s = state.read().unwrap();
+        assert_eq!(s.measurements_received, 1);
+        assert_eq!(s.last_measurement.as_ref().and_then(|m| m.y_lum), Some(100.0));
+        assert!(s.connected, "dropped: {:?}", s.disconnect_reason);
+    }
+
+    /// A document with `count` rectangles, a few times `FRAME_CHUNK` for a few thousand.
+    fn many_rectangles(count: usize) -> String {
+        let rect = "<rectangle><color red=\"12\" green=\"34\" blue=\"56\"/><geometry cx=\"0.1\" cy=\"0.1\"/></rectangle>";
+        format!("<CS_RMC version=1><shapes>{}</shapes></CS_RMC>", rect.repeat(count))
+    }
+
+    #[test]
+    fn frames_are_parsed_a_chunk_at_a_time() {
+        /// Notes the largest read asked of it.
+        struct Spy<R> { inner: R, largest: usize }
+        impl<R: Read> Read for Spy<R> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.largest = self.largest.max(buf.len()); self.inner.read(buf) }
+        }
+        let xml = many_rectangles(4096);
+        assert!(xml.len() > 4 * FRAME_CHUNK);
+        let mut frame = Vec::new();
+        send_message_to_stream(&mut frame, &xml, Endianness::Big).unwrap();
+        let mut input = Spy { inner: std::io::Cursor::new(frame), largest: 0 };
+
+        let docs = FrameReader::new(DEFAULT_MAX_PAYLOAD, Endianness::Big).read_and_parse(&mut input, None, (0, 0, 0), None).unwrap().unwrap();
+        assert_eq!(docs[0].shapes.len(), 4096);
+        // a buffered read would have asked for the whole payload at once
+        assert!(input.largest <= FRAME_CHUNK, "read {} bytes at once", input.largest);
+    }
+
+    #[test]
+    fn worker_parses_a_frame_larger_than_its_read_buffer() {
+        let (state, mut server) = worker_with_mock(WorkerConfig { read_timeout: Some(Duration::from_millis(100)), ..mock_config() });
+        let xml = many_rectangles(4096);
+        server.write_all(&(xml.len() as i32).to_be_bytes()).unwrap();
+        // stall past the read timeout with a few chunks of the payload already in
+        let half = xml.len() / 2;
+        server.write_all(&xml.as_bytes()[..half]).unwrap();
+        thread::sleep(Duration::from_millis(150));
+        server.write_all(&xml.as_bytes()[half..]).unwrap();
+
+        wait_until(|| state.read().unwrap().documents_received > 0);
+        let s = state.read().unwrap();
+        assert_eq!(s.shapes.len(), 4096);
+        assert!(s.connected, "dropped: {:?}", s.disconnect_reason);
+        // no raw capture asked for, so nothing of the payload was kept
+        assert!(s.last_raw.is_none());
+    }
+
+    /// Frame `xml` the way the worker expects it (big-endian length prefix).
+    fn send_frame(stream: &mut TcpStream, xml: &str) {
+        send_message_to_stream(stream, xml, Endianness::Big).unwrap();
+    }
+
+    /// Accept a connection within `limit`, or None.
+    fn accept_within(listener: &TcpListener, limit: Duration) -> Option<TcpStream> {
+        listener.set_nonblocking(true).unwrap();
+        let deadline = Instant::now() + limit;
+        while Instant::now() < deadline {
+            if let Ok((stream, _)) = listener.accept() {
+                stream.set_nonblocking(false).unwrap();
+                return Some(stream);
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        None
+    }
+
+    #[test]
+    fn supervisor_respawns_a_worker_that_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = WorkerConfig { restart: Some(Duration::from_millis(10)), ..mock_config() };
+        let state = spawn_worker(&listener.local_addr().unwrap().to_string(), &config).unwrap();
+        let mut first = accept_within(&listener, Duration::from_secs(2)).expect("worker never connected");
+        // a version we don't speak is fatal: the worker returns with the error
+        send_frame(&mut first, "<CS_RMC version=99><result/></CS_RMC>");
+
+        let second = accept_within(&listener, Duration::from_secs(2));
+        assert!(second.is_some(), "worker was not restarted");
+        wait_until(|| state.read().unwrap().worker_restarts > 0);
+        let s = state.read().unwrap();
+        assert_eq!(s.worker_restarts, 1);
+        assert!(s.last_worker_error.as_deref().is_some_and(|e| e.contains("version 99")), "{:?}", s.last_worker_error);
+    }
+
+    #[test]
+    #[cfg_attr(not(debug_assertions), ignore = "panics through an overflow check")]
+    fn supervisor_restarts_a_worker_that_panicked_holding_the_state() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = WorkerConfig { restart: Some(Duration::from_millis(10)), ..mock_config() };
+        let state = spawn_worker(&listener.local_addr().unwrap().to_string(), &config).unwrap();
+        let mut first = accept_within(&listener, Duration::from_secs(2)).expect("worker never connected");
+        // counting the next document overflows, mid-update under the write lock
+        state.write().unwrap().documents_received = u64::MAX;
+        send_frame(&mut first, "<CS_RMC version=1><result/></CS_RMC>");
+
+        let mut second = accept_within(&listener, Duration::from_secs(2)).expect("worker was not restarted");
+        assert!(!state.is_poisoned());
+        state.write().unwrap().documents_received = 0;
+        send_frame(&mut second, "<CS_RMC version=1><result><x>0.3127</x><y>0.3290</y><Y>100</Y></result></CS_RMC>");
+        wait_until(|| state.read().unwrap().measurements_received > 0);
+        let s = state.read().unwrap();
+        assert_eq!(s.measurements_received, 1);
+        assert!(s.connected);
+        assert_eq!(s.worker_restarts, 1);
+        assert!(s.last_worker_error.as_deref().is_some_and(|e| e.contains("overflow")), "{:?}", s.last_worker_error);
+    }
+
+    #[test]
+    fn supervisor_leaves_a_cleanly_stopped_worker_alone() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = WorkerConfig { restart: Some(Duration::from_millis(10)), ..mock_config() };
+        let state = spawn_worker(&listener.local_addr().unwrap().to_string(), &config).unwrap();
+        // the link drops and reconnect is off: the worker stops as configured
+        drop(accept_within(&listener, Duration::from_secs(2)).expect("worker never connected"));
 
-                    Ok(None) => { let mut w = state_recv.write().unwrap(); w.connected = false; thread::sleep(Duration::from_millis(50)); }
+        assert!(accept_within(&listener, Duration::from_millis(300)).is_none(), "worker restarted after a clean stop");
+        assert_eq!(state.read().unwrap().worker_restarts, 0);
+    }
+
+    #[test]
+    #[ignore = "benchmark: cargo test --release streaming_parse -- --ignored --nocapture"]
+    fn streaming_parse_against_buffered_parse() {
+        // ~3 MB: one document with 32k rectangles
+        let xml = many_rectangles(32 * 1024);
+        let mut frame = Vec::new();
+        send_message_to_stream(&mut frame, &xml, Endianness::Big).unwrap();
+        const RUNS: u32 = 10;
+
+        let started = Instant::now();
+        let mut streamed = Vec::new();
+        for _ in 0..RUNS {
+            let mut input = std::io::Cursor::new(&frame);
+            streamed = FrameReader::new(DEFAULT_MAX_PAYLOAD, Endianness::Big).read_and_parse(&mut input, None, (0, 0, 0), None).unwrap().unwrap();
+        }
+        let streaming = started.elapsed() / RUNS;
+
+        let started = Instant::now();
+        let mut buffered = Vec::new();
+        for _ in 0..RUNS {
+            let mut input = std::io::Cursor::new(&frame);
+            let text = FrameReader::new(DEFAULT_MAX_PAYLOAD, Endianness::Big).read_frame(&mut input, None).unwrap();
+            buffered = parse_measurement_from_xml(&text, 0, 0, 0).unwrap();
+        }
+        let buffering = started.elapsed() / RUNS;
+
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].shapes.len(), 32 * 1024);
+        assert_eq!(streamed[0].shapes, buffered[0].shapes);
+        println!("{} byte frame: streaming {:?}, buffered {:?} per parse", frame.len(), streaming, buffering);
+    }
+
+    #[test]
+    fn parse_stats_count_unknown_elements_and_clamps() {
+        let xml = "<CS_RMC version=1><shapes><rectangle><color red=\"10\" green=\"20\" blue=\"30\"/><geometry cx=\"1.5\" cy=\"0.5\"/><sparkle/></rectangle></shapes></CS_RMC>";
+        let (results, stats) = parse_measurement_with_stats(xml, 0, 0, 0).unwrap();
+        assert_eq!(results[0].shapes.len(), 1);
+        assert_eq!(stats.unknown_elements, ["sparkle"]);
+        // the 1.5 width is over the normalized maximum and gets drawn clipped
+        assert_eq!(stats.clamps, 1);
+        assert_eq!((stats.elements, stats.attributes_skipped, stats.values_dropped, stats.fallbacks), (6, 0, 0, 0));
+    }
+
+    #[test]
+    fn concatenated_documents_each_keep_their_shapes() {
+        let doc = |red: u16, width: f32| format!(
+            "<CS_RMC version=1><shapes><rectangle><color red=\"{}\" green=\"0\" blue=\"0\"/><geometry cx=\"{}\" cy=\"0.5\"/></rectangle></shapes></CS_RMC>",
+            red, width
+        );
+        let xml = format!("{}\n{}", doc(10, 0.25), doc(20, 0.5));
+        let results = parse_measurement_from_xml(&xml, 0, 0, 0).unwrap();
+        assert_eq!(results.len(), 2);
+        let first = |r: &MeasurementResult| match &r.shapes[..] {
+            [ShapeInstruction::Rectangle(rect)] => (rect.color.red, rect.geometry.width),
+            other => panic!("expected one rectangle, got {:?}", other),
+        };
+        assert_eq!(first(&results[0]), (10, 0.25));
+        assert_eq!(first(&results[1]), (20, 0.5));
+    }
+
+    #[test]
+    fn parse_failures_have_their_own_variants() {
+        let doc = |body: &str| format!("<CS_RMC version=1>{}</CS_RMC>", body);
+        let parse = |xml: &str| parse_measurement_from_xml(xml, 0, 0, 0).map(|_| ());
+        assert!(matches!(parse(&doc("<shapes><rectangle><geometry cx=\"0.5\" cy=\"0.5\"/></rectangle></shapes>")), Err(ParseError::MissingColor)));
+        assert!(matches!(parse(&doc("<shapes><rectangle><color role=\"edge\" red=\"1\" green=\"2\" blue=\"3\"/></rectangle></shapes>")), Err(ParseError::BadColor(_))));
+        assert!(matches!(parse(&doc("<shapes><circle r=\"-1\"><color red=\"1\" green=\"2\" blue=\"3\"/></circle></shapes>")), Err(ParseError::BadGeometry(_))));
+        assert!(matches!(parse(&doc("<shapes></shapes><shapes></shapes>")), Err(ParseError::DuplicateCommand(c)) if c == "shapes"));
+        assert!(matches!(parse("<CS_RMC version=2><shapes/></CS_RMC>"), Err(ParseError::UnsupportedVersion(v)) if v == "2"));
+        assert!(matches!(parse(&doc("<shapes></result>")), Err(ParseError::Xml { .. })));
+    }
+
+    #[test]
+    fn framing_failures_have_their_own_variants() {
+        let frame = |header: [u8; 4], payload: &[u8]| [&header[..], payload].concat();
+        let read = |bytes: Vec<u8>| read_message_from_stream(&mut &bytes[..], 16, Endianness::Big, None);
+        assert_eq!(read(frame(2i32.to_be_bytes(), b"ok")).unwrap(), "ok");
+        assert!(matches!(read(frame(17i32.to_be_bytes(), &[b'x'; 17])), Err(ProtocolError::FrameTooLarge { len: 17, max: 16 })));
+        assert!(matches!(read(frame((-1i32).to_be_bytes(), b"")), Err(ProtocolError::Closed)));
+        // EOF between frames, and part way through one
+        assert!(matches!(read(Vec::new()), Err(ProtocolError::Closed)));
+        assert!(matches!(read(frame(4i32.to_be_bytes(), b"ab")), Err(ProtocolError::Closed)));
+        assert!(matches!(read(frame(2i32.to_be_bytes(), &[0xff, 0xfe])), Err(ProtocolError::InvalidUtf8)));
+
+        let (client, _server) = socket_pair();
+        let limit = Duration::from_millis(20);
+        client.set_read_timeout(Some(limit)).unwrap();
+        assert!(matches!(read_message_from_stream(&mut &client, 16, Endianness::Big, Some(limit)), Err(ProtocolError::Timeout(t)) if t == limit));
+    }
+
+    #[test]
+    fn only_an_unsupported_version_is_fatal() {
+        assert!(ProtocolError::Parse(ParseError::UnsupportedVersion("2".to_string())).is_fatal());
+        assert!(!ProtocolError::Parse(ParseError::MissingColor).is_fatal());
+        assert!(!ProtocolError::Closed.is_fatal());
+        assert!(!ProtocolError::Timeout(Duration::from_secs(1)).is_fatal());
+    }
+
+    #[test]
+    fn silent_server_fails_the_handshake() {
+        let limit = Duration::from_millis(100);
+        // accepts and reads the handshake, but never answers it
+        let (state, mut server) = worker_with_mock(WorkerConfig { handshake_timeout: Some(limit), init_command: Some("init profile".to_string()), ..mock_config() });
+        let mut init = [0u8; 12];
+        server.read_exact(&mut init).unwrap();
+        assert_eq!(&init, b"init profile");
+
+        wait_until(|| state.read().unwrap().disconnect_reason.is_some());
+        let s = state.read().unwrap();
+        assert_eq!(s.disconnect_reason.as_deref(), Some(ProtocolError::HandshakeTimeout(limit).to_string().as_str()));
+        assert!(!s.connected);
+    }
+
+    #[test]
+    fn full_size_rectangle_is_not_the_empty_document_fallback() {
+        let parse = |body: &str| {
+            let (mut results, stats) = parse_measurement_with_stats(&format!("<CS_RMC version=1>{}</CS_RMC>", body), 5, 6, 7).unwrap();
+            (results.remove(0), stats.fallbacks)
+        };
+        let (full, fallbacks) = parse("<shapes><rectangle><color red=\"10\" green=\"20\" blue=\"30\"/><geometry cx=\"1.0\" cy=\"1.0\"/></rectangle></shapes>");
+        assert_eq!(fallbacks, 0);
+        assert!(!full.fullscreen);
+        match &full.shapes[..] {
+            [ShapeInstruction::Rectangle(rect)] => {
+                assert_eq!((rect.geometry.width, rect.geometry.height, rect.geometry.units), (1.0, 1.0, GeometryUnits::Normalized));
+                assert_eq!((rect.color.red, rect.color.green, rect.color.blue), (10, 20, 30));
+            }
+            other => panic!("expected one rectangle, got {:?}", other),
+        }
+
+        // nothing asked for: no shapes, and the caller's colour stands in
+        let (empty, fallbacks) = parse("<shapes></shapes>");
+        assert_eq!(fallbacks, 1);
+        assert!(empty.shapes.is_empty() && !empty.fullscreen);
+        assert_eq!((empty.red, empty.green, empty.blue), (5, 6, 7));
+
+        // and an explicit flat field says so
+        let (flat, _) = parse("<fullscreen/><shapes><rectangle><color red=\"10\" green=\"20\" blue=\"30\"/></rectangle></shapes>");
+        assert!(flat.fullscreen);
+        assert_eq!(flat.shapes[0].color().red, 10);
+    }
+
+    #[test]
+    fn frames_round_trip_in_both_byte_orders() {
+        let xml = "<CS_RMC version=1><result><Y>1</Y></result></CS_RMC>";
+        for (endian, header) in [(Endianness::Big, (xml.len() as i32).to_be_bytes()), (Endianness::Little, (xml.len() as i32).to_le_bytes())] {
+            let mut wire = Vec::new();
+            send_message_to_stream(&mut wire, xml, endian).unwrap();
+            assert_eq!(wire[..4], header);
+            assert_eq!(read_message_from_stream(&mut &wire[..], 1024, endian, None).unwrap(), xml);
+        }
+        // read in the wrong order, the length is nonsense and the frame is refused
+        let mut wire = Vec::new();
+        send_message_to_stream(&mut wire, xml, Endianness::Little).unwrap();
+        assert!(matches!(read_message_from_stream(&mut &wire[..], 1024, Endianness::Big, None), Err(ProtocolError::FrameTooLarge { .. })));
+        assert_eq!("LE".parse(), Ok(Endianness::Little));
+        assert_eq!("big".parse(), Ok(Endianness::Big));
+    }
 
-                    Err(e) => { eprintln!("Error reading from stream: {}", e); let mut w = state_recv.write().unwrap(); w.connected = false; thread::sleep(Duration::from_millis(50)); }
+    #[test]
+    fn binding_to_a_foreign_local_address_fails_clearly() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut resolver = Resolver::new(&listener.local_addr().unwrap().to_string(), None);
+        let connect = |resolver: &mut Resolver, bind: &str| connect_with_timeout(resolver, Duration::from_secs(1), Some(bind.parse().unwrap()), None).map(|_| ());
+
+        // 192.0.2.1 is a documentation address no interface here has
+        let err = connect(&mut resolver, "192.0.2.1").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrNotAvailable);
+        assert!(err.to_string().starts_with("cannot bind to local address 192.0.2.1: "), "{}", err);
+        // an IPv6 local address can't reach an IPv4-only server
+        let err = connect(&mut resolver, "::1").unwrap_err();
+        assert!(err.to_string().starts_with("no IPv6 address found for 127.0.0.1:"), "{}", err);
+        // and a local address we do have connects as usual
+        connect(&mut resolver, "127.0.0.1").unwrap();
+    }
+
+    /// The one rectangle of a `<shapes>` document with `color` as its colour element.
+    fn rectangle_with(color: &str) -> Result<RectangleShape, ParseError> {
+        let xml = format!("<CS_RMC version=1><shapes><rectangle>{}</rectangle></shapes></CS_RMC>", color);
+        match parse_measurement_from_xml(&xml, 0, 0, 0)?.remove(0).shapes.remove(0) {
+            ShapeInstruction::Rectangle(rect) => Ok(rect),
+            other => panic!("expected a rectangle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn colorf_full_scale_is_the_max_code_at_any_depth() {
+        for (bits, max, half) in [(8, 255, 128), (10, 1023, 512), (16, 65535, 32768)] {
+            let rect = rectangle_with(&format!("<colorf red=\"1.0\" green=\"0.5\" blue=\"0\" bits=\"{}\"/>", bits)).unwrap();
+            assert_eq!(rect.color, ColorRGB { red: max, green: half, blue: 0, depth_bits: bits });
+            assert!(!rect.clamped.any());
+        }
+        // 8 bits unless told otherwise
+        assert_eq!(rectangle_with("<colorf red=\"1\" green=\"1\" blue=\"1\"/>").unwrap().color, ColorRGB { red: 255, green: 255, blue: 255, depth_bits: 8 });
+    }
+
+    #[test]
+    fn colorf_clamps_out_of_range_and_rejects_the_malformed() {
+        let rect = rectangle_with("<colorf red=\"1.2\" green=\"-0.1\" blue=\"0.25\" bits=\"10\"/>").unwrap();
+        assert_eq!((rect.color.red, rect.color.green, rect.color.blue), (1023, 0, 256));
+        assert_eq!(rect.clamped, Clamped { red: true, green: true, blue: false });
+
+        for bad in ["<colorf red=\"1\" green=\"1\"/>", "<colorf red=\"x\" green=\"1\" blue=\"1\"/>", "<colorf red=\"1\" green=\"1\" blue=\"1\" bits=\"17\"/>"] {
+            assert!(matches!(rectangle_with(bad), Err(ParseError::BadColor(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn colour_as_text_content() {
+        assert_eq!(rectangle_with("<color>128 64 32</color>").unwrap().color, ColorRGB { red: 128, green: 64, blue: 32, depth_bits: 8 });
+        assert_eq!(rectangle_with("<colex bits=\"10\"> 1023, 512,0 </colex>").unwrap().color, ColorRGB { red: 1023, green: 512, blue: 0, depth_bits: 10 });
+        let rect = rectangle_with("<color red=\"9\" green=\"9\" blue=\"9\"/><color role=\"border\">255 255 255</color>").unwrap();
+        assert_eq!((rect.color.red, rect.border.map(|b| b.red)), (9, Some(255)));
+        // attributes win over the text
+        assert_eq!(rectangle_with("<color red=\"1\" green=\"2\" blue=\"3\">4 5 6</color>").unwrap().color.red, 1);
+        // above the declared depth, or not three numbers
+        for bad in ["<color>256 0 0</color>", "<color bits=\"10\">1024 0 0</color>", "<color>1 2</color>", "<color>1 2 3 4</color>", "<color>red</color>"] {
+            assert!(matches!(rectangle_with(bad), Err(ParseError::BadColor(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn out_of_range_conversions_warn_with_the_clamped_channels() {
+        // sRGB red at twice its luminance: only red goes past full scale
+        let rect = rectangle_with("<xyY x=\"0.64\" y=\"0.33\" Y=\"0.4252\"/>").unwrap();
+        assert_eq!(rect.clamped, Clamped { red: true, green: false, blue: false });
+        assert_eq!(ShapeInstruction::Rectangle(rect).gamut_warning().as_deref(), Some("OUT OF GAMUT: red clamped, showing 255,0,0"));
+        // BT.2020 green drawn on an sRGB display: red and blue would be negative
+        let rect = rectangle_with("<xyY x=\"0.170\" y=\"0.797\" Y=\"0.3\"/>").unwrap();
+        assert_eq!(rect.clamped, Clamped { red: true, green: false, blue: true });
+        assert!(ShapeInstruction::Rectangle(rect).gamut_warning().unwrap().starts_with("OUT OF GAMUT: red, blue clamped, showing 0,"));
+        // a YCbCr triple with no RGB equivalent
+        let rect = rectangle_with("<ycbcr y=\"940\" cb=\"960\" cr=\"64\" bits=\"10\"/>").unwrap();
+        assert!(rect.clamped.any());
+
+        let (_, stats) = parse_measurement_with_stats("<CS_RMC version=1><shapes><rectangle><colorf red=\"1.5\" green=\"0.5\" blue=\"0.5\"/></rectangle></shapes></CS_RMC>", 0, 0, 0).unwrap();
+        assert_eq!(stats.clamps, 1);
+        // in gamut, no warning
+        let rect = rectangle_with("<xyY x=\"0.3127\" y=\"0.3290\" Y=\"0.5\"/>").unwrap();
+        assert_eq!(ShapeInstruction::Rectangle(rect).gamut_warning(), None);
+    }
+
+    #[test]
+    fn ycbcr_element_keeps_its_source() {
+        let rect = rectangle_with("<ycbcr y=\"940\" cb=\"512\" cr=\"512\" bits=\"10\" matrix=\"2020\"/>").unwrap();
+        assert_eq!(rect.color, ColorRGB { red: 1023, green: 1023, blue: 1023, depth_bits: 10 });
+        let source = rect.ycbcr.unwrap();
+        assert_eq!((source.y, source.matrix, source.full_range), (940, crate::ycbcr::YcbcrMatrix::Bt2020, false));
+        assert!(matches!(rectangle_with("<ycbcr y=\"1\" cb=\"2\"/>"), Err(ParseError::BadColor(_))));
+    }
+
+    #[test]
+    fn command_values_are_escaped_on_the_wire_and_parse_back() {
+        let (command, value) = ("load <profile> \"A&B\"", "R&D's \"grey\" <50%>");
+        let mut wire = Vec::new();
+        send_message_to_stream(&mut wire, &command_xml(command, Some(("profile", &[("name", value.to_string())]))), Endianness::Big).unwrap();
+        let sent = std::str::from_utf8(&wire[4..]).unwrap();
+        assert!(sent.contains("<command>load &lt;profile&gt; &quot;A&amp;B&quot;</command>"), "{}", sent);
+        assert!(sent.contains(" name=\"R&amp;D&apos;s &quot;grey&quot; &lt;50%&gt;\"/>"), "{}", sent);
+
+        // a standard XML reader gets the original strings back
+        let mut reader = Reader::from_str(sent);
+        let (mut text, mut name) = (None, None);
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Text(t) => text = Some(t.unescape().unwrap().into_owned()),
+                Event::Empty(e) if e.name().as_ref() == b"profile" => {
+                    name = Some(e.try_get_attribute("name").unwrap().unwrap().unescape_value().unwrap().into_owned());
                 }
+                Event::Eof => break,
+                _ => {}
             }
-        }); // end thread::spawn
-    } // end if let Some(s)
+        }
+        assert_eq!((text.as_deref(), name.as_deref()), (Some(command), Some(value)));
+    }
 
-    Ok(state)
+    #[test]
+    fn history_keeps_the_latest_readings_in_order() {
+        for (sent, depth) in [(5, 3), (2, 3), (3, 3)] {
+            let (state, mut server) = worker_with_mock(WorkerConfig { history: depth, ..mock_config() });
+            for n in 1..=sent {
+                send_frame(&mut server, &format!("<CS_RMC version=1><result><Y>{}</Y></result></CS_RMC>", n));
+            }
+            wait_until(|| state.read().unwrap().measurements_received == sent);
+
+            let ys: Vec<f64> = state.read().unwrap().history.iter().filter_map(|m| m.y_lum).collect();
+            let expected: Vec<f64> = (sent.saturating_sub(depth as u64) + 1..=sent).map(|n| n as f64).collect();
+            assert_eq!(ys, expected, "{} readings into a history of {}", sent, depth);
+        }
+    }
+
+    #[test]
+    fn hold_ms_reaches_the_shared_state() {
+        let parse = |body: &str| parse_measurement_with_stats(&format!("<CS_RMC version=1>{}</CS_RMC>", body), 0, 0, 0).unwrap();
+        let (results, _) = parse("<hold_ms>500</hold_ms><result><Y>1</Y></result>");
+        assert_eq!(results[0].hold, Some(Duration::from_millis(500)));
+        // not a number: no hold, and counted as a dropped value
+        let (results, stats) = parse("<hold_ms>soon</hold_ms>");
+        assert_eq!((results[0].hold, stats.values_dropped), (None, 1));
+
+        let (state, mut server) = worker_with_mock(mock_config());
+        let wait_for = |documents: u64| {
+            wait_until(|| state.read().unwrap().documents_received >= documents);
+            state.read().unwrap().hold
+        };
+        send_frame(&mut server, "<CS_RMC version=1><hold_ms>500</hold_ms><shapes><rectangle><color red=\"1\" green=\"2\" blue=\"3\"/></rectangle></shapes></CS_RMC>");
+        assert_eq!(wait_for(1), Some(Duration::from_millis(500)));
+        // the hold is per document: the next one without it falls back to the client's
+        send_frame(&mut server, "<CS_RMC version=1><shapes><rectangle><color red=\"4\" green=\"5\" blue=\"6\"/></rectangle></shapes></CS_RMC>");
+        assert_eq!(wait_for(2), None);
+    }
+
+    #[test]
+    fn a_stuck_send_times_out_without_holding_the_state() {
+        // the server never reads, so a big enough command fills the socket buffers and stalls
+        let (state, _server) = worker_with_mock(WorkerConfig { write_timeout: Some(Duration::from_millis(300)), ..mock_config() });
+
+        let sender = { let state = Arc::clone(&state); thread::spawn(move || send_command(&state, &"x".repeat(32 * 1024 * 1024))) };
+        thread::sleep(Duration::from_millis(100));
+        let started = Instant::now();
+        state.write().unwrap().label = Some("still free".into());
+        assert!(started.elapsed() < Duration::from_millis(100), "the send held the state for {:?}", started.elapsed());
+        let sent = sender.join().unwrap();
+        assert!(matches!(sent.as_ref().map_err(std::io::Error::kind), Err(std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)), "{:?}", sent);
+    }
+
+    #[test]
+    fn measuring_is_raised_by_a_request_and_cleared_by_its_reading() {
+        let (state, mut server) = worker_with_mock(mock_config());
+        let grey = ColorRGB { red: 128, green: 128, blue: 128, depth_bits: 8 };
+        assert!(!state.read().unwrap().measuring);
+
+        request_measurement(&state, grey).unwrap();
+        let request = read_message_from_stream(&mut server, 1024, Endianness::Big, None).unwrap();
+        assert_eq!(request, measure_request_xml(grey));
+        assert_eq!(state.read().unwrap().pending_request(), Some(grey));
+
+        send_frame(&mut server, "<CS_RMC version=1><result><x>0.3127</x><y>0.3290</y><Y>21</Y></result></CS_RMC>");
+        wait_until(|| !state.read().unwrap().measuring);
+        let s = state.read().unwrap();
+        assert!(!s.measuring);
+        assert_eq!(s.pending_request(), None);
+        assert_eq!(s.measurements_received, 1);
+    }
+
+    #[test]
+    fn round_trip_time_covers_a_slow_reply() {
+        let (state, mut server) = worker_with_mock(mock_config());
+        assert_eq!(state.read().unwrap().last_rtt, None);
+        let started = Instant::now();
+        request_measurement(&state, ColorRGB { red: 255, green: 255, blue: 255, depth_bits: 8 }).unwrap();
+        read_message_from_stream(&mut server, 1024, Endianness::Big, None).unwrap();
+        // the meter takes its time
+        thread::sleep(Duration::from_millis(150));
+        send_frame(&mut server, "<CS_RMC version=1><result><x>0.3127</x><y>0.3290</y><Y>100</Y></result></CS_RMC>");
+        wait_until(|| state.read().unwrap().last_rtt.is_some());
+        let s = state.read().unwrap();
+        let rtt = s.last_rtt.expect("no round trip time after the reading");
+        assert!(rtt >= Duration::from_millis(150) && rtt <= started.elapsed(), "{:?}", rtt);
+        assert_eq!(s.request_sent_at, None);
+    }
+
+    #[test]
+    fn resolver_reuses_its_lookup_until_refresh() {
+        let (a, b): (SocketAddr, SocketAddr) = ("10.0.0.1:20002".parse().unwrap(), "10.0.0.2:20002".parse().unwrap());
+        let unused = |_: &str| -> std::io::Result<Vec<SocketAddr>> { panic!("looked up again") };
+        let failing = |_: &str| -> std::io::Result<Vec<SocketAddr>> { Err(std::io::Error::other("no DNS")) };
+        let t0 = Instant::now();
+
+        let mut cached = Resolver::new("meter.local:20002", None);
+        assert!(cached.addrs_with(t0, failing).is_err());
+        assert_eq!(cached.addrs_with(t0, |name| { assert_eq!(name, "meter.local:20002"); Ok(vec![a]) }).unwrap(), [a]);
+        // reconnects, however much later, use the first lookup
+        assert_eq!(cached.addrs_with(t0 + Duration::from_secs(3600), unused).unwrap(), [a]);
+
+        let mut refreshed = Resolver::new("meter.local:20002", Some(Duration::from_secs(60)));
+        assert_eq!(refreshed.addrs_with(t0, |_| Ok(vec![a])).unwrap(), [a]);
+        assert_eq!(refreshed.addrs_with(t0 + Duration::from_secs(59), unused).unwrap(), [a]);
+        // stale: the lookup is redone and picks up the new mapping
+        assert_eq!(refreshed.addrs_with(t0 + Duration::from_secs(60), |_| Ok(vec![b])).unwrap(), [b]);
+        assert_eq!(refreshed.addrs_with(t0 + Duration::from_secs(61), unused).unwrap(), [b]);
+        // a failed refresh keeps the last addresses
+        assert_eq!(refreshed.addrs_with(t0 + Duration::from_secs(200), failing).unwrap(), [b]);
+        assert_eq!(refreshed.addrs_with(t0 + Duration::from_secs(201), unused).unwrap(), [b]);
+    }
+
+    #[test]
+    fn measure_once_returns_the_servers_reading() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let red = ColorRGB { red: 255, green: 0, blue: 0, depth_bits: 8 };
+        let server = thread::spawn(move || {
+            let (mut server, _) = listener.accept().unwrap();
+            let mut init = vec![0u8; INIT_PROFILE_XML.len()];
+            server.read_exact(&mut init).unwrap();
+            assert_eq!(init, INIT_PROFILE_XML.as_bytes());
+            send_frame(&mut server, "<CS_RMC version=1><status>profile ready</status></CS_RMC>");
+            let request = read_message_from_stream(&mut server, 1024, Endianness::Big, None).unwrap();
+            assert_eq!(request, measure_request_xml(red));
+            // frames without a reading are skipped
+            send_frame(&mut server, "<CS_RMC version=1><shapes><rectangle><color red=\"255\" green=\"0\" blue=\"0\"/></rectangle></shapes></CS_RMC>");
+            send_frame(&mut server, "<CS_RMC version=1><result>");
+            send_frame(&mut server, "<CS_RMC version=1><result><x>0.6400</x><y>0.3300</y><Y>21.26</Y></result></CS_RMC>");
+            // the client closes once it has its reading
+            server.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            matches!(server.read(&mut [0u8; 1]), Ok(0))
+        });
+
+        let m = measure_once(&addr, red, Duration::from_secs(2)).unwrap();
+        assert_eq!((m.x, m.y, m.y_lum), (Some(0.64), Some(0.33), Some(21.26)));
+        assert_eq!((m.red, m.green, m.blue), (255, 0, 0));
+        assert!(server.join().unwrap(), "connection left open");
+    }
+
+    #[test]
+    fn greys_across_depths_and_tolerances() {
+        let c = |red, green, blue, depth_bits| ColorRGB { red, green, blue, depth_bits };
+        assert!(c(128, 128, 128, 8).is_grey());
+        assert!(c(512, 512, 512, 10).is_grey());
+        assert!(c(65535, 65535, 65535, 16).is_grey());
+        assert!(c(0, 0, 0, 0).is_grey());
+        assert!(!c(128, 129, 128, 8).is_grey());
+        assert!(!c(1023, 1023, 1022, 10).is_grey());
+
+        // 1% of full scale: 2.55 8-bit codes, 10.23 10-bit codes, 655 16-bit codes
+        assert!(c(128, 130, 128, 8).is_near_grey(0.01));
+        assert!(!c(128, 131, 128, 8).is_near_grey(0.01));
+        assert!(c(500, 510, 505, 10).is_near_grey(0.01));
+        assert!(!c(500, 511, 505, 10).is_near_grey(0.01));
+        assert!(c(30000, 30655, 30000, 16).is_near_grey(0.01));
+        assert!(!c(30000, 30656, 30000, 16).is_near_grey(0.01));
+        // depth 0 is taken as 8 bits
+        assert!(c(10, 12, 10, 0).is_near_grey(0.01) && !c(10, 13, 10, 0).is_near_grey(0.01));
+    }
+
+    #[test]
+    fn profile_list_from_a_mock_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut server, _) = listener.accept().unwrap();
+            let request = read_message_from_stream(&mut server, 1024, Endianness::Big, None).unwrap();
+            // an unrelated frame first, which is skipped
+            send_frame(&mut server, "<CS_RMC version=1><status>busy</status></CS_RMC>");
+            send_frame(&mut server, "<CS_RMC version=1><profiles><profile>Rec.709 SDR</profile><profile name=\"P3 D65\"/>\
+                <profile> BT.2020 &amp; PQ </profile><profile/></profiles></CS_RMC>");
+            request
+        });
+        let config = WorkerConfig { init_command: None, ..WorkerConfig::default() };
+        assert_eq!(list_profiles(&addr, Duration::from_secs(2), &config).unwrap(), ["Rec.709 SDR", "P3 D65", "BT.2020 & PQ"]);
+        assert_eq!(server.join().unwrap(), command_xml(LIST_PROFILES_COMMAND, None));
+
+        assert_eq!(parse_profile_list("<CS_RMC version=1><profiles/></CS_RMC>").unwrap(), Some(Vec::new()));
+        assert_eq!(parse_profile_list("<CS_RMC version=1><result><Y>1</Y></result></CS_RMC>").unwrap(), None);
+        assert!(matches!(parse_profile_list("<CS_RMC version=2><profiles/></CS_RMC>"), Err(ParseError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn error_frame_then_close_is_the_disconnect_reason() {
+        let close_after = |frames: &[&str]| {
+            let (state, mut server) = worker_with_mock(mock_config());
+            for xml in frames { send_frame(&mut server, xml); }
+            drop(server);
+            wait_until(|| state.read().unwrap().disconnect_reason.is_some());
+            state.read().unwrap().disconnect_reason.clone()
+        };
+        assert_eq!(
+            close_after(&["<CS_RMC version=1><error>probe not found</error></CS_RMC>"]),
+            Some(ProtocolError::ServerError("probe not found".to_string()).to_string())
+        );
+        // an error followed by a normal frame was dealt with; the close is just a close
+        assert_eq!(
+            close_after(&["<CS_RMC version=1><error>busy</error></CS_RMC>", "<CS_RMC version=1><result><Y>1</Y></result></CS_RMC>"]),
+            Some(ProtocolError::Closed.to_string())
+        );
+    }
+
+    #[test]
+    fn v1_protocol_handshakes_and_frames_big_endian() {
+        let protocol: Protocol = "cs-rmc-v1".parse().unwrap();
+        assert_eq!(protocol, Protocol::CsRmcV1);
+        assert!("v2".parse::<Protocol>().is_err());
+        let config = WorkerConfig { reconnect: None, restart: None, ..WorkerConfig::for_protocol(protocol) };
+        assert_eq!((config.init_command.as_deref(), config.endianness), (Some(INIT_PROFILE_XML), Endianness::Big));
+
+        let (state, mut server) = worker_with_mock(config);
+        // the handshake goes out first, unframed
+        let mut init = vec![0u8; INIT_PROFILE_XML.len()];
+        server.read_exact(&mut init).unwrap();
+        assert_eq!(init, INIT_PROFILE_XML.as_bytes());
+        send_frame(&mut server, "<CS_RMC version=1><status>ok</status></CS_RMC>");
+        assert!(wait_until(|| state.read().unwrap().connected));
+
+        // then requests, each behind a big-endian length
+        let grey = ColorRGB { red: 64, green: 64, blue: 64, depth_bits: 8 };
+        request_measurement(&state, grey).unwrap();
+        let expected = measure_request_xml(grey);
+        let mut header = [0u8; 4];
+        server.read_exact(&mut header).unwrap();
+        assert_eq!(header, (expected.len() as u32).to_be_bytes());
+        let mut payload = vec![0u8; expected.len()];
+        server.read_exact(&mut payload).unwrap();
+        assert_eq!(payload, expected.as_bytes());
+    }
+
+    #[test]
+    fn last_patch_of_a_burst_is_the_one_displayed() {
+        const BURST: u16 = 50;
+        let (state, mut server) = worker_with_mock(mock_config());
+        let patch = |v: u16| format!("<CS_RMC version=1><shapes><rectangle><color red=\"{}\" green=\"{}\" blue=\"0\"/></rectangle></shapes></CS_RMC>", v, 255 - v);
+        // all in one go, faster than any renderer polls
+        let mut burst = Vec::new();
+        for v in 1..=BURST { send_message_to_stream(&mut burst, &patch(v), Endianness::Big).unwrap(); }
+        server.write_all(&burst).unwrap();
+
+        // the renderer's view, read once per "frame" until the worker goes quiet
+        let (mut seen, mut skipped) = (0u64, 0u64);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let shown = loop {
+            let (seq, colour, documents) = { let r = state.read().unwrap(); (r.patch_seq, r.shapes.first().map(ShapeInstruction::color), r.documents_received) };
+            if seq > seen + 1 { skipped += seq - seen - 1; }
+            seen = seq;
+            if documents == BURST as u64 || Instant::now() > deadline { break colour; }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(shown, Some(ColorRGB { red: BURST, green: 255 - BURST, blue: 0, depth_bits: 8 }));
+        assert_eq!(seen, BURST as u64);
+        assert!(skipped < BURST as u64);
+
+        // the same patch again is not a new one
+        send_frame(&mut server, &patch(BURST));
+        wait_until(|| state.read().unwrap().documents_received > BURST as u64);
+        assert_eq!(state.read().unwrap().patch_seq, BURST as u64);
+    }
+
+    #[test]
+    fn measuring_hint_is_set_by_its_frame_and_cleared_by_the_next() {
+        let (state, mut server) = worker_with_mock(mock_config());
+        let mut documents = 0;
+        let mut frame = |xml: &str| {
+            send_frame(&mut server, xml);
+            documents += 1;
+            wait_until(|| state.read().unwrap().documents_received >= documents);
+            state.read().unwrap().hide_markers
+        };
+        let patch = "<shapes><rectangle><color red=\"255\" green=\"255\" blue=\"255\"/></rectangle></shapes>";
+        assert!(!frame(&format!("<CS_RMC version=1>{}</CS_RMC>", patch)));
+        assert!(frame(&format!("<CS_RMC version=1><measuring/>{}</CS_RMC>", patch)));
+        assert!(!frame(&format!("<CS_RMC version=1>{}</CS_RMC>", patch)));
+        assert!(frame(&format!("<CS_RMC version=1>{}<hide_markers></hide_markers></CS_RMC>", patch)));
+        assert!(!frame("<CS_RMC version=1><result><Y>100</Y></result></CS_RMC>"));
+    }
+
+    #[test]
+    fn failed_request_does_not_leave_measuring_raised() {
+        let state = RwLock::new(SharedState::default());
+        let err = request_measurement(&state, ColorRGB::default()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotConnected);
+        let s = state.read().unwrap();
+        assert!(!s.measuring && s.request_sent_at.is_none());
+    }
+
+    #[test]
+    fn server_that_hangs_up_at_once_is_not_reported_connected() {
+        // accept and close before reading anything
+        let (state, server) = worker_with_mock(WorkerConfig { init_command: Some("init profile".to_string()), ..mock_config() });
+        drop(server);
+
+        wait_until(|| {
+            let s = state.read().unwrap();
+            assert!(!s.connected, "reported connected to a closed socket");
+            s.disconnect_reason.is_some()
+        });
+        // depending on timing the handshake write fails, or the first read finds the close
+        let s = state.read().unwrap();
+        let reason = s.disconnect_reason.as_deref().unwrap_or_default();
+        assert!(reason.starts_with("handshake not sent") || reason == ProtocolError::Closed.to_string() || reason.contains("reset"), "{:?}", reason);
+        assert!(s.writer.is_none());
+    }
+
+    #[test]
+    fn units_read_the_same_on_every_shape() {
+        let colour = "<color red=\"1\" green=\"2\" blue=\"3\"/>";
+        let units = |body: String| -> Result<GeometryUnits, ParseError> {
+            match parse_measurement_from_xml(&format!("<CS_RMC version=1><shapes>{}</shapes></CS_RMC>", body), 0, 0, 0)?.remove(0).shapes.remove(0) {
+                ShapeInstruction::Rectangle(rect) => Ok(rect.geometry.units),
+                ShapeInstruction::Circle(circle) => Ok(circle.geometry.units),
+                ShapeInstruction::Ellipse(ellipse) => Ok(ellipse.geometry.units),
+            }
+        };
+        for (value, expected) in [("px", GeometryUnits::Pixels), ("Pixels", GeometryUnits::Pixels), ("normalised", GeometryUnits::Normalized)] {
+            assert_eq!(units(format!("<rectangle>{}<geometry units=\"{}\" cx=\"1\" cy=\"1\"/></rectangle>", colour, value)).unwrap(), expected);
+            assert_eq!(units(format!("<circle units=\"{}\" r=\"1\">{}</circle>", value, colour)).unwrap(), expected);
+            assert_eq!(units(format!("<ellipse units=\"{}\" rx=\"1\" ry=\"1\">{}</ellipse>", value, colour)).unwrap(), expected);
+        }
+        assert!(matches!(units(format!("<circle units=\"inches\" r=\"1\">{}</circle>", colour)), Err(ParseError::BadGeometry(msg)) if msg.contains("inches")));
+    }
+
+    #[test]
+    fn rectangle_keeps_fill_and_border_colours_apart() {
+        let rgb = |c: ColorRGB| (c.red, c.green, c.blue);
+        let rect = rectangle_with("<color role=\"border\" red=\"255\" green=\"255\" blue=\"255\"/><color red=\"10\" green=\"20\" blue=\"30\"/>").unwrap();
+        assert_eq!((rgb(rect.color), rect.border.map(rgb)), ((10, 20, 30), Some((255, 255, 255))));
+        // either order, and an explicit fill role
+        let rect = rectangle_with("<color role=\"fill\" red=\"10\" green=\"20\" blue=\"30\"/><color role=\"Border\" red=\"1\" green=\"2\" blue=\"3\"/>").unwrap();
+        assert_eq!((rgb(rect.color), rect.border.map(rgb)), ((10, 20, 30), Some((1, 2, 3))));
+        // no role: fill only, as before
+        let rect = rectangle_with("<color red=\"10\" green=\"20\" blue=\"30\"/>").unwrap();
+        assert_eq!((rgb(rect.color), rect.border), ((10, 20, 30), None));
+        // a border alone has nothing to fill
+        assert!(matches!(rectangle_with("<color role=\"border\" red=\"1\" green=\"2\" blue=\"3\"/>"), Err(ParseError::MissingColor)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn worker_round_trips_frames_over_a_unix_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("colourspace-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let config = WorkerConfig { socket: Some(path.clone()), init_command: Some("init profile".to_string()), ..mock_config() };
+        // the address is only a name for logs
+        let state = spawn_worker("local", &config).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let mut init = [0u8; 12];
+        server.read_exact(&mut init).unwrap();
+        assert_eq!(&init, b"init profile");
+        send_message_to_stream(&mut server, "<CS_RMC version=1><result><x>0.3127</x><y>0.3290</y><Y>42</Y></result></CS_RMC>", Endianness::Big).unwrap();
+        wait_until(|| state.read().unwrap().measurements_received > 0);
+        assert_eq!(state.read().unwrap().last_measurement.as_ref().and_then(|m| m.y_lum), Some(42.0));
+
+        // and back the other way
+        let grey = ColorRGB { red: 64, green: 64, blue: 64, depth_bits: 8 };
+        request_measurement(&state, grey).unwrap();
+        assert_eq!(read_message_from_stream(&mut server, 1024, Endianness::Big, None).unwrap(), measure_request_xml(grey));
+    }
+
+    #[test]
+    fn element_names_match_in_any_case_but_readings_keep_theirs() {
+        let xml = "<Cs_Rmc version=1><Shapes><Rectangle><Color red=\"10\" green=\"20\" blue=\"30\"/><GEOMETRY cx=\"0.5\" cy=\"0.25\"/></Rectangle></Shapes>\
+                   <Result><x>0.3127</x><y>0.3290</y><Y>80</Y></Result></Cs_Rmc>";
+        let results = parse_measurement_from_xml(xml, 0, 0, 0).unwrap();
+        let m = &results[0];
+        match &m.shapes[..] {
+            [ShapeInstruction::Rectangle(rect)] => {
+                assert_eq!((rect.color.red, rect.color.green, rect.color.blue), (10, 20, 30));
+                assert_eq!((rect.geometry.width, rect.geometry.height), (0.5, 0.25));
+            }
+            other => panic!("expected one rectangle, got {:?}", other),
+        }
+        // y is chromaticity and Y luminance: never folded together
+        assert_eq!((m.x, m.y, m.y_lum), (Some(0.3127), Some(0.3290), Some(80.0)));
+    }
+
+    #[test]
+    fn result_is_found_under_any_wrapper() {
+        for body in [
+            "<result><x>0.3127</x><y>0.3290</y><Y>55</Y></result>",
+            "<response><result><x>0.3127</x><y>0.3290</y><Y>55</Y></result></response>",
+            "<response><reading><result><x>0.3127</x><y>0.3290</y><Y>55</Y></result></reading></response>",
+        ] {
+            let results = parse_measurement_from_xml(&format!("<CS_RMC version=1>{}</CS_RMC>", body), 0, 0, 0).unwrap();
+            let m = &results[0];
+            assert_eq!((m.x, m.y, m.y_lum), (Some(0.3127), Some(0.3290), Some(55.0)), "{}", body);
+        }
+        // a reading-named element outside any result is not a reading
+        let results = parse_measurement_from_xml("<CS_RMC version=1><response><Y>55</Y></response></CS_RMC>", 0, 0, 0).unwrap();
+        assert_eq!(results[0].y_lum, None);
+    }
+
+    #[test]
+    fn without_a_handshake_nothing_is_written_before_the_server_speaks() {
+        let (state, mut server) = worker_with_mock(mock_config());
+        server.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        // nothing arrives within the server's read timeout
+        fn quiet(server: &mut TcpStream) -> bool {
+            matches!(server.read(&mut [0u8; 1]), Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut))
+        }
+        assert!(quiet(&mut server), "client wrote before the server spoke");
+
+        // the worker is reading all the while: the server's first frame gets through
+        send_frame(&mut server, "<CS_RMC version=1><result><Y>3</Y></result></CS_RMC>");
+        wait_until(|| state.read().unwrap().measurements_received > 0);
+        assert!(state.read().unwrap().connected);
+        assert!(quiet(&mut server), "client answered a reading nobody asked it to");
+        // and the default does send one
+        assert_eq!(WorkerConfig::default().init_command.as_deref(), Some(INIT_PROFILE_XML));
+    }
 }
@@ -1,40 +1,881 @@
 use argh::FromArgs;
-use tinyfiledialogs as tfd;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, PoisonError,
 };
 use std::time::{Duration, Instant};
 use std::thread::{sleep, spawn};
 use std::error::Error;
 
+mod beep;
+mod bridge;
+mod cct;
+mod click;
+mod compare;
+mod control;
+mod config;
+mod delta_e;
+mod dialog;
+mod draw;
+mod flash;
+mod hook;
 mod lan;
-use lan::{ColorRGB, ShapeInstruction, spawn_worker};
+mod metrics;
+mod overlay;
+mod pacing;
+mod pattern;
+mod shm;
+mod sweep;
+#[cfg(feature = "tls")]
+mod tls;
+mod view;
+mod xyz;
+mod ycbcr;
+use click::{Click, ClickTracker, SingleClickAction};
+use config::{Config, WindowGeometry};
+use control::ControlCommand;
+use draw::{Backdrop, ChannelView, MeasureTarget, cell_to_pixels, draw_bars, draw_idle, draw_shapes, fill_cell, fill_field, region_to_pixels, select_measure_colour, select_measure_shape, shape_rect};
+use lan::{ColorRGB, Endianness, FrameSnapshot, MeasurementResult, PatchRegion, Protocol, ShapeInstruction, parse_measurement_with_stats, spawn_worker, TlsOptions, WorkerConfig};
+use pattern::{Grid, Marker, Pattern};
+use sweep::{ExportFormat, OnTimeout, Sweep, SweepAction, SweepKind, UniformityGrid};
+use view::{CloseAction, DisconnectMode, PatchView};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let sdl_context = sdl2::init()?;
-    let video = sdl_context.video()?;
+    // ---------------------------------------------------------------------
+    // ARG PARSING (before SDL so offline tools never open a window)
+    // ---------------------------------------------------------------------
+    let args: Args = argh::from_env();
+
+    if args.capabilities {
+        print_capabilities();
+        return Ok(());
+    }
+
+    if let Some(path) = args.parse_file.as_ref() {
+        let xml = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let (results, stats) = parse_measurement_with_stats(&xml, 0, 0, 0)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        print_measurements(&results);
+        println!(
+            "parser: {} element(s), {} attribute(s) skipped, {} value(s) dropped, {} clamp(s), {} default(s) filled in",
+            stats.elements, stats.attributes_skipped, stats.values_dropped, stats.clamps, stats.fallbacks
+        );
+        if !stats.unknown_elements.is_empty() {
+            println!("  unknown elements: {}", stats.unknown_elements.join(", "));
+        }
+        return Ok(());
+    }
+
+    if !args.compare.is_empty() {
+        let [a, b] = &args.compare[..] else {
+            return Err(format!("--compare needs exactly two runs (got {})", args.compare.len()).into());
+        };
+        let deltas = compare::compare(&compare::load_run(a)?, &compare::load_run(b)?, args.delta_e, args.reference_white_y);
+        println!("{} -> {}", a.display(), b.display());
+        print!("{}", compare::report(&deltas, args.delta_e));
+        return Ok(());
+    }
+
+    let mut config = Config::load();
+
+    // Precedence: CLI positional > COLOURSPACE_REMOTE > config file > dialog.
+    let mut maybe_remote = args
+    .remote
+    .clone()
+    .or_else(|| std::env::var(REMOTE_ENV_VAR).ok().filter(|r| !r.trim().is_empty()))
+    .or_else(|| config.remote.clone());
+
+    // Connection settings shared by --ping and the worker.
+    let tls = (args.tls || args.ca.is_some() || args.insecure).then(|| TlsOptions { ca: args.ca.clone(), insecure: args.insecure });
+    if let Some(path) = args.socket.as_ref() {
+        if tls.is_some() || args.bind.is_some() {
+            return Err("--socket can't be combined with --tls, --ca, --insecure or --bind".into());
+        }
+        // the socket path stands in for the address (no dialog, and it names the link in logs)
+        maybe_remote = Some(path.display().to_string());
+    }
+    // host:port for TCP; a --socket path is used as is
+    let link_name = |remote: &str| if args.socket.is_some() { remote.to_string() } else { add_default_port(remote) };
+    let worker_config = WorkerConfig {
+        endianness: args.frame_endian.unwrap_or(args.protocol.endianness()),
+        bind: args.bind,
+        tls,
+        socket: args.socket.clone(),
+        re_resolve: args.re_resolve_secs.map(Duration::from_secs),
+        init_command: if args.no_handshake { None } else { args.protocol.handshake() },
+        keep_raw: args.keep_raw,
+        dump_raw: args.dump_raw.clone(),
+        history: args.history,
+        restart: (args.worker_restart_ms > 0).then(|| Duration::from_millis(args.worker_restart_ms)),
+        ..WorkerConfig::for_protocol(args.protocol)
+    };
+
+    if args.ping {
+        const PING_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+        const PING_FRAME_TIMEOUT: Duration = Duration::from_secs(3);
+
+        let Some(remote) = maybe_remote.as_deref() else {
+            eprintln!("FAIL: --ping needs a server address (argument, {} or config file)", REMOTE_ENV_VAR);
+            std::process::exit(2);
+        };
+        let remote_addr = link_name(remote);
+        let ping_config = WorkerConfig { connect_timeout: PING_CONNECT_TIMEOUT, ..worker_config.clone() };
+        match lan::ping(&remote_addr, &ping_config, PING_FRAME_TIMEOUT) {
+            Ok((connect, rtt)) => {
+                println!(
+                    "OK {}: connected in {:.1}ms, handshake round trip {:.1}ms",
+                    remote_addr,
+                    connect.as_secs_f64() * 1000.0,
+                    rtt.as_secs_f64() * 1000.0
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("FAIL {}: {}", remote_addr, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(frames) = args.hexdump {
+        const HEXDUMP_FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+        let Some(remote) = maybe_remote.as_deref() else {
+            eprintln!("--hexdump needs a server address (argument, {} or config file)", REMOTE_ENV_VAR);
+            std::process::exit(2);
+        };
+        let remote_addr = link_name(remote);
+        let dumped = lan::hexdump(&remote_addr, &worker_config, frames, HEXDUMP_FRAME_TIMEOUT, &mut std::io::stdout().lock());
+        dumped.map_err(|e| format!("{}: {}", remote_addr, e))?;
+        return Ok(());
+    }
+
+    if let Some((r, g, b)) = args.measure_once {
+        const MEASURE_ONCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+        let Some(remote) = maybe_remote.as_deref() else {
+            eprintln!("--measure-once needs a server address (argument, {} or config file)", REMOTE_ENV_VAR);
+            std::process::exit(2);
+        };
+        let remote_addr = link_name(remote);
+        let colour = ColorRGB::from_components_u16(r, g, b, args.measure_bits);
+        let timeout = args.measure_timeout_ms.map_or(MEASURE_ONCE_TIMEOUT, Duration::from_millis);
+        let reading = lan::measure_once_with(&remote_addr, colour, timeout, &worker_config).map_err(|e| format!("{}: {}", remote_addr, e))?;
+        print_measurements(std::slice::from_ref(&reading));
+        return Ok(());
+    }
+
+    if args.list_profiles {
+        const LIST_PROFILES_TIMEOUT: Duration = Duration::from_secs(10);
+
+        let Some(remote) = maybe_remote.as_deref() else {
+            eprintln!("--list-profiles needs a server address (argument, {} or config file)", REMOTE_ENV_VAR);
+            std::process::exit(2);
+        };
+        let remote_addr = link_name(remote);
+        let profiles = lan::list_profiles(&remote_addr, LIST_PROFILES_TIMEOUT, &worker_config).map_err(|e| format!("{}: {}", remote_addr, e))?;
+        if profiles.is_empty() {
+            eprintln!("{} reports no profiles", remote_addr);
+        }
+        for name in profiles {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    if let Some(port) = args.bridge {
+        let Some(remote) = maybe_remote.as_deref() else {
+            eprintln!("--bridge needs a server address (argument, {} or config file)", REMOTE_ENV_VAR);
+            std::process::exit(2);
+        };
+        let remote_addr = link_name(remote);
+        let listen = std::net::SocketAddr::new(args.bridge_bind, port);
+        eprintln!("Relaying {} to display clients on {}", remote_addr, listen);
+        bridge::run(&remote_addr, listen, &worker_config).map_err(|e| format!("bridge port {}: {}", listen, e))?;
+        return Ok(());
+    }
+
+    // Desktop dialogs if the system can show them, else prompts and errors on the terminal.
+    if !dialog::init(args.no_dialogs) && !args.no_dialogs {
+        eprintln!("No dialog program found (zenity, kdialog, ...), using the terminal instead");
+    }
+
+    // Flashing and --sync-divisor must change content on refresh boundaries, so present waits for vsync.
+    if args.flash.is_some() || args.sync_divisor.is_some() {
+        sdl2::hint::set("SDL_RENDER_VSYNC", "1");
+    }
+
+    // A broken display stack is the usual first-run failure: say so in a dialog rather
+    // than exiting with a bare SDL error.
+    let sdl_context = sdl2::init().unwrap_or_else(|e| display_init_failed("SDL could not be initialised", &e));
+    let video = sdl_context.video().unwrap_or_else(|e| display_init_failed("The SDL video subsystem could not be started", &e));
 
     const DEFAULT_W: u32 = 1280;
     const DEFAULT_H: u32 = 720;
+    // Below this patches get too small to measure (geometry clamps to 1px).
+    // No maximum: desktop fullscreen must be free to use the whole display.
+    const MIN_W: u32 = 320;
+    const MIN_H: u32 = 240;
+
+    // Start windowed; fullscreen via double-click, or restored from the last run below.
+    // Try the requested backend first, then fall back down the list.
+    let mut canvas = None;
+    for renderer in args.renderer.fallbacks() {
+        match create_canvas(&video, renderer, DEFAULT_W, DEFAULT_H) {
+            Ok(c) => {
+                if renderer != args.renderer {
+                    eprintln!("Using the {:?} renderer instead", renderer);
+                }
+                canvas = Some((c, renderer));
+                break;
+            }
+            Err(e) => eprintln!("{:?} renderer failed to initialize: {}", renderer, e),
+        }
+    }
+    let (mut canvas, active_renderer) = canvas.unwrap_or_else(|| {
+        let tried = args.renderer.fallbacks().iter().map(|r| format!("{:?}", r).to_lowercase()).collect::<Vec<_>>().join(", ");
+        display_init_failed("No renderer could be initialised", &format!("tried {} (see the terminal for each error)", tried))
+    });
+    canvas.window_mut().set_minimum_size(MIN_W, MIN_H)?;
+    if let Some(index) = args.display {
+        move_to_display(&mut canvas, &video, index).map_err(|e| format!("--display {}: {}", index, e))?;
+    }
+    // Back where the operator left it last time, unless --display says otherwise.
+    let restore_fullscreen = match config.window.filter(|_| args.display.is_none() && !args.no_restore_window) {
+        Some(saved) => match restore_window(&mut canvas, &video, saved, MIN_W, MIN_H) {
+            Ok(()) => saved.fullscreen,
+            Err(e) => {
+                eprintln!("Not restoring the last window position ({}), opening centred", e);
+                false
+            }
+        },
+        None => false,
+    };
+    if args.native_fullscreen {
+        // fail now rather than on the first double-click
+        let index = canvas.window().display_index()?;
+        let mode = native_mode(&video, index).map_err(|e| format!("--native-fullscreen: {}", e))?;
+        eprintln!("Fullscreen will switch display {} to {}x{} @ {} Hz", index, mode.w, mode.h, mode.refresh_rate);
+    }
+
+    // Extra output-only windows showing the same patch on other displays. Input stays
+    // with the main window (keys work from any of them).
+    let mut mirrors = Vec::new();
+    for &index in args.mirror_displays.iter().flatten() {
+        let mirror = create_mirror(&video, active_renderer, index).map_err(|e| format!("--mirror-displays: display {}: {}", index, e))?;
+        eprintln!("Mirroring patches to display {}", index);
+        mirrors.push((index, mirror));
+    }
+
+    if args.print_caps {
+        print_caps(&video, &canvas)?;
+        return Ok(());
+    }
 
-    // Always start windowed; fullscreen only via double-click
-    let window = video
-    .window("Calibration Client Linux", DEFAULT_W, DEFAULT_H)
-    .position_centered()
-    .vulkan()
-    .resizable()
-    .allow_highdpi()
-    .build()?;
+    /// Environment variable consulted for the server address when none is given on the CLI.
+    const REMOTE_ENV_VAR: &str = "COLOURSPACE_REMOTE";
 
     #[derive(FromArgs)]
     /// Colourspace viewer
+    #[argh(note = "The server address is taken from, in order: the positional argument, the \
+COLOURSPACE_REMOTE environment variable, `remote = ...` in ~/.config/colourspace/config, \
+and finally a startup dialog (pre-filled with the server that last connected).")]
     struct Args {
         /// remote server host[:port] (positional). Optional.
         #[argh(positional)]
         remote: Option<String>,
+
+        /// restore the patch region drawn with the mouse from the config file, and save new ones
+        #[argh(switch)]
+        persist_region: bool,
+
+        /// open at the default size and position instead of where the window was at the last exit (and don't save it)
+        #[argh(switch)]
+        no_restore_window: bool,
+
+        /// parse a captured XML payload file, print the result and what the parser skipped or
+        /// adjusted, and exit (no network or window)
+        #[argh(option)]
+        parse_file: Option<std::path::PathBuf>,
+
+        /// compare two saved sweep runs: give it twice (--compare before.csv --compare after.csv) to print
+        /// per-patch delta E (--delta-e, against --reference-white-y) and delta Y from the first to the second, then exit
+        #[argh(option)]
+        compare: Vec<std::path::PathBuf>,
+
+        /// name this run: sweep results go to <name>_<default file> (e.g. before_gamma_sweep.csv) unless --csv is given
+        #[argh(option)]
+        run_name: Option<String>,
+
+        /// check the server is reachable and answers the handshake, print the round trip and exit
+        #[argh(switch)]
+        ping: bool,
+
+        /// connect, print the raw bytes of the next N frames (length prefix included) as a hex dump and exit
+        #[argh(option)]
+        hexdump: Option<usize>,
+
+        /// measure this colour r,g,b (code values at --measure-bits) once, print the reading and exit (no window)
+        #[argh(option, from_str_fn(parse_rgb16))]
+        measure_once: Option<(u16, u16, u16)>,
+
+        /// ask the server which calibration profiles it has, print one per line and exit (no window)
+        #[argh(switch)]
+        list_profiles: bool,
+
+        /// double-click window in ms (default 400, or `double_click_ms` in the config file)
+        #[argh(option)]
+        double_click_ms: Option<u64>,
+
+        /// what a single left click does once no double-click follows: none (default) or overlay
+        #[argh(option, default = "SingleClickAction::None")]
+        click_action: SingleClickAction,
+
+        /// print video driver, displays, renderer and logical vs drawable (HiDPI) size, then exit
+        #[argh(switch)]
+        print_caps: bool,
+
+        /// print what this build supports (version, shapes, bit depths, renderers, features) as JSON, then exit
+        #[argh(switch)]
+        capabilities: bool,
+
+        /// run a client-driven measurement sweep (gamma) and write the results as CSV
+        #[argh(option)]
+        sweep: Option<SweepKind>,
+
+        /// uniformity scan: show a white patch in each cell of an RxC grid (e.g. 3x3) in turn,
+        /// measure each and write row/col/Y as CSV
+        #[argh(option)]
+        uniformity: Option<UniformityGrid>,
+
+        /// measure the patches listed in this CSV (red,green,blue[,bits] per line, bits default
+        /// --measure-bits) in order and write the readings keyed by input line
+        #[argh(option)]
+        patch_list: Option<std::path::PathBuf>,
+
+        /// measure the patches of this ArgyllCMS .ti1 target (device RGB at --measure-bits) in order
+        /// and write the readings as a .ti3
+        #[argh(option)]
+        ti1: Option<std::path::PathBuf>,
+
+        /// number of patches in a sweep (default 21, i.e. 5% steps)
+        #[argh(option, default = "21")]
+        steps: usize,
+
+        /// how long each sweep patch is shown before its reading is requested, in ms (default 500)
+        #[argh(option, default = "500")]
+        settle_ms: u64,
+
+        /// cross-fade between sweep patches over this many ms instead of cutting (default 0); the
+        /// settle time and the reading only start once the fade is over
+        #[argh(option, default = "0")]
+        crossfade_ms: u64,
+
+        /// readings to take and average per sweep patch (default 1)
+        #[argh(option, default = "1")]
+        average: usize,
+
+        /// drop the highest and lowest Y of each patch's readings and report the median of the rest
+        #[argh(switch)]
+        reject_outliers: bool,
+
+        /// give up on a sweep patch's reading after this many ms (default: wait for ever)
+        #[argh(option)]
+        measure_timeout_ms: Option<u64>,
+
+        /// what a sweep does when a reading times out: skip (default, record the patch as timed out) or abort
+        #[argh(option, default = "OnTimeout::Skip")]
+        on_timeout: OnTimeout,
+
+        /// where to write sweep results (default: gamma_sweep.csv, uniformity_scan.csv, patch_list_results.csv or ti1_results.ti3)
+        #[argh(option)]
+        csv: Option<std::path::PathBuf>,
+
+        /// write sweep results for another tool instead of the sweep's own CSV: calman or lightspace
+        /// (default file: calman_export.csv or lightspace_export.csv)
+        #[argh(option)]
+        export: Option<ExportFormat>,
+
+        /// continue a sweep from this progress file (saved after every patch, by default as <csv>.resume);
+        /// a missing file starts the requested sweep afresh and saves there
+        #[argh(option)]
+        resume: Option<std::path::PathBuf>,
+
+        /// never show desktop dialogs: ask for the server address on stdin and print errors to stderr
+        /// (the default when no dialog program such as zenity or kdialog is installed)
+        #[argh(switch)]
+        no_dialogs: bool,
+
+        /// pre-fill the server address dialog from the clipboard
+        #[argh(switch)]
+        from_clipboard: bool,
+
+        /// show an offline test pattern (grid, smpte or ebu colour bars) instead of connecting to a server
+        #[argh(option)]
+        pattern: Option<Pattern>,
+
+        /// colour bar level for --pattern smpte/ebu: 75 or 100 (% of full scale, default 75)
+        #[argh(option, default = "75")]
+        bar_level: u8,
+
+        /// grid rows, for --pattern grid and the L key overlay (default 9)
+        #[argh(option, default = "9")]
+        grid_rows: u32,
+
+        /// grid columns (default 16)
+        #[argh(option, default = "16")]
+        grid_cols: u32,
+
+        /// grid line thickness in drawable pixels (default 1)
+        #[argh(option, default = "1")]
+        grid_thickness: u32,
+
+        /// grid line colour as 8-bit r,g,b (default 255,255,255)
+        #[argh(option, default = "Color::RGB(255, 255, 255)", from_str_fn(pattern::parse_rgb))]
+        grid_colour: Color,
+
+        /// shift every patch off centre by dx,dy (fractions of the window/region, + is right/down),
+        /// e.g. to keep a stuck pixel out of the measured area; patches stay fully on screen
+        #[argh(option, default = "(0.0, 0.0)", from_str_fn(parse_offset))]
+        patch_offset: (f32, f32),
+
+        /// probe alignment marker (X key) size: crosshair arm length in drawable pixels (default 40)
+        #[argh(option, default = "40")]
+        marker_size: u32,
+
+        /// alignment marker colour as 8-bit r,g,b (default 255,0,255)
+        #[argh(option, default = "Color::RGB(255, 0, 255)", from_str_fn(pattern::parse_rgb))]
+        marker_colour: Color,
+
+        /// which patch's colour is tracked when several are drawn: smallest (default), largest or first
+        #[argh(option, default = "MeasureTarget::Smallest")]
+        measure_target: MeasureTarget,
+
+        /// accept remote-control commands (colour, clear, fullscreen, screenshot, quit) on this TCP port
+        #[argh(option)]
+        control_port: Option<u16>,
+
+        /// read the same commands from stdin, one per line (set R G B, fullscreen, shot, quit, ...), e.g. piped from a script
+        #[argh(switch)]
+        stdin_commands: bool,
+
+        /// keep each server patch on screen at least this many ms before showing the next (default 0; a frame's <hold_ms> overrides it)
+        #[argh(option, default = "0")]
+        min_hold_ms: u64,
+
+        /// colour r,g,b around the patches inside the region, e.g. for a window pattern on grey (default black)
+        #[argh(option, default = "Color::RGB(0, 0, 0)", from_str_fn(pattern::parse_rgb))]
+        patch_surround: Color,
+
+        /// colour r,g,b shown (with a "Connecting..." note) until the server connects (default 64,64,64, dark grey)
+        #[argh(option, default = "Color::RGB(64, 64, 64)", from_str_fn(pattern::parse_rgb))]
+        idle_colour: Color,
+
+        /// colour r,g,b of the canvas outside the region, i.e. letterbox bars (default black)
+        #[argh(option, default = "Color::RGB(0, 0, 0)", from_str_fn(pattern::parse_rgb))]
+        canvas_clear: Color,
+
+        /// make double-click fullscreen switch the display to its native mode (exclusive fullscreen) instead of covering the desktop at its current resolution, for 1:1 pixel patches
+        #[argh(switch)]
+        native_fullscreen: bool,
+
+        /// open the window on this display (numbers as in --print-caps); fullscreen is kept there across resolution changes
+        #[argh(option)]
+        display: Option<i32>,
+
+        /// also show the patches full-screen on these displays, e.g. 1,2 (display numbers as in --print-caps)
+        #[argh(option, from_str_fn(parse_displays))]
+        mirror_displays: Option<Vec<i32>>,
+
+        /// run headless as a relay: display clients connecting on this port are passed through to the server (no window)
+        #[argh(option)]
+        bridge: Option<u16>,
+
+        /// address the --bridge port binds to (default 0.0.0.0, since the display is usually on another machine)
+        #[argh(option, default = "std::net::IpAddr::from([0, 0, 0, 0])")]
+        bridge_bind: std::net::IpAddr,
+
+        /// address the control port binds to (default 127.0.0.1, i.e. local clients only)
+        #[argh(option, default = "std::net::IpAddr::from([127, 0, 0, 1])")]
+        control_bind: std::net::IpAddr,
+
+        /// how many recent readings the control port's `history` command can return (default 100, 0 = off)
+        #[argh(option, default = "100")]
+        history: usize,
+
+        /// server dialect: handshake, framing and protocol version (default cs-rmc-v1, ColourSpace)
+        #[argh(option, default = "Protocol::CsRmcV1")]
+        protocol: Protocol,
+
+        /// byte order of the frame length prefix, big or little (default: the --protocol's, big for ColourSpace)
+        #[argh(option)]
+        frame_endian: Option<Endianness>,
+
+        /// play a short beep whenever the displayed patch colour changes
+        #[argh(switch)]
+        beep_on_change: bool,
+
+        /// shell command to run right after each new patch is presented (colour in
+        /// COLOURSPACE_RED/_GREEN/_BLUE/_BITS), e.g. to trigger an external meter
+        #[argh(option)]
+        on_present: Option<String>,
+
+        /// publish the displayed patch and link state in shared memory /dev/shm/NAME (or a path) for
+        /// another process to mmap; 32-byte layout described in shm.rs
+        #[argh(option)]
+        shm: Option<String>,
+
+        /// look the server name up again on reconnect once the last lookup is this many seconds old
+        /// (default: resolve once at startup and keep reconnecting to those addresses)
+        #[argh(option)]
+        re_resolve_secs: Option<u64>,
+
+        /// restart the connection worker this many ms after it panics or fails, doubling per
+        /// failed attempt up to a minute (0 = leave it stopped; default 1000)
+        #[argh(option, default = "1000")]
+        worker_restart_ms: u64,
+
+        /// local address to connect from, to pick the network interface (default: OS routing)
+        #[argh(option)]
+        bind: Option<std::net::IpAddr>,
+
+        /// initial patch colour as normalized floats r,g,b in 0.0-1.0 (e.g. 0.5,0.5,0.5)
+        #[argh(option, from_str_fn(parse_rgb_f))]
+        measure_f: Option<(f64, f64, f64)>,
+
+        /// show this solid colour r,g,b (code values at --measure-bits) with no server at all, e.g. for dead-pixel checks
+        #[argh(option, from_str_fn(parse_rgb16))]
+        solid: Option<(u16, u16, u16)>,
+
+        /// alternate the patch between two colours A,B with no server, for response-time checks:
+        /// grey levels (0,255) or r/g/b code values (255/0/0,0/0/0) at --measure-bits
+        #[argh(option, from_str_fn(flash::parse_pair))]
+        flash: Option<(flash::Codes, flash::Codes)>,
+
+        /// flash cycles (A then B) per second for --flash (default 1)
+        #[argh(option, default = "1.0")]
+        flash_hz: f64,
+
+        /// pace the loop to the display: present every refresh with vsync and let --flash swap
+        /// colours every N refreshes (instead of --flash-hz), for patterns locked to the panel
+        #[argh(option)]
+        sync_divisor: Option<u32>,
+
+        /// bit depth --measure-f, --measure-cct, --solid and --flash are given in, and colour bars are built at (default 8)
+        #[argh(option, default = "8")]
+        measure_bits: u8,
+
+        /// initial patch as a grey of this colour temperature in kelvin (4000-25000), assuming an sRGB display
+        #[argh(option)]
+        measure_cct: Option<f64>,
+
+        /// show the colour difference between the latest reading and this target x,y,Y on the overlay
+        #[argh(option, from_str_fn(parse_xyy))]
+        target: Option<(f64, f64, f64)>,
+
+        /// delta E formula for --target: 2000 (default) or 76
+        #[argh(option, default = "delta_e::Formula::Cie2000")]
+        delta_e: delta_e::Formula,
+
+        /// delta E at or below which the reading counts as a match (default 1.0)
+        #[argh(option, default = "1.0")]
+        delta_e_tolerance: f64,
+
+        /// luminance of the D65 reference white used for Lab, in the meter's units (default 100)
+        #[argh(option, default = "100.0")]
+        reference_white_y: f64,
+
+        /// luminance of the --measure-cct patch in % of white (default 100)
+        #[argh(option, default = "100.0")]
+        target_y: f64,
+
+        /// connect to the server over TLS (needs a build with the `tls` feature)
+        #[argh(switch)]
+        tls: bool,
+
+        /// PEM file with the CA certificate(s) to trust for --tls instead of the usual web roots
+        #[argh(option)]
+        ca: Option<std::path::PathBuf>,
+
+        /// with --tls, accept any server certificate (no verification)
+        #[argh(switch)]
+        insecure: bool,
+
+        /// don't send the `init profile` handshake after connecting, just wait for frames. ColourSpace
+        /// needs it; only for servers or bridges that reject it
+        #[argh(switch)]
+        no_handshake: bool,
+
+        /// connect to a server on this machine through the Unix domain socket at this path instead of TCP
+        #[argh(option)]
+        socket: Option<std::path::PathBuf>,
+
+        /// keep the raw XML of the latest frame so V can show it on screen
+        #[argh(switch)]
+        keep_raw: bool,
+
+        /// write the raw XML of every frame to this file (overwritten each frame)
+        #[argh(option)]
+        dump_raw: Option<std::path::PathBuf>,
+
+        /// what to show while disconnected: hold (default, keep the last patches), black or background (dark grey)
+        #[argh(option, default = "DisconnectMode::Hold")]
+        on_disconnect: DisconnectMode,
+
+        /// serve Prometheus metrics on http://<metrics-bind>:N/metrics
+        #[argh(option)]
+        metrics_port: Option<u16>,
+
+        /// address the metrics endpoint binds to (default 127.0.0.1; use 0.0.0.0 for remote scraping)
+        #[argh(option, default = "std::net::IpAddr::from([127, 0, 0, 1])")]
+        metrics_bind: std::net::IpAddr,
+
+        /// window/renderer backend: vulkan (default), opengl or software; falls back automatically
+        #[argh(option, default = "Renderer::Vulkan")]
+        renderer: Renderer,
+
+        /// keep rendering at full rate when the window is unfocused or minimized
+        #[argh(switch)]
+        no_idle: bool,
+
+        /// pause after each frame, in ms (default 1). 0 runs the loop uncapped for benchmarking and
+        /// fast --flash rates: no pause and no waiting for events, at the cost of a busy CPU core
+        /// (vsync, when on, still paces it; an unfocused window still idles unless --no-idle)
+        #[argh(option, default = "1")]
+        frame_sleep_ms: u64,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Renderer {
+        Vulkan,
+        OpenGl,
+        Software,
+    }
+
+    impl std::str::FromStr for Renderer {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s.to_ascii_lowercase().as_str() {
+                "vulkan" => Ok(Renderer::Vulkan),
+                "opengl" | "gl" => Ok(Renderer::OpenGl),
+                "software" => Ok(Renderer::Software),
+                other => Err(format!("unknown renderer '{}', expected: vulkan, opengl, software", other)),
+            }
+        }
+    }
+
+    impl Renderer {
+        /// Every backend, most capable first (the names `--renderer` takes).
+        const ALL: [(Renderer, &str); 3] = [(Renderer::Vulkan, "vulkan"), (Renderer::OpenGl, "opengl"), (Renderer::Software, "software")];
+
+        /// This backend, then the ones after it that are more likely to work.
+        fn fallbacks(self) -> Vec<Renderer> {
+            let all = Renderer::ALL.map(|(r, _)| r);
+            std::iter::once(self).chain(all.into_iter().filter(|r| *r != self)).collect()
+        }
+    }
+
+    /// `--capabilities`: one JSON object describing this build, for harnesses that drive
+    /// several client versions. Keys only ever get added; `schema` goes up if one has
+    /// to change meaning.
+    ///
+    /// - `name`, `version`: crate name and version
+    /// - `shapes`: shape elements the parser understands
+    /// - `bit_depths`: `min`/`max` bits per channel accepted for colours
+    /// - `geometry_units`: values of `<geometry units="...">`
+    /// - `renderers`: `--renderer` choices; `render_drivers`: what this SDL offers
+    /// - `features`: optional cargo features and whether this build has them
+    /// - `services`: optional network services built in (`control`, `metrics`, `bridge`)
+    fn print_capabilities() {
+        let list = |items: Vec<&str>| items.into_iter().map(control::json_string).collect::<Vec<_>>().join(", ");
+        println!("{{");
+        println!("  \"schema\": 1,");
+        println!("  \"name\": {},", control::json_string(env!("CARGO_PKG_NAME")));
+        println!("  \"version\": {},", control::json_string(env!("CARGO_PKG_VERSION")));
+        println!("  \"shapes\": [{}],", list(ShapeInstruction::KINDS.to_vec()));
+        println!("  \"bit_depths\": {{\"min\": {}, \"max\": {}}},", lan::DEPTH_BITS.start(), lan::DEPTH_BITS.end());
+        println!("  \"geometry_units\": [\"normalized\", \"pixels\"],");
+        println!("  \"renderers\": [{}],", list(Renderer::ALL.iter().map(|&(_, name)| name).collect()));
+        println!("  \"render_drivers\": [{}],", list(sdl2::render::drivers().map(|d| d.name).collect()));
+        println!("  \"features\": {{\"tls\": {}}},", cfg!(feature = "tls"));
+        println!("  \"services\": [\"control\", \"metrics\", \"bridge\"]");
+        println!("}}");
+    }
+
+    /// Report a display-init failure on stderr and in a dialog, then exit non-zero.
+    fn display_init_failed(what: &str, err: &str) -> ! {
+        if dialog::enabled() {
+            eprintln!("{}: {}", what, err);
+        }
+        let msg = format!(
+            "{}:\n\n{}\n\nCheck the graphics drivers, or try another backend with --renderer opengl or --renderer software.",
+            what, err
+        );
+        dialog::error("Calibration Client Linux", &msg);
+        std::process::exit(1);
+    }
+
+    fn create_canvas(video: &sdl2::VideoSubsystem, renderer: Renderer, w: u32, h: u32) -> Result<sdl2::render::Canvas<sdl2::video::Window>, String> {
+        let mut builder = video.window("Calibration Client Linux", w, h);
+        builder.position_centered().resizable().allow_highdpi();
+        build_canvas(&mut builder, renderer)
+    }
+
+    /// Borderless desktop-fullscreen window covering display `index`, for --mirror-displays.
+    fn create_mirror(video: &sdl2::VideoSubsystem, renderer: Renderer, index: i32) -> Result<sdl2::render::Canvas<sdl2::video::Window>, String> {
+        let bounds = video.display_bounds(index)?;
+        let mut builder = video.window(&format!("Calibration Client Linux - display {}", index), bounds.width(), bounds.height());
+        builder.position(bounds.x(), bounds.y()).borderless().allow_highdpi();
+        let mut canvas = build_canvas(&mut builder, renderer)?;
+        canvas.window_mut().set_fullscreen(sdl2::video::FullscreenType::Desktop)?;
+        Ok(canvas)
+    }
+
+    fn build_canvas(builder: &mut sdl2::video::WindowBuilder, renderer: Renderer) -> Result<sdl2::render::Canvas<sdl2::video::Window>, String> {
+        match renderer {
+            Renderer::Vulkan => { builder.vulkan(); }
+            Renderer::OpenGl => {
+                builder.opengl();
+                sdl2::hint::set("SDL_RENDER_DRIVER", "opengl");
+            }
+            Renderer::Software => {}
+        }
+        let window = builder.build().map_err(|e| e.to_string())?;
+
+        let canvas = window.into_canvas();
+        let canvas = if renderer == Renderer::Software { canvas.software() } else { canvas };
+        canvas.build().map_err(|e| e.to_string())
+    }
+
+    /// Pretty-print parsed documents for `--parse-file`.
+    fn print_measurements(results: &[MeasurementResult]) {
+        fn opt(v: Option<f64>) -> String {
+            v.map(|v| format!("{:.4}", v)).unwrap_or_else(|| "-".to_string())
+        }
+
+        for (i, res) in results.iter().enumerate() {
+            println!("document {}:", i + 1);
+            println!("  colour     : R = {} , G = {} , B = {}", res.red, res.green, res.blue);
+            println!("  measurement: x = {} , y = {} , Y = {}", opt(res.x), opt(res.y), opt(res.y_lum));
+            if let Some(label) = res.label.as_deref() {
+                println!("  label      : {}", label);
+            }
+            if res.fullscreen {
+                println!("  fullscreen : yes (flat field over the whole screen)");
+            }
+            if let Some(hold) = res.hold {
+                println!("  hold       : {} ms", hold.as_millis());
+            }
+            println!("  shapes     : {}", res.shapes.len());
+            for (n, shape) in res.shapes.iter().enumerate() {
+                match shape {
+                    ShapeInstruction::Rectangle(rect) => {
+                        let c = rect.color;
+                        println!(
+                            "    [{}] rectangle  {}-bit  R = {} , G = {} , B = {}  size = {:.4} x {:.4}{}",
+                            n, c.depth_bits, c.red, c.green, c.blue, rect.geometry.width, rect.geometry.height,
+                            if rect.geometry.units == lan::GeometryUnits::Pixels { " px" } else { "" }
+                        );
+                        if let Some(src) = rect.ycbcr {
+                            println!(
+                                "        from YCbCr {:?} {}  Y = {} , Cb = {} , Cr = {}",
+                                src.matrix, if src.full_range { "full" } else { "limited" }, src.y, src.cb, src.cr
+                            );
+                        }
+                        if let Some(src) = rect.xyz {
+                            println!("        from XYZ ({})  X = {:.4} , Y = {:.4} , Z = {:.4}", src.primaries, src.xyz[0], src.xyz[1], src.xyz[2]);
+                        }
+                    }
+                    ShapeInstruction::Circle(circle) => {
+                        let (c, g) = (circle.color, circle.geometry);
+                        println!(
+                            "    [{}] circle     {}-bit  R = {} , G = {} , B = {}  centre = {}  r = {:.4}{}",
+                            n, c.depth_bits, c.red, c.green, c.blue, centre_text(g.cx, g.cy), g.r,
+                            if g.units == lan::GeometryUnits::Pixels { " px" } else { "" }
+                        );
+                    }
+                    ShapeInstruction::Ellipse(ellipse) => {
+                        let (c, g) = (ellipse.color, ellipse.geometry);
+                        println!(
+                            "    [{}] ellipse    {}-bit  R = {} , G = {} , B = {}  centre = {}  radii = {:.4} x {:.4}{}",
+                            n, c.depth_bits, c.red, c.green, c.blue, centre_text(g.cx, g.cy), g.rx, g.ry,
+                            if g.units == lan::GeometryUnits::Pixels { " px" } else { "" }
+                        );
+                    }
+                }
+                if shape.clamped().any() {
+                    println!("        out of gamut, {} clamped", shape.clamped());
+                }
+            }
+        }
+    }
+
+    /// `cx,cy` of a circle or ellipse for `--parse-file`, or "centred" when not given.
+    fn centre_text(cx: Option<f32>, cy: Option<f32>) -> String {
+        match (cx, cy) {
+            (None, None) => "centred".to_string(),
+            _ => format!("{},{}", cx.map_or("mid".to_string(), |v| format!("{:.4}", v)), cy.map_or("mid".to_string(), |v| format!("{:.4}", v))),
+        }
+    }
+
+    /// Parse `dx,dy` for `--patch-offset` (fractions of the patch area, + is right/down).
+    fn parse_offset(value: &str) -> Result<(f32, f32), String> {
+        let bad = || format!("bad offset '{}', expected dx,dy as fractions, e.g. 0.05,-0.02", value);
+        let (dx, dy) = value.split_once(',').ok_or_else(bad)?;
+        match (dx.trim().parse::<f32>(), dy.trim().parse::<f32>()) {
+            (Ok(dx), Ok(dy)) if dx.is_finite() && dy.is_finite() => Ok((dx, dy)),
+            _ => Err(bad()),
+        }
+    }
+
+    /// Parse `r,g,b` normalized floats for `--measure-f` (range is clamped on conversion).
+    fn parse_rgb_f(value: &str) -> Result<(f64, f64, f64), String> {
+        let parts: Vec<f64> = value
+        .split(',')
+        .map(|p| p.trim().parse::<f64>().ok().filter(|v| v.is_finite()))
+        .collect::<Option<_>>()
+        .ok_or_else(|| format!("bad colour '{}', expected r,g,b with 0.0-1.0 each", value))?;
+        match parts[..] {
+            [r, g, b] => Ok((r, g, b)),
+            _ => Err(format!("bad colour '{}', expected r,g,b with 0.0-1.0 each", value)),
+        }
+    }
+
+    fn parse_xyy(value: &str) -> Result<(f64, f64, f64), String> {
+        let bad = || format!("bad colour '{}', expected x,y,Y (chromaticity 0-1, luminance >= 0)", value);
+        let parts: Vec<f64> = value
+        .split(',')
+        .map(|p| p.trim().parse::<f64>().ok().filter(|v| v.is_finite()))
+        .collect::<Option<_>>()
+        .ok_or_else(bad)?;
+        match parts[..] {
+            [x, y, big_y] if (0.0..=1.0).contains(&x) && y > 0.0 && y <= 1.0 && big_y >= 0.0 => Ok((x, y, big_y)),
+            _ => Err(bad()),
+        }
+    }
+
+    fn parse_rgb16(value: &str) -> Result<(u16, u16, u16), String> {
+        let parts: Vec<u16> = value
+        .split(',')
+        .map(|p| p.trim().parse::<u16>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("bad colour '{}', expected r,g,b code values", value))?;
+        match parts[..] {
+            [r, g, b] => Ok((r, g, b)),
+            _ => Err(format!("bad colour '{}', expected r,g,b code values", value)),
+        }
+    }
+
+    fn parse_displays(value: &str) -> Result<Vec<i32>, String> {
+        value
+        .split(',')
+        .map(|p| p.trim().parse::<i32>().ok().filter(|&i| i >= 0))
+        .collect::<Option<_>>()
+        .ok_or_else(|| format!("bad display list '{}', expected comma-separated display numbers like 1,2", value))
     }
 
     fn pad(msg: &str, width: usize) -> String {
@@ -48,18 +889,33 @@ fn main() -> Result<(), Box<dyn Error>> {
         s
     }
 
-    fn show_startup_ui() -> Option<String> {
+    /// Trim a typed or pasted server address; rejects empty input and anything with
+    /// whitespace left inside (a pasted sentence, several lines, ...).
+    fn clean_address(s: &str) -> Option<String> {
+        let s = s.trim();
+        (!s.is_empty() && !s.contains(char::is_whitespace)).then(|| s.to_string())
+    }
+
+    /// Ask for the server address, pre-filled with `default`. None = cancelled/empty.
+    fn show_startup_ui(default: &str) -> Option<String> {
         // Make this large enough to avoid title truncation on your desktop.
         // Try 80..120 if your title is still clipped.
         const PAD_WIDTH: usize = 80;
 
         let title = "Calibration Client Linux";
-        let server = tfd::input_box(title, &pad("ColourSpace IP:", PAD_WIDTH), "")?;
-
-        if server.trim().is_empty() {
-            None
-        } else {
-            Some(server)
+        let mut default = default.to_string();
+        loop {
+            let server = dialog::input(title, &pad("ColourSpace IP:", PAD_WIDTH), &default)?;
+            if server.trim().is_empty() {
+                return None;
+            }
+            match clean_address(&server) {
+                Some(addr) => return Some(addr),
+                None => {
+                    dialog::error(title, &format!("'{}' is not a valid address", server.trim()));
+                    default = server;
+                }
+            }
         }
     }
 
@@ -72,75 +928,200 @@ fn main() -> Result<(), Box<dyn Error>> {
         format!("{}:20002", s)
     }
 
-    fn select_measure_colour(shapes: &[ShapeInstruction]) -> Option<ColorRGB> {
-        shapes
-        .iter()
-        .filter_map(|shape| match shape {
-            ShapeInstruction::Rectangle(rect) => {
-                let area = (rect.geometry.width * rect.geometry.height).max(0.0001);
-                Some((area, rect.color))
+    /// Drawable pixels per logical window pixel (2.0 on a 2x-scaled HiDPI panel).
+    /// Everything drawn is laid out in drawable pixels from `output_size()`, so a
+    /// requested X% patch is X% of the physical pixels; only mouse coordinates
+    /// arrive in logical units and need this factor.
+    fn hidpi_scale(canvas: &sdl2::render::Canvas<sdl2::video::Window>) -> Result<(f32, f32), String> {
+        let (ww, wh) = canvas.window().size();
+        let (dw, dh) = canvas.output_size()?;
+        Ok((dw as f32 / ww.max(1) as f32, dh as f32 / wh.max(1) as f32))
+    }
+
+    /// What the I key shows: server link, protocol, display and bit depths, for support reports.
+    fn connection_info(
+        video: &sdl2::VideoSubsystem,
+        canvas: &sdl2::render::Canvas<sdl2::video::Window>,
+        worker: Option<&std::sync::RwLock<lan::SharedState>>,
+        server: Option<&str>,
+        measure_bits: u8,
+    ) -> String {
+        let mut lines = Vec::new();
+        match (server, worker) {
+            (Some(server), Some(state)) => {
+                let r = state.read().unwrap_or_else(PoisonError::into_inner);
+                lines.push(format!("Server: {} ({})", server, if r.connected { "connected" } else { "disconnected" }));
+                lines.push(format!("Protocol: CS_RMC version {}", lan::SUPPORTED_VERSION));
+                lines.push(format!("Readings: {}, reconnects: {}, worker restarts: {}", r.measurements_received, r.reconnects, r.worker_restarts));
+                if let Some(e) = r.last_worker_error.as_ref() {
+                    lines.push(format!("Last worker error: {}", e));
+                }
+                if let Some(reason) = r.disconnect_reason.as_ref().filter(|_| !r.connected) {
+                    lines.push(format!("Disconnected: {}", reason));
+                }
             }
-        })
-        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
-        .map(|(_, color)| color)
+            _ => lines.push("Server: none (offline)".to_string()),
+        }
+        let index = canvas.window().display_index().unwrap_or(0);
+        let name = video.display_name(index).unwrap_or_default();
+        match video.current_display_mode(index) {
+            Ok(mode) => lines.push(format!("Display {}: {} {}x{}@{}Hz, {:?}", index, name, mode.w, mode.h, mode.refresh_rate, mode.format)),
+            Err(e) => lines.push(format!("Display {}: {} ({})", index, name, e)),
+        }
+        let (dw, dh) = canvas.output_size().unwrap_or_default();
+        lines.push(format!("Drawable: {}x{}, renderer {}, video driver {}", dw, dh, canvas.info().name, video.current_video_driver()));
+        lines.push(format!("Patch depth: {}-bit requests, drawn at 8 bits per channel", measure_bits));
+        lines.join("\n")
     }
 
-    /// Helper: convert a `ColorRGB` (u16 + depth_bits) into an 8-bit RGB tuple.
-    ///
-    /// Note: this is intentionally local to `main.rs` so the `lan` module stays
-    /// depth-agnostic. When you add a Vulkan 10-bit pipeline, replace or extend
-    /// this helper to return higher-bit buffers or skip the conversion entirely.
-    fn color_to_u8_tuple(color: ColorRGB) -> (u8, u8, u8) {
-        let bits = if color.depth_bits == 0 { 8 } else { color.depth_bits };
-        let max_in: u32 = if bits >= 16 {
-            0xFFFF
-        } else {
-            (1u32 << bits as u32) - 1
-        };
+    /// `--print-caps`: video driver, displays, renderer and logical vs drawable size.
+    fn print_caps(video: &sdl2::VideoSubsystem, canvas: &sdl2::render::Canvas<sdl2::video::Window>) -> Result<(), String> {
+        println!("video driver : {}", video.current_video_driver());
+        for i in 0..video.num_video_displays()? {
+            let name = video.display_name(i).unwrap_or_default();
+            let bounds = video.display_bounds(i)?;
+            let mode = video.current_display_mode(i)?;
+            let dpi = video
+            .display_dpi(i)
+            .map(|(d, _, _)| format!("{:.0}", d))
+            .unwrap_or_else(|_| "?".to_string());
+            println!(
+                "display {}    : {} bounds {}x{} at {},{}  mode {}x{}@{}Hz  dpi {}",
+                i, name, bounds.width(), bounds.height(), bounds.x(), bounds.y(), mode.w, mode.h, mode.refresh_rate, dpi
+            );
+        }
+
+        let info = canvas.info();
+        println!("renderer     : {} (max texture {}x{})", info.name, info.max_texture_width, info.max_texture_height);
+
+        let (lw, lh) = canvas.window().size();
+        let (dw, dh) = canvas.output_size()?;
+        let (sx, sy) = hidpi_scale(canvas)?;
+        println!("window       : {}x{} logical", lw, lh);
+        println!("drawable     : {}x{} physical (scale {:.2} x {:.2})", dw, dh, sx, sy);
+        Ok(())
+    }
 
-        // avoid division by zero (defensive)
-        let max_in = if max_in == 0 { 255 } else { max_in };
+    /// Refresh rate of the display the window is on, in Hz; 0 when SDL doesn't know.
+    fn refresh_rate(video: &sdl2::VideoSubsystem, canvas: &sdl2::render::Canvas<sdl2::video::Window>) -> i32 {
+        canvas.window().display_mode().ok()
+            .filter(|m| m.refresh_rate > 0)
+            .or_else(|| canvas.window().display_index().and_then(|i| video.current_display_mode(i)).ok())
+            .map_or(0, |m| m.refresh_rate)
+    }
 
-        let r = ((color.red as u32 * 255 + max_in / 2) / max_in) as u8;
-        let g = ((color.green as u32 * 255 + max_in / 2) / max_in) as u8;
-        let b = ((color.blue as u32 * 255 + max_in / 2) / max_in) as u8;
-        (r, g, b)
+    /// Put the window at `saved`, provided its display still exists and the saved rectangle
+    /// still overlaps it (a display that changed resolution or arrangement may not).
+    fn restore_window(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>, video: &sdl2::VideoSubsystem, saved: WindowGeometry, min_w: u32, min_h: u32) -> Result<(), String> {
+        let displays = video.num_video_displays()?;
+        if saved.display >= displays {
+            return Err(format!("display {} is gone, {} left", saved.display, displays));
+        }
+        let bounds = video.display_bounds(saved.display)?;
+        let rect = sdl2::rect::Rect::new(saved.x, saved.y, saved.width.max(min_w), saved.height.max(min_h));
+        if !rect.has_intersection(bounds) {
+            return Err(format!("{}x{} at {},{} is off display {}", rect.width(), rect.height(), rect.x(), rect.y(), saved.display));
+        }
+        canvas.window_mut().set_size(rect.width(), rect.height()).map_err(|e| e.to_string())?;
+        canvas.window_mut().set_position(sdl2::video::WindowPos::Positioned(rect.x()), sdl2::video::WindowPos::Positioned(rect.y()));
+        Ok(())
     }
 
-    fn draw_shapes(
-        canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
-        shapes: &[ShapeInstruction],
-        w: u32,
-        h: u32,
-    ) {
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
+    /// The windowed rectangle, display and fullscreen state to save on exit.
+    fn window_geometry(canvas: &sdl2::render::Canvas<sdl2::video::Window>, windowed: (i32, i32, u32, u32), fullscreen: bool) -> Option<WindowGeometry> {
+        let (x, y, width, height) = windowed;
+        let display = canvas.window().display_index().ok()?;
+        Some(WindowGeometry { display, x, y, width, height, fullscreen })
+    }
 
-        for shape in shapes {
-            match shape {
-                ShapeInstruction::Rectangle(rect) => {
-                    let geom = rect.geometry;
-                    // clamp widths/heights and ensure at least 1 pixel
-                    let rw = (geom.width.clamp(0.0, 1.0) * w as f32).round().max(1.0) as u32;
-                    let rh = (geom.height.clamp(0.0, 1.0) * h as f32).round().max(1.0) as u32;
+    /// Centre the (windowed) window on display `index`.
+    fn move_to_display(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>, video: &sdl2::VideoSubsystem, index: i32) -> Result<(), String> {
+        let bounds = video.display_bounds(index)?;
+        let (w, h) = canvas.window().size();
+        let x = bounds.x() + (bounds.width() as i32 - w as i32) / 2;
+        let y = bounds.y() + (bounds.height() as i32 - h as i32) / 2;
+        canvas.window_mut().set_position(sdl2::video::WindowPos::Positioned(x), sdl2::video::WindowPos::Positioned(y));
+        Ok(())
+    }
+
+    /// The display to put a fullscreen window back on after it moved or changed size:
+    /// the `--display` one, if the window ended up elsewhere and that display still exists.
+    fn display_to_restore(intended: Option<i32>, current: i32, fullscreen: bool, displays: i32) -> Option<i32> {
+        intended.filter(|&d| fullscreen && d != current && (0..displays).contains(&d))
+    }
 
-                    let left = ((w as f32 - rw as f32) / 2.0).round() as i32;
-                    let top = ((h as f32 - rh as f32) / 2.0).round() as i32;
+    /// The display's native mode: the one with the most pixels, fastest refresh on a tie.
+    /// SDL lists modes the driver reports, so on a panel this is its native resolution.
+    fn native_mode(video: &sdl2::VideoSubsystem, index: i32) -> Result<sdl2::video::DisplayMode, String> {
+        let count = video.num_display_modes(index)?;
+        (0..count)
+            .filter_map(|i| video.display_mode(index, i).ok())
+            .max_by_key(|m| (m.w as i64 * m.h as i64, m.refresh_rate))
+            .ok_or_else(|| format!("display {} reports no display modes, so its native resolution is unknown", index))
+    }
 
-                    let color = rect.color;
-                    // downscale from u16/depth to u8 here using local helper
-                    let (r8, g8, b8) = color_to_u8_tuple(color);
-                    canvas.set_draw_color(Color::RGB(r8, g8, b8));
-                    let _ = canvas.fill_rect(Rect::new(left, top, rw, rh));
+    /// Switch fullscreen on/off; returns the resulting state. With `native` the display
+    /// is switched to its native mode (exclusive fullscreen) and back when leaving it,
+    /// otherwise the window just covers the desktop at its current resolution.
+    fn set_fullscreen(canvas: &mut sdl2::render::Canvas<sdl2::video::Window>, video: &sdl2::VideoSubsystem, on: bool, native: bool) -> bool {
+        let target = match (on, native) {
+            (false, _) => sdl2::video::FullscreenType::Off,
+            (true, false) => sdl2::video::FullscreenType::Desktop,
+            (true, true) => {
+                let mode = canvas.window().display_index().and_then(|index| native_mode(video, index));
+                match mode.and_then(|mode| canvas.window_mut().set_display_mode(mode).map(|()| mode)) {
+                    Ok(mode) => {
+                        eprintln!("Switching to native mode {}x{} @ {} Hz", mode.w, mode.h, mode.refresh_rate);
+                        sdl2::video::FullscreenType::True
+                    }
+                    Err(e) => {
+                        eprintln!("Native fullscreen unavailable: {}", e);
+                        return !on;
+                    }
                 }
             }
+        };
+        match canvas.window_mut().set_fullscreen(target) {
+            Ok(()) => on,
+            Err(_) => !on,
         }
     }
 
-    // ---------------------------------------------------------------------
-    // ARG PARSING (ONLY POSITIONAL IP)
-    // ---------------------------------------------------------------------
-    let args: Args = argh::from_env();
+    /// Save what has been drawn this frame (call before `present`) as a BMP.
+    fn save_screenshot(canvas: &sdl2::render::Canvas<sdl2::video::Window>, path: &std::path::Path) -> Result<(), String> {
+        let (w, h) = canvas.output_size()?;
+        let format = sdl2::pixels::PixelFormatEnum::RGB24;
+        let mut pixels = canvas.read_pixels(None, format)?;
+        let surface = sdl2::surface::Surface::from_data(&mut pixels, w, h, w * 3, format)?;
+        surface.save_bmp(path)
+    }
+
+    /// Rebuild the renderer after a device reset or failed draw: first on the same
+    /// window, then with a fresh window via the usual backend fallbacks.
+    fn recreate_canvas(
+        canvas: sdl2::render::Canvas<sdl2::video::Window>,
+        video: &sdl2::VideoSubsystem,
+        renderer: Renderer,
+    ) -> Result<sdl2::render::Canvas<sdl2::video::Window>, String> {
+        let window = canvas.into_window();
+        let (w, h) = window.size();
+        let builder = window.into_canvas();
+        let builder = if renderer == Renderer::Software { builder.software() } else { builder };
+        match builder.build() {
+            Ok(canvas) => Ok(canvas),
+            Err(e) => {
+                eprintln!("Renderer rebuild on the existing window failed ({}), opening a new window", e);
+                let mut last_err = e.to_string();
+                for r in renderer.fallbacks() {
+                    match create_canvas(video, r, w, h) {
+                        Ok(canvas) => return Ok(canvas),
+                        Err(e) => last_err = e,
+                    }
+                }
+                Err(last_err)
+            }
+        }
+    }
 
     // ---------------------------------------------------------------------
     // Create event pump early so we can keep the window responsive during waits
@@ -151,8 +1132,70 @@ fn main() -> Result<(), Box<dyn Error>> {
     // STARTUP UI + NETWORK WORKER SETUP (retry on failure) - with connect timeout
     // and non-freezing dialog handling
     // ---------------------------------------------------------------------
-    let mut current_measure_colour = ColorRGB::default();
-    let mut maybe_remote = args.remote;
+    let mut current_measure_colour = match (args.measure_f, args.measure_cct) {
+        (Some(_), Some(_)) => return Err("--measure-f and --measure-cct can't be combined".into()),
+        (Some((r, g, b)), None) => ColorRGB::from_normalized(r, g, b, args.measure_bits),
+        (None, Some(kelvin)) => cct::patch(kelvin, args.target_y, args.measure_bits).map_err(|e| format!("--measure-cct: {}", e))?,
+        (None, None) => ColorRGB::default(),
+    };
+    if let Some((r, g, b)) = args.solid {
+        if args.measure_f.is_some() || args.measure_cct.is_some() {
+            return Err("--solid can't be combined with --measure-f or --measure-cct".into());
+        }
+        let max = ((1u32 << args.measure_bits.clamp(1, 16)) - 1) as u16;
+        if r > max || g > max || b > max {
+            return Err(format!("--solid: channel value above {} for {}-bit (see --measure-bits)", max, args.measure_bits).into());
+        }
+        current_measure_colour = ColorRGB::from_components_u16(r, g, b, args.measure_bits);
+    }
+    let flash = match args.flash {
+        Some(((ar, ag, ab), (br, bg, bb))) => {
+            if args.solid.is_some() {
+                return Err("--flash can't be combined with --solid".into());
+            }
+            if !(args.flash_hz.is_finite() && args.flash_hz > 0.0) {
+                return Err("--flash-hz must be above 0".into());
+            }
+            let max = ((1u32 << args.measure_bits.clamp(1, 16)) - 1) as u16;
+            if [ar, ag, ab, br, bg, bb].iter().any(|&v| v > max) {
+                return Err(format!("--flash: channel value above {} for {}-bit (see --measure-bits)", max, args.measure_bits).into());
+            }
+            let a = ColorRGB::from_components_u16(ar, ag, ab, args.measure_bits);
+            let b = ColorRGB::from_components_u16(br, bg, bb, args.measure_bits);
+            Some(flash::Flash::new(a, b, args.flash_hz, Instant::now()))
+        }
+        None => None,
+    };
+    let mut pacer = match args.sync_divisor {
+        Some(0) => return Err("--sync-divisor must be at least 1".into()),
+        Some(n) => {
+            let refresh = refresh_rate(&video, &canvas);
+            match pacing::content_hz(refresh, n) {
+                Some(hz) => eprintln!("Pacing to every {} refresh(es) of {} Hz: {:.2} content frames/s", n, refresh, hz),
+                None => eprintln!("Display refresh rate unknown; pacing to every {} vsync without a rate", n),
+            }
+            Some(pacing::RefreshPacer::new(n))
+        }
+        None => None,
+    };
+    if !matches!(args.bar_level, 75 | 100) {
+        return Err(format!("--bar-level must be 75 or 100, not {}", args.bar_level).into());
+    }
+    // --pattern smpte/ebu: laid out once, drawn over the region every frame
+    let bars = args.pattern.map(|p| pattern::colour_bars(p, args.bar_level as f64 / 100.0, args.measure_bits)).filter(|b| !b.is_empty());
+
+    // Clipboard text (if asked for and it looks like an address) becomes the dialog default,
+    // else the server that last connected.
+    let last_host = config.last_host.clone().unwrap_or_default();
+    let dialog_default = if args.from_clipboard {
+        let text = video.clipboard().clipboard_text().unwrap_or_default();
+        clean_address(&text).unwrap_or_else(|| {
+            eprintln!("Clipboard does not hold a server address, ignoring it");
+            last_host
+        })
+    } else {
+        last_host
+    };
 
     // Increased timeout to 6000ms to give slower setups time to connect.
     const CONNECT_TIMEOUT_MS: u64 = 6000;
@@ -160,9 +1203,19 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // The loop yields Some(worker_state) when we have a worker that successfully connected.
     // If the user cancels the UI, we exit cleanly.
+    // what the worker connected to, for the I key's info box
+    let mut server_addr: Option<String> = None;
     let worker = loop {
-        // Use CLI-provided address once; otherwise prompt the UI.
-        let remote_input = maybe_remote.take().or_else(|| show_startup_ui());
+        // Offline patterns, solid colours and flashing never touch the network.
+        if args.pattern.is_some() || args.solid.is_some() || flash.is_some() {
+            break None;
+        }
+
+        // Something defined behind the address dialog, rather than an uninitialized window.
+        draw_idle(&mut canvas, args.idle_colour, None)?;
+
+        // Use the CLI/env/config address once; otherwise prompt the UI.
+        let remote_input = maybe_remote.take().or_else(|| show_startup_ui(&dialog_default));
 
         // If the user cancelled the UI (or provided empty input), exit gracefully.
         let remote = match remote_input {
@@ -170,30 +1223,37 @@ fn main() -> Result<(), Box<dyn Error>> {
             None => return Ok(()),
         };
 
-        let remote_addr = add_default_port(&remote);
+        let remote_addr = link_name(&remote);
 
-        match spawn_worker(&remote_addr, false) {
+        match spawn_worker(&remote_addr, &worker_config) {
             Ok(state) => {
-                // Tell worker what colour to request initially.
-                state.write().unwrap().request_colour = current_measure_colour;
+                // Tell worker what colour to request initially (and show it until the server drives).
+                {
+                    let mut w = state.write().unwrap_or_else(PoisonError::into_inner);
+                    w.request_colour = current_measure_colour;
+                    w.current_measure_colour = current_measure_colour;
+                }
 
                 // Wait a short while for the worker thread to actually establish a connection,
                 // but keep the SDL window responsive while we wait.
                 let mut elapsed = 0u64;
                 let mut connected = {
-                    let r = state.read().unwrap();
+                    let r = state.read().unwrap_or_else(PoisonError::into_inner);
                     r.connected
                 };
 
                 // debug print initial state
                 eprintln!("Waiting up to {}ms for ColourSpace to connect (initial connected={})", CONNECT_TIMEOUT_MS, connected);
 
+                let connecting = format!("Connecting to {}...", remote_addr);
                 while !connected && elapsed < CONNECT_TIMEOUT_MS {
+                    // Redrawn every poll, so the window keeps up with resizes and looks alive.
+                    draw_idle(&mut canvas, args.idle_colour, Some(&connecting))?;
+
                     // Poll SDL events so the window remains responsive
                     for evt in event_pump.poll_iter() {
-                        match evt {
-                            sdl2::event::Event::Quit { .. } => return Ok(()),
-                            _ => {}
+                        if let sdl2::event::Event::Quit { .. } = evt {
+                            return Ok(());
                         }
                     }
 
@@ -201,12 +1261,12 @@ fn main() -> Result<(), Box<dyn Error>> {
                     elapsed += CONNECT_POLL_MS;
 
                     connected = {
-                        let r = state.read().unwrap();
+                        let r = state.read().unwrap_or_else(PoisonError::into_inner);
                         r.connected
                     };
 
                     // small debug print every 1s
-                    if elapsed % 1000 == 0 {
+                    if elapsed.is_multiple_of(1000) {
                         eprintln!("  connect wait: {}ms elapsed, connected={}", elapsed, connected);
                     }
                 }
@@ -214,6 +1274,14 @@ fn main() -> Result<(), Box<dyn Error>> {
                 if connected {
                     // success: worker connected within timeout — keep it.
                     eprintln!("ColourSpace connected after {}ms", elapsed);
+                    // offer it in the dialog next time (a --socket path is no host to offer)
+                    if args.socket.is_none() && config.last_host.as_deref() != Some(remote_addr.as_str()) {
+                        config.last_host = Some(remote_addr.clone());
+                        if let Err(e) = config.save() {
+                            eprintln!("Failed to save config: {}", e);
+                        }
+                    }
+                    server_addr = Some(remote_addr);
                     break Some(state);
                 } else {
                     // Timed out: worker never connected. Drop it and show error dialog without freezing the UI.
@@ -229,20 +1297,15 @@ fn main() -> Result<(), Box<dyn Error>> {
 
                     // Spawn the dialog on another thread (it will block there until user presses OK).
                     let _dialog_thread = spawn(move || {
-                        let _ = tfd::message_box_ok(
-                            "Calibration Client Linux",
-                            "ColourSpace not reachable, check IP address",
-                            tfd::MessageBoxIcon::Error,
-                        );
+                        dialog::error("Calibration Client Linux", "ColourSpace not reachable, check IP address");
                         dialog_done_clone.store(true, Ordering::SeqCst);
                     });
 
                     // Wait for the dialog to be dismissed while continuing to poll SDL events.
                     while !dialog_done.load(Ordering::SeqCst) {
                         for evt in event_pump.poll_iter() {
-                            match evt {
-                                sdl2::event::Event::Quit { .. } => return Ok(()),
-                                _ => {}
+                            if let sdl2::event::Event::Quit { .. } = evt {
+                                return Ok(());
                             }
                         }
                         std::thread::sleep(std::time::Duration::from_millis(10));
@@ -260,15 +1323,14 @@ fn main() -> Result<(), Box<dyn Error>> {
 
                 let err_str = format!("ColourSpace not found\n\n{}", err);
                 let _dialog_thread = spawn(move || {
-                    let _ = tfd::message_box_ok("Calibration Client Linux", &err_str, tfd::MessageBoxIcon::Error);
+                    dialog::error("Calibration Client Linux", &err_str);
                     dialog_done_clone.store(true, Ordering::SeqCst);
                 });
 
                 while !dialog_done.load(Ordering::SeqCst) {
                     for evt in event_pump.poll_iter() {
-                        match evt {
-                            sdl2::event::Event::Quit { .. } => return Ok(()),
-                            _ => {}
+                        if let sdl2::event::Event::Quit { .. } = evt {
+                            return Ok(());
                         }
                     }
                     std::thread::sleep(std::time::Duration::from_millis(10));
@@ -280,25 +1342,228 @@ fn main() -> Result<(), Box<dyn Error>> {
     };
 
     // Build the canvas once we have a worker (or the user cancelled earlier).
-    let mut canvas = window.into_canvas().build()?;
     // Note: we already created event_pump earlier; reuse it.
 
-    // double-click detection
-    let mut last_click_time = None::<Instant>;
-    let mut is_fullscreen = false;
-    let dc_threshold = Duration::from_millis(400);
+    // Restore a previously drawn patch region if the user asked for persistence.
+    if args.persist_region && let Some(state) = worker.as_ref() {
+        state.write().unwrap_or_else(PoisonError::into_inner).region = config.region;
+    }
 
-    // FPS bookkeeping (unused but left intentionally)
-    let _last_fps = Instant::now();
-    let mut _frames = 0u32;
+    // double-click detection (threshold: CLI > config > 400ms)
+    const DEFAULT_DOUBLE_CLICK_MS: u64 = 400;
+    let dc_threshold = Duration::from_millis(
+        args.double_click_ms.or(config.double_click_ms).unwrap_or(DEFAULT_DOUBLE_CLICK_MS),
+    );
+    let mut clicks = ClickTracker::new(dc_threshold);
+    // the windowed rectangle to save on exit; fullscreen leaves it as it was before
+    let mut windowed_rect = {
+        let ((x, y), (w, h)) = (canvas.window().position(), canvas.window().size());
+        (x, y, w, h)
+    };
+    let mut is_fullscreen = restore_fullscreen && set_fullscreen(&mut canvas, &video, true, args.native_fullscreen);
+    let mut show_overlay = true;
+    let mut channel_view = ChannelView::All;
+    let grid = Grid { rows: args.grid_rows, cols: args.grid_cols, thickness: args.grid_thickness, colour: args.grid_colour };
+    let mut show_grid = args.pattern == Some(Pattern::Grid);
+    // Probe alignment crosshair (X key). Switched off by itself as soon as anything may be
+    // measured: a new document from the server or a reading requested by a sweep.
+    let marker = Marker { size: args.marker_size, colour: args.marker_colour };
+    let mut show_marker = false;
+    let mut documents_seen = 0u64;
+    // for the overlay: server patches replaced before a frame could show them
+    let (mut patch_seq_seen, mut patches_skipped) = (0u64, 0u64);
+    // Raw frame viewer (V key, needs --keep-raw); arrows/PageUp/PageDown scroll it.
+    const RAW_VIEW_LINES: usize = 30;
+    const RAW_VIEW_WIDTH: usize = 100;
+    let mut show_raw = false;
+    let mut raw_scroll = 0usize;
+    // overlay spinner while a requested reading is outstanding
+    const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+    let spinner_epoch = Instant::now();
+
+    // Optional remote control, over TCP and/or stdin (see control.rs for the command set).
+    let mut control = Vec::new();
+    if let Some(port) = args.control_port {
+        let addr = std::net::SocketAddr::new(args.control_bind, port);
+        control.push(control::spawn_listener(addr, worker.clone()).map_err(|e| format!("control port {}: {}", addr, e))?);
+        eprintln!("Listening for control commands on {}", addr);
+    }
+    if args.stdin_commands {
+        control.push(control::spawn_stdin(worker.clone()));
+    }
+    // a colour set over the control port wins over the server until `clear`
+    let mut control_colour: Option<ColorRGB> = None;
+    let mut pending_screenshot: Option<std::path::PathBuf> = None;
+
+    // Optional audible cue on each new patch; audio trouble only costs the beep.
+    let mut beeper = if args.beep_on_change {
+        match sdl_context.audio().and_then(|audio| beep::Beeper::open(&audio)) {
+            Ok(b) => Some(b),
+            Err(e) => {
+                eprintln!("--beep-on-change: audio unavailable: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut last_shown_colour: Option<ColorRGB> = None;
+    let mut present_hook = args.on_present.as_deref().map(hook::PresentHook::command);
+    let mut shm_mirror = match args.shm.as_deref().map(shm::ShmMirror::create) {
+        Some(Ok(mirror)) => {
+            eprintln!("Publishing patch state to {}", mirror.path().display());
+            Some(mirror)
+        }
+        Some(Err(e)) => return Err(format!("--shm: {}", e).into()),
+        None => None,
+    };
+
+    // last frame the server drove (shapes, fullscreen flag, colour), for --on-disconnect hold
+    let mut last_good: Option<(Vec<ShapeInstruction>, bool, ColorRGB)> = None;
+
+    // The server patch on screen and when it went up, so a newer document can wait
+    // out its minimum display time (--min-hold-ms, or the document's own <hold_ms>).
+    struct ShownPatch {
+        document: u64,
+        shapes: Vec<ShapeInstruction>,
+        fullscreen: bool,
+        colour: ColorRGB,
+        since: Instant,
+        hold: Duration,
+    }
+    let min_hold = Duration::from_millis(args.min_hold_ms);
+    let backdrop = Backdrop { clear: args.canvas_clear, surround: args.patch_surround };
+    let mut shown_patch: Option<ShownPatch> = None;
+
+    // Optional /metrics endpoint for monitoring.
+    let counters = Arc::new(metrics::Counters::default());
+    if let Some(port) = args.metrics_port {
+        let addr = std::net::SocketAddr::new(args.metrics_bind, port);
+        metrics::spawn_server(addr, counters.clone(), worker.clone()).map_err(|e| format!("metrics port {}: {}", addr, e))?;
+        eprintln!("Serving metrics on http://{}/metrics", addr);
+    }
+
+    // Renderer health (in `counters`, so /metrics shows it): cleared on a device reset or a
+    // failed draw, then rebuilt.
+    const MAX_RENDER_RECOVERIES: u32 = 5;
+    // attempts since the last frame that drew cleanly
+    let mut recovery_streak = 0u32;
+
+    // region selection: left-drag further than this (logical px) draws a region
+    // instead of counting as a click; right-click clears it.
+    const MIN_DRAG_PX: i32 = 8;
+    let mut drag_start: Option<(i32, i32)> = None;
+    let mut drag_current: Option<(i32, i32)> = None;
+
+    // Client-driven sweep, if requested. It owns the displayed patch until done.
+    if [args.sweep.is_some(), args.uniformity.is_some(), args.patch_list.is_some(), args.ti1.is_some()].iter().filter(|&&on| on).count() > 1 {
+        return Err("only one of --sweep, --uniformity, --patch-list and --ti1 at a time".into());
+    }
+    if flash.is_some() && (args.sweep.is_some() || args.uniformity.is_some() || args.patch_list.is_some() || args.ti1.is_some() || args.resume.is_some()) {
+        return Err("--flash is display-only and can't run a sweep".into());
+    }
+    let settle = Duration::from_millis(args.settle_ms);
+    let measure_timeout = args.measure_timeout_ms.map(Duration::from_millis);
+    let requested = args
+    .uniformity
+    .map(|_| SweepKind::Uniformity)
+    .or(args.patch_list.as_ref().map(|_| SweepKind::PatchList))
+    .or(args.ti1.as_ref().map(|_| SweepKind::Ti1))
+    .or(args.sweep);
+    let resumed = match args.resume.as_deref() {
+        Some(path) if path.exists() => {
+            let sw = Sweep::resume(path, settle, args.average, args.reject_outliers).map_err(|e| format!("--resume: {}", e))?;
+            if requested.is_some_and(|kind| kind != sw.kind()) {
+                return Err(format!("--resume: {} holds a {:?} sweep, not the one requested", path.display(), sw.kind()).into());
+            }
+            eprintln!("Resuming {:?} sweep from {} ({} patch(es) already measured)", sw.kind(), path.display(), sw.rows().len());
+            Some(sw)
+        }
+        Some(path) if requested.is_none() => return Err(format!("--resume: {} not found", path.display()).into()),
+        _ => None,
+    };
+    let mut sweep = match (resumed, args.sweep, args.uniformity, args.patch_list.as_deref(), args.ti1.as_deref()) {
+        (Some(sw), ..) => Some(sw),
+        (None, _, Some(grid), ..) => Some(Sweep::uniformity(grid, settle, args.average, args.reject_outliers)),
+        (None, _, _, Some(path), _) => {
+            let patches = sweep::load_patch_list(path, args.measure_bits).map_err(|e| format!("--patch-list: {}", e))?;
+            eprintln!("Loaded {} patch(es) from {}", patches.len(), path.display());
+            Some(Sweep::patch_list(patches, settle, args.average, args.reject_outliers))
+        }
+        (None, _, _, _, Some(path)) => {
+            let patches = sweep::load_ti1(path, args.measure_bits).map_err(|e| format!("--ti1: {}", e))?;
+            eprintln!("Loaded {} patch(es) from {}", patches.len(), path.display());
+            Some(Sweep::ti1(patches, settle, args.average, args.reject_outliers))
+        }
+        (None, Some(kind), None, None, None) => Some(Sweep::new(kind, args.steps, settle, args.average, args.reject_outliers)),
+        (None, None, None, None, None) => None,
+    }
+    .map(|sw| sw.with_measure_timeout(measure_timeout, args.on_timeout).with_crossfade(Duration::from_millis(args.crossfade_ms)));
+    if args.reject_outliers && args.average < 3 {
+        eprintln!("--reject-outliers needs --average 3 or more to drop anything");
+    }
+    let sweep_csv = args
+    .csv
+    .clone()
+    .or_else(|| sweep.as_ref().map(|sw| {
+        let file = args.export.map_or(sw.kind().default_csv(), ExportFormat::default_file);
+        args.run_name.as_ref().map_or_else(|| file.into(), |name| format!("{}_{}", name, file).into())
+    }));
+    let sweep_progress = args.resume.clone().or_else(|| sweep_csv.as_deref().map(Sweep::default_progress));
+
+    // FPS readout for the overlay, counted over whole seconds
+    let mut fps_since = Instant::now();
+    let mut fps_frames = 0u32;
+    let mut fps: Option<u32> = None;
 
     // Use u32 here because wait_event_timeout expects u32
     const EVENT_WAIT_MS: u32 = 8;
+    // Unfocused: still redraw (the patch must follow the server) but only ~10 times a
+    // second. Minimized: nothing to draw at all. Any event wakes the loop straight away.
+    const IDLE_WAIT_MS: u32 = 100;
+    let mut unfocused = false;
+    let mut minimized = false;
+    // an I-key info box is up
+    let info_open = Arc::new(AtomicBool::new(false));
+
+    // paced to vsync: any pause or wait for events would cost refreshes
+    let frame_sleep_ms = if pacer.is_some() { 0 } else { args.frame_sleep_ms };
+    // flash colour of the current content frame, when paced
+    let mut paced_flash = None;
 
     'running: loop {
         // wait_event_timeout takes a u32; it returns None on timeout
         // handle the first event (if any) and then drain remaining queued events via poll_iter()
-        if let Some(event) = event_pump.wait_event_timeout(EVENT_WAIT_MS) {
+        let idle = !args.no_idle && (unfocused || minimized);
+        // uncapped (--frame-sleep-ms 0): take what is queued and go straight on to drawing
+        let first_event = match (idle, frame_sleep_ms) {
+            (false, 0) => event_pump.poll_event(),
+            (true, _) => event_pump.wait_event_timeout(IDLE_WAIT_MS),
+            (false, _) => event_pump.wait_event_timeout(EVENT_WAIT_MS),
+        };
+        let mut resized = false;
+        let mut display_changed = false;
+        for event in first_event.into_iter().chain(event_pump.poll_iter()) {
+            // A close button (or the WM) closing a window: a mirror just goes, the main window
+            // quits even when the WM doesn't turn it into Quit.
+            if let sdl2::event::Event::Window { win_event: sdl2::event::WindowEvent::Close, window_id, .. } = event {
+                match view::on_close(window_id, canvas.window().id()) {
+                    CloseAction::Quit => break 'running,
+                    CloseAction::CloseMirror(id) => {
+                        mirrors.retain(|(_, m)| m.window().id() != id);
+                        continue;
+                    }
+                }
+            }
+            // Mirror windows only display: keep their keys and focus changes, drop the rest
+            // (a drag or resize there means nothing here).
+            if let Some(id) = event.get_window_id() && id != canvas.window().id() {
+                match event {
+                    sdl2::event::Event::KeyDown { .. }
+                    | sdl2::event::Event::Window { win_event: sdl2::event::WindowEvent::FocusGained | sdl2::event::WindowEvent::FocusLost, .. } => {}
+                    _ => continue,
+                }
+            }
             match event {
                 sdl2::event::Event::Quit { .. }
                 | sdl2::event::Event::KeyDown {
@@ -306,122 +1571,626 @@ fn main() -> Result<(), Box<dyn Error>> {
                     ..
                 } => break 'running,
 
+                sdl2::event::Event::KeyDown { keycode: Some(sdl2::keyboard::Keycode::V), repeat: false, .. } => {
+                    show_raw = !show_raw;
+                    raw_scroll = 0;
+                    if show_raw {
+                        show_overlay = true;
+                    }
+                }
+
+                sdl2::event::Event::KeyDown { keycode: Some(key @ (sdl2::keyboard::Keycode::Up | sdl2::keyboard::Keycode::Down | sdl2::keyboard::Keycode::PageUp | sdl2::keyboard::Keycode::PageDown)), .. } if show_raw => {
+                    raw_scroll = match key {
+                        sdl2::keyboard::Keycode::Up => raw_scroll.saturating_sub(1),
+                        sdl2::keyboard::Keycode::Down => raw_scroll + 1,
+                        sdl2::keyboard::Keycode::PageUp => raw_scroll.saturating_sub(RAW_VIEW_LINES),
+                        _ => raw_scroll + RAW_VIEW_LINES,
+                    };
+                }
+
+                // Connection details for support reports, shown from a thread like the error
+                // dialogs so drawing carries on; one box at a time.
+                sdl2::event::Event::KeyDown { keycode: Some(sdl2::keyboard::Keycode::I), repeat: false, .. } if !info_open.swap(true, Ordering::SeqCst) => {
+                    let text = connection_info(&video, &canvas, worker.as_deref(), server_addr.as_deref(), args.measure_bits);
+                    let info_open = Arc::clone(&info_open);
+                    spawn(move || {
+                        dialog::info("Calibration Client Linux - connection info", &text);
+                        info_open.store(false, Ordering::SeqCst);
+                    });
+                }
+                sdl2::event::Event::KeyDown { keycode: Some(sdl2::keyboard::Keycode::X), repeat: false, .. } => {
+                    show_marker = !show_marker;
+                }
+
+                sdl2::event::Event::KeyDown { keycode: Some(sdl2::keyboard::Keycode::L), repeat: false, .. } => {
+                    show_grid = !show_grid;
+                }
+
+                // Tab steps the focus through the received shapes, then back to all of them
+                sdl2::event::Event::KeyDown { keycode: Some(sdl2::keyboard::Keycode::Tab), .. } => {
+                    if let Some(state) = worker.as_ref() {
+                        let mut w = state.write().unwrap_or_else(PoisonError::into_inner);
+                        w.focused_shape = match w.focused_shape {
+                            None if !w.shapes.is_empty() => Some(0),
+                            Some(i) if i + 1 < w.shapes.len() => Some(i + 1),
+                            _ => None,
+                        };
+                    }
+                }
+
+                sdl2::event::Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                    let view = match key {
+                        sdl2::keyboard::Keycode::R => ChannelView::Red,
+                        sdl2::keyboard::Keycode::G => ChannelView::Green,
+                        sdl2::keyboard::Keycode::B => ChannelView::Blue,
+                        sdl2::keyboard::Keycode::A => ChannelView::All,
+                        _ => continue,
+                    };
+                    // pressing the active channel again also restores full colour
+                    channel_view = if view == channel_view { ChannelView::All } else { view };
+                }
+
                 sdl2::event::Event::MouseButtonDown {
                     mouse_btn: sdl2::mouse::MouseButton::Left,
+                    x,
+                    y,
                     ..
                 } => {
-                    let now = Instant::now();
-                    if let Some(prev) = last_click_time {
-                        if now.duration_since(prev) <= dc_threshold {
-                            // Toggle fullscreen
-                            if is_fullscreen {
-                                canvas
-                                .window_mut()
-                                .set_fullscreen(sdl2::video::FullscreenType::Off)
-                                .ok();
-                                is_fullscreen = false;
-                            } else {
-                                canvas
-                                .window_mut()
-                                .set_fullscreen(sdl2::video::FullscreenType::Desktop)
-                                .ok();
-                                is_fullscreen = true;
-                            }
-                            last_click_time = None;
-                        } else {
-                            last_click_time = Some(now);
+                    drag_start = Some((x, y));
+                    drag_current = None;
+
+                    if clicks.press(Instant::now()) == Some(Click::Double) {
+                        is_fullscreen = set_fullscreen(&mut canvas, &video, !is_fullscreen, args.native_fullscreen);
+                        // relayout against the new drawable straight away
+                        resized = true;
+                    }
+                }
+
+                sdl2::event::Event::MouseMotion { x, y, .. } if drag_start.is_some() => {
+                    drag_current = Some((x, y));
+                }
+
+                sdl2::event::Event::MouseButtonUp {
+                    mouse_btn: sdl2::mouse::MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    drag_current = None;
+                    let Some((sx, sy)) = drag_start.take() else { continue };
+                    if (x - sx).abs() < MIN_DRAG_PX || (y - sy).abs() < MIN_DRAG_PX {
+                        continue;
+                    }
+
+                    // A drag is not a click: don't let it arm a double or fire a single.
+                    clicks.cancel();
+
+                    // Mouse coordinates are logical window pixels, so normalize against
+                    // the window size (not the drawable size, which differs under HiDPI).
+                    let (ww, wh) = canvas.window().size();
+                    let (ww, wh) = (ww.max(1) as f32, wh.max(1) as f32);
+                    let x0 = (sx.min(x) as f32 / ww).clamp(0.0, 1.0);
+                    let y0 = (sy.min(y) as f32 / wh).clamp(0.0, 1.0);
+                    let x1 = (sx.max(x) as f32 / ww).clamp(0.0, 1.0);
+                    let y1 = (sy.max(y) as f32 / wh).clamp(0.0, 1.0);
+                    let region = PatchRegion { x: x0, y: y0, width: x1 - x0, height: y1 - y0 };
+                    eprintln!("Patch region set to {:?}", region);
+
+                    if let Some(state) = worker.as_ref() {
+                        state.write().unwrap_or_else(PoisonError::into_inner).region = Some(region);
+                    }
+                    if args.persist_region {
+                        config.region = Some(region);
+                        if let Err(e) = config.save() {
+                            eprintln!("Failed to save config: {}", e);
+                        }
+                    }
+                }
+
+                sdl2::event::Event::Window {
+                    win_event: sdl2::event::WindowEvent::Resized(..) | sdl2::event::WindowEvent::SizeChanged(..),
+                    ..
+                } => {
+                    // A drag in progress refers to the old size; drop it. Patch layout is
+                    // recomputed from output_size() below, so the redraw happens this frame.
+                    drag_start = None;
+                    drag_current = None;
+                    resized = true;
+                    display_changed = true;
+                }
+
+                sdl2::event::Event::Window { win_event: sdl2::event::WindowEvent::Moved(..), .. } => display_changed = true,
+
+                sdl2::event::Event::RenderDeviceReset { .. } => {
+                    eprintln!("Render device reset");
+                    counters.renderer_healthy.store(false, Ordering::Relaxed);
+                }
+
+                // no textures are kept, so lost render targets need nothing but a redraw
+                sdl2::event::Event::RenderTargetsReset { .. } => resized = true,
+
+                sdl2::event::Event::Window { win_event, .. } => match win_event {
+                    sdl2::event::WindowEvent::FocusLost => unfocused = true,
+                    sdl2::event::WindowEvent::FocusGained => unfocused = false,
+                    sdl2::event::WindowEvent::Minimized => minimized = true,
+                    sdl2::event::WindowEvent::Restored | sdl2::event::WindowEvent::Shown => minimized = false,
+                    _ => {}
+                },
+
+                sdl2::event::Event::MouseButtonDown {
+                    mouse_btn: sdl2::mouse::MouseButton::Right,
+                    ..
+                } => {
+                    if let Some(state) = worker.as_ref() {
+                        state.write().unwrap_or_else(PoisonError::into_inner).region = None;
+                    }
+                    eprintln!("Patch region cleared");
+                    if args.persist_region {
+                        config.region = None;
+                        if let Err(e) = config.save() {
+                            eprintln!("Failed to save config: {}", e);
                         }
-                    } else {
-                        last_click_time = Some(now);
                     }
                 }
 
                 _ => {}
             }
+        }
 
-            // Drain any other queued events so we don't process them next frame
-            for event in event_pump.poll_iter() {
-                match event {
-                    sdl2::event::Event::Quit { .. }
-                    | sdl2::event::Event::KeyDown {
-                        keycode: Some(sdl2::keyboard::Keycode::Escape),
-                        ..
-                    } => break 'running,
-
-                    sdl2::event::Event::MouseButtonDown {
-                        mouse_btn: sdl2::mouse::MouseButton::Left,
-                        ..
-                    } => {
-                        let now = Instant::now();
-                        if let Some(prev) = last_click_time {
-                            if now.duration_since(prev) <= dc_threshold {
-                                if is_fullscreen {
-                                    canvas
-                                    .window_mut()
-                                    .set_fullscreen(sdl2::video::FullscreenType::Off)
-                                    .ok();
-                                    is_fullscreen = false;
-                                } else {
-                                    canvas
-                                    .window_mut()
-                                    .set_fullscreen(sdl2::video::FullscreenType::Desktop)
-                                    .ok();
-                                    is_fullscreen = true;
-                                }
-                                last_click_time = None;
-                            } else {
-                                last_click_time = Some(now);
-                            }
-                        } else {
-                            last_click_time = Some(now);
+        // Hot-plug or a resolution change can push a fullscreen window onto another display;
+        // put it back on the one under test.
+        if display_changed
+        && let Ok(current) = canvas.window().display_index()
+        && let Some(index) = display_to_restore(args.display, current, is_fullscreen, video.num_video_displays().unwrap_or(0)) {
+            eprintln!("Window moved to display {}, returning it to display {}", current, index);
+            set_fullscreen(&mut canvas, &video, false, args.native_fullscreen);
+            match move_to_display(&mut canvas, &video, index) {
+                Ok(()) => is_fullscreen = set_fullscreen(&mut canvas, &video, true, args.native_fullscreen),
+                Err(e) => eprintln!("Could not move back to display {}: {}", index, e),
+            }
+            resized = true;
+        }
+
+        if !is_fullscreen && !minimized {
+            let ((x, y), (w, h)) = (canvas.window().position(), canvas.window().size());
+            windowed_rect = (x, y, w, h);
+        }
+
+        // Apply remote commands queued since the last frame.
+        for rx in &control {
+            while let Ok(cmd) = rx.try_recv() {
+                match cmd {
+                    ControlCommand::Colour(c) => control_colour = Some(c),
+                    ControlCommand::Clear => control_colour = None,
+                    ControlCommand::Fullscreen(on) => {
+                        let want = on.unwrap_or(!is_fullscreen);
+                        if want != is_fullscreen {
+                            is_fullscreen = set_fullscreen(&mut canvas, &video, want, args.native_fullscreen);
+                            resized = true;
                         }
                     }
-
-                    _ => {}
+                    ControlCommand::Screenshot(path) => pending_screenshot = Some(path),
+                    ControlCommand::Quit => break 'running,
                 }
             }
         }
 
+        // A lone click fires once the double-click window has passed (held while dragging).
+        if drag_current.is_none() && clicks.poll(Instant::now()) == Some(Click::Single) {
+            match args.click_action {
+                SingleClickAction::None => {}
+                SingleClickAction::Overlay => show_overlay = !show_overlay,
+            }
+        }
+
         // One read of the worker state per frame (if any)
-        let (disconnected, shapes, worker_current_colour) = if let Some(state) = worker.as_ref() {
-            let r = state.read().unwrap();
-            (!r.connected, r.shapes.clone(), r.current_measure_colour)
-        } else {
-            (true, Vec::new(), ColorRGB::default())
-        };
+        let FrameSnapshot {
+            connected, shapes, current_measure_colour: worker_current_colour, region, measurements_received, last_measurement, label,
+            fullscreen_field, focused_shape, documents_received, hold, pending_request, patch_seq, hide_markers, disconnect_reason, last_rtt,
+        } = worker.as_ref().map(|state| state.read().unwrap_or_else(PoisonError::into_inner).snapshot()).unwrap_or_default();
+        let disconnected = !connected;
+        if documents_received != documents_seen {
+            documents_seen = documents_received;
+            show_marker = false;
+        }
+        // patches the server went through between two frames were never on screen
+        if patch_seq > patch_seq_seen + 1 {
+            patches_skipped += patch_seq - patch_seq_seen - 1;
+        }
+        patch_seq_seen = patch_seq;
+        let focused_shape = focused_shape.filter(|&i| i < shapes.len());
+
+        // Step the sweep: request readings as patches settle, write the CSV when done.
+        let mut overlay_lines: Vec<String> = Vec::new();
+        if let Some(sw) = sweep.as_mut() {
+            let now = Instant::now();
+            let rows_before = sw.rows().len();
+            let action = sw.tick(now, !disconnected, measurements_received, last_measurement.as_ref());
 
-        // Update current measure colour depending on worker state and shapes
+            // Save progress after every patch so a crash only costs the patch in hand.
+            if sw.rows().len() > rows_before
+            && let Some(path) = sweep_progress.as_ref()
+            && let Err(e) = sw.save_progress(path) {
+                eprintln!("Failed to save sweep progress to {}: {}", path.display(), e);
+            }
+
+            // Publish each completed (averaged) patch reading for other consumers.
+            if sw.rows().len() > rows_before
+            && let (Some(state), Some(row)) = (worker.as_ref(), sw.rows().last()) {
+                state.write().unwrap_or_else(PoisonError::into_inner).averaged_measurement = Some(MeasurementResult {
+                    red: row.colour.red,
+                    green: row.colour.green,
+                    blue: row.colour.blue,
+                    x: row.x,
+                    y: row.y,
+                    y_lum: row.y_lum,
+                    label: row.label.clone(),
+                    fullscreen: false,
+                    hide_markers: false,
+                    hold: None,
+                    shapes: Vec::new(),
+                    error: None,
+                });
+            }
+
+            match action {
+                SweepAction::Request(colour) => {
+                    show_marker = false;
+                    if let Some(state) = worker.as_ref()
+                    && let Err(e) = lan::request_measurement(state, colour) {
+                        eprintln!("Failed to send measure request: {}", e);
+                        sw.retry(now);
+                    }
+                }
+                action @ (SweepAction::Finished | SweepAction::Aborted | SweepAction::TimedOut) => {
+                    // Partial results are still written on abort.
+                    if let Some(path) = sweep_csv.as_ref() {
+                        match args.export.map_or_else(|| sw.write_csv(path), |format| sw.write_export(path, format)) {
+                            Ok(()) => eprintln!("Sweep results ({} rows) written to {}", sw.rows().len(), path.display()),
+                            Err(e) => eprintln!("Failed to write {}: {}", path.display(), e),
+                        }
+                    }
+                    // A finished sweep needs no resuming; an aborted one keeps its file.
+                    if let Some(path) = sweep_progress.as_ref() {
+                        if action == SweepAction::Finished {
+                            let _ = std::fs::remove_file(path);
+                        } else if path.exists() {
+                            eprintln!("Continue later with --resume {}", path.display());
+                        }
+                    }
+                    match action {
+                        SweepAction::Aborted => return Err(format!("{:?} sweep aborted: link to ColourSpace lost", sw.kind()).into()),
+                        SweepAction::TimedOut => return Err(format!("{:?} sweep aborted: no reading within --measure-timeout-ms", sw.kind()).into()),
+                        _ => {}
+                    }
+                    break 'running;
+                }
+                SweepAction::None => {}
+            }
+            overlay_lines.extend(sw.status_lines());
+        }
+        if let Some(c) = control_colour {
+            overlay_lines.push(format!("remote colour {},{},{} ({}-bit)", c.red, c.green, c.blue, c.depth_bits));
+        }
+        if disconnected && let Some(reason) = disconnect_reason {
+            overlay_lines.push(format!("disconnected: {}", reason));
+        }
+        if let Some(label) = label.as_deref() {
+            overlay_lines.push(format!("patch: {}", label));
+        }
+        // a converted patch that had to be clamped isn't the colour the server asked for
+        overlay_lines.extend(shapes.iter().filter_map(ShapeInstruction::gamut_warning));
+        if let Some(rtt) = last_rtt {
+            overlay_lines.push(format!("last reading {:.1} ms after its request", rtt.as_secs_f64() * 1000.0));
+        }
+        // Running colour difference against --target, from the latest full reading.
+        if let Some((tx, ty, t_lum)) = args.target {
+            let reading = last_measurement.as_ref().and_then(|m| Some((m.x?, m.y?, m.y_lum?)));
+            overlay_lines.push(match reading {
+                Some((x, y, lum)) => {
+                    let white = args.reference_white_y;
+                    let de = args.delta_e.delta_e(delta_e::xyy_to_lab(x, y, lum, white), delta_e::xyy_to_lab(tx, ty, t_lum, white));
+                    let verdict = if de <= args.delta_e_tolerance { "match" } else { "OFF" };
+                    format!("{} {:.2} to target {:.4},{:.4},{:.2} - {} (tolerance {:.2})", args.delta_e.name(), de, tx, ty, t_lum, verdict, args.delta_e_tolerance)
+                }
+                None => format!("{} to target {:.4},{:.4},{:.2}: no reading yet", args.delta_e.name(), tx, ty, t_lum),
+            });
+        }
+        if let Some(i) = focused_shape {
+            let c = shapes[i].color();
+            let size = match &shapes[i] {
+                ShapeInstruction::Rectangle(rect) => format!("{:.3}x{:.3}", rect.geometry.width, rect.geometry.height),
+                ShapeInstruction::Circle(circle) => format!("circle r {:.3}", circle.geometry.r),
+                ShapeInstruction::Ellipse(ellipse) => format!("ellipse {:.3}x{:.3}", ellipse.geometry.rx, ellipse.geometry.ry),
+            };
+            overlay_lines.push(format!(
+                "shape {}/{}: {},{},{} ({}-bit) {} - Tab for next",
+                i + 1, shapes.len(), c.red, c.green, c.blue, c.depth_bits, size
+            ));
+        }
+        if show_raw {
+            let raw = worker.as_ref().and_then(|state| state.read().unwrap_or_else(PoisonError::into_inner).last_raw.clone());
+            match raw {
+                Some(raw) => {
+                    // hard-wrap long lines (frames are often one long line) and show a window of them
+                    let lines: Vec<String> = raw
+                    .lines()
+                    .flat_map(|line| {
+                        let chars: Vec<char> = line.chars().collect();
+                        chars.chunks(RAW_VIEW_WIDTH).map(|c| c.iter().collect::<String>()).collect::<Vec<_>>()
+                    })
+                    .collect();
+                    raw_scroll = raw_scroll.min(lines.len().saturating_sub(RAW_VIEW_LINES));
+                    let end = (raw_scroll + RAW_VIEW_LINES).min(lines.len());
+                    overlay_lines.push(format!("raw frame, {} bytes, lines {}-{} of {} - V closes", raw.len(), raw_scroll + 1, end, lines.len()));
+                    overlay_lines.extend(lines[raw_scroll..end].iter().cloned());
+                }
+                None if !args.keep_raw => overlay_lines.push("raw frame view needs --keep-raw".to_string()),
+                None => overlay_lines.push("no frame received yet".to_string()),
+            }
+        }
+        if pending_request.is_some() {
+            let step = (spinner_epoch.elapsed().as_millis() / 150) as usize % SPINNER.len();
+            overlay_lines.push(format!("measuring {} - keep the probe still", SPINNER[step]));
+        }
+        if show_marker {
+            overlay_lines.push("alignment marker on - X hides (auto-hides on measurement)".to_string());
+        }
+        if channel_view != ChannelView::All {
+            overlay_lines.push(format!("{:?} channel only (display) - A restores", channel_view));
+        }
+
+        // Update current measure colour depending on worker state and shapes. A colour we
+        // asked to have measured wins until its reading is in, then the server's shapes.
         if disconnected {
-            if worker.is_none() {
-                // keep whatever current_measure_colour already is
-            } else {
+            if worker.is_some() {
                 current_measure_colour = worker_current_colour;
             }
+        } else if let Some(requested) = pending_request {
+            current_measure_colour = requested;
+        } else if shapes.is_empty() {
+            current_measure_colour = worker_current_colour;
+        } else if let Some(i) = focused_shape {
+            current_measure_colour = shapes[i].color();
         } else {
-            if shapes.is_empty() {
-                current_measure_colour = worker_current_colour;
-            } else {
-                current_measure_colour = select_measure_colour(&shapes).unwrap_or(current_measure_colour);
+            let (cw, ch) = canvas.output_size().unwrap_or_default();
+            current_measure_colour = select_measure_colour(&shapes, args.measure_target, region_to_pixels(region, cw, ch)).unwrap_or(current_measure_colour);
+        }
+
+        // Keep the patch on screen until its hold is up; the latest document then replaces
+        // it (anything that arrived in between is skipped).
+        let holding = !disconnected && shown_patch.as_ref().is_some_and(|p| p.document != documents_received && p.since.elapsed() < p.hold);
+        if !disconnected && !holding {
+            let (since, hold) = match shown_patch.as_ref() {
+                Some(p) if p.document == documents_received => (p.since, p.hold),
+                _ => (Instant::now(), hold.unwrap_or(min_hold)),
+            };
+            shown_patch = Some(ShownPatch { document: documents_received, shapes: shapes.clone(), fullscreen: fullscreen_field, colour: current_measure_colour, since, hold });
+        }
+        let held = shown_patch.as_ref().filter(|_| holding);
+
+        // This frame's flash colour, picked once so the screen, overlay and hook agree.
+        // Paced, it only changes when a new content frame starts, alternating per frame.
+        let flash_colour = match pacer.as_mut() {
+            Some(p) => {
+                if p.tick() {
+                    paced_flash = flash.as_ref().map(|f| f.colour_for_frame(p.frame()));
+                }
+                paced_flash
+            }
+            None => flash.as_ref().map(|f| f.colour_at(Instant::now())),
+        };
+        if let Some(f) = flash.as_ref() {
+            match pacer.as_ref() {
+                Some(p) => overlay_lines.push(format!("flashing {},{},{} / {},{},{} every {} refresh(es)", f.a.red, f.a.green, f.a.blue, f.b.red, f.b.green, f.b.blue, p.divisor())),
+                None => overlay_lines.push(format!("flashing {},{},{} / {},{},{} at {} Hz", f.a.red, f.a.green, f.a.blue, f.b.red, f.b.green, f.b.blue, f.hz)),
             }
         }
+        if let Some(p) = pacer.as_ref() {
+            let refresh = refresh_rate(&video, &canvas);
+            overlay_lines.push(match pacing::content_hz(refresh, p.divisor()) {
+                Some(hz) => format!("paced: 1/{} of {} Hz = {:.2} Hz", p.divisor(), refresh, hz),
+                None => format!("paced: 1/{} of an unknown refresh rate", p.divisor()),
+            });
+        }
 
-        // Draw
-        let (cw, ch) = canvas.output_size()?;
-        if !disconnected && !shapes.is_empty() {
-            draw_shapes(&mut canvas, &shapes, cw, ch);
-        } else {
-            let c = current_measure_colour;
-            // downscale before giving to SDL using the helper
-            let (r8, g8, b8) = color_to_u8_tuple(c);
-            canvas.set_draw_color(Color::RGB(r8, g8, b8));
-            canvas.clear();
+        // Beep when the patch changes (flash, else sweep patch if one is running, else the server's colour).
+        // what the sweep has on screen this frame: its patch, or a cross-fade into it
+        let sweep_patch = sweep.as_ref().and_then(|sw| sw.displayed_patch(Instant::now()));
+        let shown = flash_colour.or(sweep.as_ref().and_then(|sw| sw.current_patch())).or(held.map(|p| p.colour)).unwrap_or(current_measure_colour);
+        if last_shown_colour.replace(shown).is_some_and(|prev| prev != shown)
+        && let Some(b) = beeper.as_mut() {
+            b.beep();
+        }
+
+        // Nothing visible to draw; the worker keeps draining the socket meanwhile.
+        if minimized && !args.no_idle {
+            continue;
+        }
+
+        // Rebuild a lost renderer (device reset, failed draw) before drawing into it again.
+        if !counters.renderer_healthy.load(Ordering::Relaxed) {
+            recovery_streak += 1;
+            let render_recoveries = counters.render_recoveries.fetch_add(1, Ordering::Relaxed) + 1;
+            if recovery_streak > MAX_RENDER_RECOVERIES {
+                return Err(format!("renderer could not be recovered after {} attempts", MAX_RENDER_RECOVERIES).into());
+            }
+            eprintln!("Recreating renderer (recovery attempt {})", render_recoveries);
+            canvas = recreate_canvas(canvas, &video, args.renderer)?;
+            canvas.window_mut().set_minimum_size(MIN_W, MIN_H)?;
+            if is_fullscreen {
+                is_fullscreen = set_fullscreen(&mut canvas, &video, true, args.native_fullscreen);
+            }
+            counters.renderer_healthy.store(true, Ordering::Relaxed);
+        }
+        let render_recoveries = counters.render_recoveries.load(Ordering::Relaxed);
+        if render_recoveries > 0 {
+            overlay_lines.push(format!("renderer recovered {} time(s)", render_recoveries));
+        }
+        if patches_skipped > 0 {
+            overlay_lines.push(format!("{} server patch(es) replaced before they were shown", patches_skipped));
+        }
+        if let Some(fps) = fps {
+            overlay_lines.push(format!("{} fps", fps));
+        }
+
+        // What the server asked for, or what stands in for it while the link is down.
+        if !disconnected && !holding {
+            last_good = Some((shapes.clone(), fullscreen_field, current_measure_colour));
+        }
+        // Offline (--pattern, --solid, --flash) there is no link to lose: always show our own colour.
+        let live = PatchView { shapes: &shapes, fullscreen: fullscreen_field, colour: current_measure_colour };
+        let held_view = held.map(|p| PatchView { shapes: &p.shapes, fullscreen: p.fullscreen, colour: p.colour });
+        let good_view = last_good.as_ref().map(|(s, f, c)| PatchView { shapes: s, fullscreen: *f, colour: *c });
+        let PatchView { shapes: view_shapes, fullscreen: view_fullscreen, colour: view_colour } =
+            view::on_screen(disconnected && worker.is_some(), args.on_disconnect, pending_request, held_view, live, good_view);
+
+        let view_focus = if disconnected || holding { None } else { focused_shape };
+
+        // Draw. Every step reports failure so a dead renderer is noticed. Mirrors get the
+        // same picture without the operator's overlay and drag outline.
+        let draw = |canvas: &mut sdl2::render::Canvas<sdl2::video::Window>, primary: bool| -> Result<(u32, u32), String> {
+            let (cw, ch) = canvas.output_size()?;
+            if let Some(colour) = flash_colour {
+                // local timing only; nothing else gets to change the patch while flashing
+                fill_field(canvas, colour, region, channel_view, backdrop, cw, ch)?;
+            } else if let Some(patch) = sweep_patch {
+                // the sweep decides what is on screen, not the server
+                match sweep.as_ref().and_then(|sw| sw.current_cell()) {
+                    Some(cell) => fill_cell(canvas, patch, cell, region_to_pixels(region, cw, ch), channel_view, backdrop)?,
+                    None => fill_field(canvas, patch, region, channel_view, backdrop, cw, ch)?,
+                }
+            } else if let Some(colour) = control_colour {
+                fill_field(canvas, colour, region, channel_view, backdrop, cw, ch)?;
+            } else if let Some(bars) = bars.as_deref() {
+                draw_bars(canvas, bars, region_to_pixels(region, cw, ch), channel_view, backdrop)?;
+            } else if view_fullscreen {
+                // explicit flat field: whole drawable, region deliberately ignored
+                fill_field(canvas, view_colour, None, channel_view, backdrop, cw, ch)?;
+            } else if !view_shapes.is_empty() {
+                draw_shapes(canvas, view_shapes, view_focus, region_to_pixels(region, cw, ch), args.patch_offset, channel_view, backdrop)?;
+            } else {
+                fill_field(canvas, view_colour, region, channel_view, backdrop, cw, ch)?;
+            }
+
+            if show_grid {
+                grid.draw(canvas, cw, ch)?;
+            }
+
+            // Alignment marker on the centre of what is measured: the cell during a uniformity
+            // scan, the tracked patch (offset included) for server shapes, else the region.
+            if show_marker && !hide_markers && !sweep.as_ref().is_some_and(|sw| sw.measuring()) {
+                let area = region_to_pixels(region, cw, ch);
+                let shapes_drawn = sweep.as_ref().and_then(|sw| sw.current_patch()).is_none() && control_colour.is_none() && !view_fullscreen && view_focus.is_none();
+                let target = if let Some(cell) = sweep.as_ref().and_then(|sw| sw.current_cell()) {
+                    cell_to_pixels(area, cell)
+                } else if shapes_drawn && let Some(shape) = select_measure_shape(view_shapes, args.measure_target, area) {
+                    shape_rect(area, shape, args.patch_offset)
+                } else {
+                    area
+                };
+                let centre = target.center();
+                marker.draw(canvas, centre.x(), centre.y())?;
+            }
+
+            if !primary {
+                return Ok((cw, ch));
+            }
+
+            // Outline the region being dragged (mouse coords are logical, so scale to drawable)
+            if let (Some((sx, sy)), Some((x, y))) = (drag_start, drag_current) {
+                let (scale_x, scale_y) = hidpi_scale(canvas)?;
+                let left = (sx.min(x) as f32 * scale_x) as i32;
+                let top = (sy.min(y) as f32 * scale_y) as i32;
+                let rw = ((x - sx).unsigned_abs() as f32 * scale_x).max(1.0) as u32;
+                let rh = ((y - sy).unsigned_abs() as f32 * scale_y).max(1.0) as u32;
+                canvas.set_draw_color(Color::RGB(255, 255, 255));
+                canvas.draw_rect(Rect::new(left, top, rw, rh))?;
+            }
+
+            // the server's <measuring/> hint wins over the click toggle (and V) until a frame without it
+            if show_overlay && !hide_markers {
+                overlay::draw_panel(canvas, &overlay_lines, overlay::scale_for(ch))?;
+            }
+            Ok((cw, ch))
+        };
+        let drawn = draw(&mut canvas, true);
+
+        let (cw, ch) = match drawn {
+            Ok(size) => {
+                recovery_streak = 0;
+                size
+            }
+            Err(e) => {
+                eprintln!("Drawing failed, renderer lost: {}", e);
+                counters.renderer_healthy.store(false, Ordering::Relaxed);
+                continue;
+            }
+        };
+
+        if let Some(path) = pending_screenshot.take() {
+            match save_screenshot(&canvas, &path) {
+                Ok(()) => eprintln!("Screenshot saved to {}", path.display()),
+                Err(e) => eprintln!("Failed to save screenshot {}: {}", path.display(), e),
+            }
         }
 
         // Present once per frame (consistent timing fixes the double-click quirk)
         canvas.present();
+        counters.frames_drawn.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        fps_frames += 1;
+        if fps_since.elapsed() >= Duration::from_secs(1) {
+            fps = Some((fps_frames as f64 / fps_since.elapsed().as_secs_f64()).round() as u32);
+            (fps_since, fps_frames) = (Instant::now(), 0);
+        }
+        // as soon as possible after present, before the mirrors (see hook.rs on timing)
+        if let Some(hook) = present_hook.as_mut() {
+            hook.presented(shown);
+        }
+        if let Some(mirror) = shm_mirror.as_mut() {
+            let measuring = pending_request.is_some() || sweep.as_ref().is_some_and(|sw| sw.measuring());
+            let flags = [(!disconnected, shm::FLAG_CONNECTED), (measuring, shm::FLAG_MEASURING), (worker.is_none(), shm::FLAG_OFFLINE)]
+            .iter()
+            .filter(|(on, _)| *on)
+            .fold(0, |flags, (_, flag)| flags | flag);
+            if let Err(e) = mirror.update(shown, flags) {
+                eprintln!("--shm: update failed, no longer publishing: {}", e);
+                shm_mirror = None;
+            }
+        }
+
+        // a mirror that can't draw is closed rather than nursed back like the main window
+        mirrors.retain_mut(|(index, mirror)| match draw(mirror, false) {
+            Ok(_) => {
+                mirror.present();
+                true
+            }
+            Err(e) => {
+                eprintln!("Closing mirror on display {}: {}", index, e);
+                false
+            }
+        });
+
+        if resized {
+            eprintln!("Window resized, drawable now {}x{}", cw, ch);
+        } else if frame_sleep_ms > 0 {
+            // small sleep to avoid burning CPU in pathological cases
+            // (skipped right after a resize so the new layout shows without delay)
+            sleep(Duration::from_millis(frame_sleep_ms));
+        }
+    }
+
+    if !args.no_restore_window && let Some(geometry) = window_geometry(&canvas, windowed_rect, is_fullscreen) && config.window != Some(geometry) {
+        config.window = Some(geometry);
+        if let Err(e) = config.save() {
+            eprintln!("Failed to save the window position: {}", e);
+        }
+    }
 
-        // small sleep to avoid burning CPU in pathological cases
-        sleep(Duration::from_millis(1));
+    // Give the display its desktop mode back before the window goes away (SDL would
+    // too on teardown, but not if something keeps the process alive past here).
+    if is_fullscreen && args.native_fullscreen {
+        set_fullscreen(&mut canvas, &video, false, true);
     }
 
     Ok(())
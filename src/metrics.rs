@@ -0,0 +1,176 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::lan::SharedState;
+
+/// Counters owned by the render loop; everything else comes from `SharedState`.
+#[derive(Debug)]
+pub struct Counters {
+    pub frames_drawn: AtomicU64,
+    /// cleared by a device reset or failed draw, set again once the renderer is rebuilt
+    pub renderer_healthy: AtomicBool,
+    pub render_recoveries: AtomicU64,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self { frames_drawn: AtomicU64::new(0), renderer_healthy: AtomicBool::new(true), render_recoveries: AtomicU64::new(0) }
+    }
+}
+
+/// How long a metrics client gets to send its request and take the reply.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serve Prometheus text exposition on `GET /metrics` (plain HTTP/1.0, one request
+/// per connection, each on its own thread so a stalled client doesn't hold up the
+/// next scrape). `state` is None when running without a server (offline pattern).
+pub fn spawn_server(addr: SocketAddr, counters: Arc<Counters>, state: Option<Arc<RwLock<SharedState>>>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let (counters, state) = (counters.clone(), state.clone());
+                    thread::spawn(move || {
+                        if let Err(e) = respond(stream, &counters, state.as_deref()) {
+                            eprintln!("Metrics client error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Metrics listener error: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(stream: TcpStream, counters: &Counters, state: Option<&RwLock<SharedState>>) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut out = stream.try_clone()?;
+    let mut request_line = String::new();
+    BufReader::new(stream).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(counters, state)),
+        _ => ("404 Not Found", "only GET /metrics is served\n".to_string()),
+    };
+    write!(
+        out,
+        "HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    out.flush()
+}
+
+fn render(counters: &Counters, state: Option<&RwLock<SharedState>>) -> String {
+    let (connected, measurements, reconnects, parse_errors, restarts) = match state {
+        Some(state) => {
+            let r = state.read().unwrap_or_else(PoisonError::into_inner);
+            (r.connected, r.measurements_received, r.reconnects, r.parse_errors, r.worker_restarts)
+        }
+        None => (false, 0, 0, 0, 0),
+    };
+
+    let metrics: [(&str, &str, &str, u64); 8] = [
+        ("colourspace_frames_drawn_total", "counter", "Frames presented by the render loop.", counters.frames_drawn.load(Ordering::Relaxed)),
+        ("colourspace_renderer_healthy", "gauge", "0 from a render device reset or failed draw until the renderer is rebuilt.", counters.renderer_healthy.load(Ordering::Relaxed) as u64),
+        ("colourspace_render_recoveries_total", "counter", "Times the renderer was rebuilt after a device reset or failed draw.", counters.render_recoveries.load(Ordering::Relaxed)),
+        ("colourspace_measurements_received_total", "counter", "Documents received that carried a reading.", measurements),
+        ("colourspace_reconnects_total", "counter", "Successful reconnects after the link dropped.", reconnects),
+        ("colourspace_parse_errors_total", "counter", "Frames skipped because they failed to parse.", parse_errors),
+        ("colourspace_worker_restarts_total", "counter", "Times the network worker exited and was restarted.", restarts),
+        ("colourspace_connected", "gauge", "1 while the link to the server is up.", connected as u64),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of metric `name` in an exposition `body`.
+    fn sample(body: &str, name: &str) -> Option<u64> {
+        body.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+    }
+
+    #[test]
+    fn renderer_health_is_exported() {
+        let counters = Counters::default();
+        let body = render(&counters, None);
+        assert_eq!(sample(&body, "colourspace_renderer_healthy"), Some(1));
+        assert_eq!(sample(&body, "colourspace_render_recoveries_total"), Some(0));
+
+        counters.renderer_healthy.store(false, Ordering::Relaxed);
+        counters.render_recoveries.fetch_add(2, Ordering::Relaxed);
+        let body = render(&counters, None);
+        assert_eq!(sample(&body, "colourspace_renderer_healthy"), Some(0));
+        assert_eq!(sample(&body, "colourspace_render_recoveries_total"), Some(2));
+    }
+
+    /// Send one request line to a server on `port` and return the status line and body.
+    fn get(port: u16, request_line: &str) -> (String, String) {
+        use std::io::Read;
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        // well under CLIENT_TIMEOUT: a reply held up behind another client fails the test
+        stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        write!(stream, "{}\r\n\r\n", request_line).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let length: usize = head.lines().find_map(|l| l.strip_prefix("Content-Length: ")).unwrap().parse().unwrap();
+        assert_eq!(length, body.len());
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn a_silent_client_does_not_block_the_endpoint() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        spawn_server(SocketAddr::from(([127, 0, 0, 1], port)), Arc::new(Counters::default()), None).unwrap();
+        // connects and never sends its request line
+        let _silent = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (status, _) = get(port, "GET /metrics HTTP/1.0");
+        assert_eq!(status, "HTTP/1.0 200 OK");
+    }
+
+    #[test]
+    fn endpoint_serves_parseable_exposition() {
+        // a free port: taken and let go again for the server to bind
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let counters = Arc::new(Counters::default());
+        counters.frames_drawn.store(42, Ordering::Relaxed);
+        let state = Arc::new(RwLock::new(SharedState { connected: true, measurements_received: 7, ..SharedState::default() }));
+        spawn_server(SocketAddr::from(([127, 0, 0, 1], port)), counters, Some(state)).unwrap();
+
+        let (status, body) = get(port, "GET /metrics HTTP/1.0");
+        assert_eq!(status, "HTTP/1.0 200 OK");
+        // every sample comes after its HELP and TYPE, with a valid name and a number
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len() % 3, 0);
+        for group in lines.chunks(3) {
+            let name = group[2].split(' ').next().unwrap();
+            assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') && !name.starts_with(|c: char| c.is_ascii_digit()), "{}", name);
+            assert!(group[0].starts_with(&format!("# HELP {} ", name)), "{}", group[0]);
+            let kind = group[1].strip_prefix(&format!("# TYPE {} ", name)).unwrap();
+            assert!((kind == "counter" && name.ends_with("_total")) || kind == "gauge", "{} {}", name, kind);
+            group[2][name.len() + 1..].parse::<f64>().unwrap();
+        }
+        assert_eq!(sample(&body, "colourspace_frames_drawn_total"), Some(42));
+        assert_eq!(sample(&body, "colourspace_measurements_received_total"), Some(7));
+        assert_eq!(sample(&body, "colourspace_connected"), Some(1));
+
+        assert_eq!(get(port, "GET / HTTP/1.0").0, "HTTP/1.0 404 Not Found");
+    }
+}
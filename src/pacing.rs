@@ -0,0 +1,68 @@
+// Content paced to the display refresh (--sync-divisor N). The renderer runs with vsync,
+// so each present takes exactly one refresh; the loop then presents every refresh and
+// only lets locally timed content (--flash) move on every Nth one. Counting refreshes
+// instead of reading a clock keeps the pattern locked to the panel: no frame is ever
+// shown for N - 1 or N + 1 refreshes because a timer landed near a vblank.
+
+/// Counts presented refreshes and groups them into content frames of `divisor` refreshes.
+#[derive(Debug, Clone, Copy)]
+pub struct RefreshPacer {
+    divisor: u32,
+    refreshes: u64,
+}
+
+impl RefreshPacer {
+    /// A divisor of 0 is taken as 1 (every refresh).
+    pub fn new(divisor: u32) -> Self {
+        Self { divisor: divisor.max(1), refreshes: 0 }
+    }
+
+    pub fn divisor(&self) -> u32 {
+        self.divisor
+    }
+
+    /// Count the refresh about to be presented; true when it starts a new content frame
+    /// (refreshes 0, N, 2N, ...).
+    pub fn tick(&mut self) -> bool {
+        let starts = self.refreshes.is_multiple_of(self.divisor as u64);
+        self.refreshes += 1;
+        starts
+    }
+
+    /// Index of the content frame the last counted refresh belongs to.
+    pub fn frame(&self) -> u64 {
+        self.refreshes.saturating_sub(1) / self.divisor as u64
+    }
+}
+
+/// Content frames per second at `refresh_hz`, or None when the refresh rate is unknown
+/// (SDL reports 0 for some drivers and virtual displays).
+pub fn content_hz(refresh_hz: i32, divisor: u32) -> Option<f64> {
+    (refresh_hz > 0).then(|| refresh_hz as f64 / divisor.max(1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_nth_refresh_starts_a_content_frame() {
+        let mut pacer = RefreshPacer::new(3);
+        let ticks: Vec<(bool, u64)> = (0..8).map(|_| { let starts = pacer.tick(); (starts, pacer.frame()) }).collect();
+        assert_eq!(ticks, [(true, 0), (false, 0), (false, 0), (true, 1), (false, 1), (false, 1), (true, 2), (false, 2)]);
+        // 0 is every refresh, like 1
+        let mut every = RefreshPacer::new(0);
+        assert_eq!(every.divisor(), 1);
+        assert!((0..5).all(|_| every.tick()));
+        assert_eq!(every.frame(), 4);
+        assert_eq!(RefreshPacer::new(2).frame(), 0);
+    }
+
+    #[test]
+    fn content_rate_needs_a_known_refresh_rate() {
+        assert_eq!(content_hz(120, 2), Some(60.0));
+        assert_eq!(content_hz(60, 0), Some(60.0));
+        assert_eq!(content_hz(0, 2), None);
+        assert_eq!(content_hz(-1, 1), None);
+    }
+}
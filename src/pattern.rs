@@ -0,0 +1,164 @@
+use std::str::FromStr;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Point;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::lan::ColorRGB;
+
+/// Offline test patterns, drawn without connecting to a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// full-screen line grid for geometry/convergence checks
+    Grid,
+    /// SMPTE (EG 1) colour bars: seven bars, reverse-blue strip, -I/white/+Q and PLUGE
+    Smpte,
+    /// EBU colour bars: eight full-height bars, white to black
+    Ebu,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "grid" => Ok(Pattern::Grid),
+            "smpte" => Ok(Pattern::Smpte),
+            "ebu" => Ok(Pattern::Ebu),
+            other => Err(format!("unknown pattern '{}', expected: grid, smpte, ebu", other)),
+        }
+    }
+}
+
+/// Parse an 8-bit `r,g,b` triple, e.g. `255,255,255`.
+pub fn parse_rgb(value: &str) -> Result<Color, String> {
+    let parts: Vec<u8> = value
+    .split(',')
+    .map(|p| p.trim().parse::<u8>())
+    .collect::<Result<_, _>>()
+    .map_err(|_| format!("bad colour '{}', expected r,g,b with 0-255 each", value))?;
+    match parts[..] {
+        [r, g, b] => Ok(Color::RGB(r, g, b)),
+        _ => Err(format!("bad colour '{}', expected r,g,b with 0-255 each", value)),
+    }
+}
+
+/// `cols` x `rows` cells across the whole drawable, outer border included.
+#[derive(Debug, Clone, Copy)]
+pub struct Grid {
+    pub rows: u32,
+    pub cols: u32,
+    /// line width in drawable pixels
+    pub thickness: u32,
+    pub colour: Color,
+}
+
+impl Grid {
+    pub fn draw(&self, canvas: &mut Canvas<Window>, w: u32, h: u32) -> Result<(), String> {
+        let (w, h) = (w.max(1), h.max(1));
+        let t = self.thickness.clamp(1, w.min(h));
+        canvas.set_draw_color(self.colour);
+
+        // Line i sits at i/n of the way across; thick lines grow around that
+        // position but are kept on screen so the border stays fully visible.
+        let starts = |n: u32, len: u32| {
+            let n = n.max(1);
+            (0..=n).map(move |i| {
+                let pos = (i as f32 / n as f32 * (len - 1) as f32).round() as i64;
+                (pos - t as i64 / 2).clamp(0, (len - t) as i64) as i32
+            })
+        };
+
+        for x in starts(self.cols, w) {
+            for k in 0..t as i32 {
+                canvas.draw_line(Point::new(x + k, 0), Point::new(x + k, h as i32 - 1))?;
+            }
+        }
+        for y in starts(self.rows, h) {
+            for k in 0..t as i32 {
+                canvas.draw_line(Point::new(0, y + k), Point::new(w as i32 - 1, y + k))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One bar of a colour bar pattern, as fractions (0..1) of the area it is drawn in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bar {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub colour: ColorRGB,
+}
+
+/// The bars of `pattern` (Smpte or Ebu; empty for the grid) with the colours at `level`
+/// (0.75 or 1.0 of full scale) in code values at `bits`. Full-range RGB: the SMPTE -I/+Q
+/// chips are the usual RGB renderings, and the PLUGE's below-black step can't go under
+/// code 0, so it reads black / black / +4%.
+pub fn colour_bars(pattern: Pattern, level: f64, bits: u8) -> Vec<Bar> {
+    let rgb = |r: f64, g: f64, b: f64| ColorRGB::from_normalized(r, g, b, bits);
+    let l = level;
+    // white, yellow, cyan, green, magenta, red, blue at `level`
+    let [grey, yellow, cyan, green, magenta, red, blue] =
+    [(1, 1, 1), (1, 1, 0), (0, 1, 1), (0, 1, 0), (1, 0, 1), (1, 0, 0), (0, 0, 1)].map(|(r, g, b)| rgb(r as f64 * l, g as f64 * l, b as f64 * l));
+    let black = rgb(0.0, 0.0, 0.0);
+    let white = rgb(1.0, 1.0, 1.0);
+    let row = |top: f32, bottom: f32, cells: &[(f32, ColorRGB)]| {
+        let mut left = 0.0;
+        cells.iter().map(|&(width, colour)| {
+            let bar = Bar { left, top, right: (left + width).min(1.0), bottom, colour };
+            left += width;
+            bar
+        }).collect::<Vec<_>>()
+    };
+    let seventh = 1.0 / 7.0;
+
+    match pattern {
+        Pattern::Grid => Vec::new(),
+        Pattern::Smpte => {
+            let minus_i = rgb(0.0, 33.0 / 255.0, 76.0 / 255.0);
+            let plus_q = rgb(50.0 / 255.0, 0.0, 106.0 / 255.0);
+            let plus_4 = rgb(0.04, 0.04, 0.04);
+            let mut bars = row(0.0, 2.0 / 3.0, &[grey, yellow, cyan, green, magenta, red, blue].map(|c| (seventh, c)));
+            bars.extend(row(2.0 / 3.0, 0.75, &[blue, black, magenta, black, cyan, black, grey].map(|c| (seventh, c))));
+            // four chips 5/4 of a bar wide, then the PLUGE (three steps in one bar) and black
+            let chip = seventh * 1.25;
+            let step = seventh / 3.0;
+            bars.extend(row(0.75, 1.0, &[(chip, minus_i), (chip, white), (chip, plus_q), (chip, black), (step, black), (step, black), (step, plus_4), (seventh, black)]));
+            bars
+        }
+        // EBU 100/0/75/0 at 75%: white stays at 100%, only the colours drop
+        Pattern::Ebu => row(0.0, 1.0, &[white, yellow, cyan, green, magenta, red, blue, black].map(|c| (1.0 / 8.0, c))),
+    }
+}
+
+/// Probe alignment marker: a crosshair with a ring, centred on the patch.
+#[derive(Debug, Clone, Copy)]
+pub struct Marker {
+    /// crosshair arm length and ring radius, in drawable pixels
+    pub size: u32,
+    pub colour: Color,
+}
+
+impl Marker {
+    pub fn draw(&self, canvas: &mut Canvas<Window>, cx: i32, cy: i32) -> Result<(), String> {
+        let r = self.size.max(2) as i32;
+        canvas.set_draw_color(self.colour);
+        canvas.draw_line(Point::new(cx - r, cy), Point::new(cx + r, cy))?;
+        canvas.draw_line(Point::new(cx, cy - r), Point::new(cx, cy + r))?;
+
+        // ring at half the arm length; enough points that it stays closed at any size
+        let ring = r / 2;
+        let steps = (ring * 8).max(16);
+        let points: Vec<Point> = (0..steps)
+        .map(|i| {
+            let a = i as f32 / steps as f32 * std::f32::consts::TAU;
+            Point::new(cx + (a.cos() * ring as f32).round() as i32, cy + (a.sin() * ring as f32).round() as i32)
+        })
+        .collect();
+        canvas.draw_points(&points[..])
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::lan::ColorRGB;
+
+// The displayed patch and link state in a small file meant to be mmap'ed by a
+// measurement controller in another process (--shm NAME). On Linux NAME lives in
+// /dev/shm (tmpfs, so it never touches a disk); a NAME with a '/' is used as a path.
+// Written with plain file writes: a reader's mapping sees them as soon as each returns.
+//
+// Layout, 32 bytes, little-endian:
+//   0  magic    4 bytes  "CSPM"
+//   4  version  u32      1
+//   8  seqno    u64      odd while an update is being written, +2 per update
+//  16  red      u16      code values at `depth`
+//  18  green    u16
+//  20  blue     u16
+//  22  depth    u8       bits per channel
+//  23  flags    u8       see FLAG_*
+//  24  reserved 8 bytes  zero
+//
+// Readers: read seqno, and if it is even read the rest and then seqno again; if it
+// changed (or was odd) an update raced the read, so try again. The region is only
+// rewritten when something in it changes, so a new seqno always means a new patch or
+// state.
+
+pub const MAGIC: &[u8; 4] = b"CSPM";
+pub const VERSION: u32 = 1;
+pub const SIZE: usize = 32;
+
+/// Connected to the server.
+pub const FLAG_CONNECTED: u8 = 1;
+/// A reading has been requested and hasn't arrived yet.
+pub const FLAG_MEASURING: u8 = 2;
+/// No server at all (--pattern, --solid, --flash).
+pub const FLAG_OFFLINE: u8 = 4;
+
+pub struct ShmMirror {
+    file: File,
+    path: PathBuf,
+    seq: u64,
+    last: Option<(ColorRGB, u8)>,
+}
+
+impl ShmMirror {
+    /// Create (or take over) the region for `name` and write an empty record.
+    pub fn create(name: &str) -> io::Result<Self> {
+        let path = if name.contains('/') { PathBuf::from(name) } else { PathBuf::from("/dev/shm").join(name) };
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+        file.set_len(SIZE as u64)?;
+        let mut mirror = Self { file, path, seq: 0, last: None };
+        let mut header = [0u8; 8];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..].copy_from_slice(&VERSION.to_le_bytes());
+        mirror.write_at(0, &header)?;
+        mirror.write_at(8, &0u64.to_le_bytes())?;
+        Ok(mirror)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Publish the current patch and flags. Returns whether anything changed (and so
+    /// was written).
+    pub fn update(&mut self, colour: ColorRGB, flags: u8) -> io::Result<bool> {
+        if self.last == Some((colour, flags)) {
+            return Ok(false);
+        }
+        let mut body = [0u8; SIZE - 16];
+        body[0..2].copy_from_slice(&colour.red.to_le_bytes());
+        body[2..4].copy_from_slice(&colour.green.to_le_bytes());
+        body[4..6].copy_from_slice(&colour.blue.to_le_bytes());
+        body[6] = colour.depth_bits;
+        body[7] = flags;
+        self.write_at(8, &(self.seq + 1).to_le_bytes())?;
+        self.write_at(16, &body)?;
+        self.seq += 2;
+        self.write_at(8, &self.seq.to_le_bytes())?;
+        self.last = Some((colour, flags));
+        Ok(true)
+    }
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(bytes)
+    }
+}
+
+impl Drop for ShmMirror {
+    // the region describes this process; don't leave a stale one behind
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// seqno and body of the region, as another process would read them.
+    fn read_back(path: &std::path::Path) -> (u64, ColorRGB, u8) {
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(bytes.len(), SIZE);
+        assert_eq!(&bytes[0..4], MAGIC);
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), VERSION);
+        assert_eq!(&bytes[24..], &[0u8; 8]);
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let seq = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+        (seq, ColorRGB { red: u16_at(16), green: u16_at(18), blue: u16_at(20), depth_bits: bytes[22] }, bytes[23])
+    }
+
+    #[test]
+    fn region_is_written_and_read_back() {
+        let path = std::env::temp_dir().join(format!("colourspace-shm-{}", std::process::id()));
+        let mut mirror = ShmMirror::create(path.to_str().unwrap()).unwrap();
+        assert_eq!(read_back(&path), (0, ColorRGB { red: 0, green: 0, blue: 0, depth_bits: 0 }, 0));
+
+        let white = ColorRGB { red: 1023, green: 1023, blue: 1023, depth_bits: 10 };
+        assert!(mirror.update(white, FLAG_CONNECTED | FLAG_MEASURING).unwrap());
+        assert_eq!(read_back(&path), (2, white, FLAG_CONNECTED | FLAG_MEASURING));
+        // nothing changed, nothing written
+        assert!(!mirror.update(white, FLAG_CONNECTED | FLAG_MEASURING).unwrap());
+        assert_eq!(read_back(&path).0, 2);
+        let red = ColorRGB { red: 65535, green: 0, blue: 258, depth_bits: 16 };
+        assert!(mirror.update(red, FLAG_OFFLINE).unwrap());
+        assert_eq!(read_back(&path), (4, red, FLAG_OFFLINE));
+
+        drop(mirror);
+        assert!(!path.exists(), "region left behind");
+    }
+}
@@ -0,0 +1,1268 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::delta_e::xyy_to_xyz;
+use crate::lan::{ColorRGB, DEPTH_BITS, MeasurementResult};
+
+/// Give up on a sweep if the link stays down this long.
+const LINK_LOST_ABORT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepKind {
+    /// 0%..100% greys, evenly spaced, to build a gamma/EOTF curve.
+    Gamma,
+    /// one white patch walked across a grid of screen positions (`--uniformity RxC`)
+    Uniformity,
+    /// patches read from a CSV of RGB triples (`--patch-list`), measured in file order
+    PatchList,
+    /// patches from an ArgyllCMS .ti1 target (`--ti1`), results written as .ti3
+    Ti1,
+}
+
+impl FromStr for SweepKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gamma" => Ok(SweepKind::Gamma),
+            other => Err(format!("unknown sweep '{}', expected: gamma", other)),
+        }
+    }
+}
+
+impl SweepKind {
+    fn name(self) -> &'static str {
+        match self {
+            SweepKind::Gamma => "gamma",
+            SweepKind::Uniformity => "uniformity",
+            SweepKind::PatchList => "patch-list",
+            SweepKind::Ti1 => "ti1",
+        }
+    }
+
+    pub fn default_csv(self) -> &'static str {
+        match self {
+            SweepKind::Gamma => "gamma_sweep.csv",
+            SweepKind::Uniformity => "uniformity_scan.csv",
+            SweepKind::PatchList => "patch_list_results.csv",
+            SweepKind::Ti1 => "ti1_results.ti3",
+        }
+    }
+}
+
+/// What a sweep does when a requested reading doesn't arrive in time (`--on-timeout`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnTimeout {
+    /// record the patch as timed out (empty reading) and go on with the next one
+    Skip,
+    /// stop the sweep; the rows so far are still written
+    Abort,
+}
+
+impl FromStr for OnTimeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(OnTimeout::Skip),
+            "abort" => Ok(OnTimeout::Abort),
+            other => Err(format!("unknown timeout action '{}', expected: skip, abort", other)),
+        }
+    }
+}
+
+/// Layout of the sweep results file for another tool to import (`--export`). Both are
+/// plain CSV, one measured patch per line in sweep order; patches without a reading
+/// are left out.
+///
+/// `calman`: `Name,R,G,B,X,Y,Z,x,y,Y(cd/m2)`. Name is the server's label or `Patch N`,
+/// R,G,B the code values shown, X,Y,Z absolute tristimulus values (cd/m2).
+///
+/// `lightspace`: `R,G,B,X,Y,Z`. R,G,B the shown colour normalized to 0..1, X,Y,Z
+/// normalized so the brightest patch has Y = 1 (the layout of LightSpace's manual
+/// import).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Calman,
+    LightSpace,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "calman" => Ok(ExportFormat::Calman),
+            "lightspace" => Ok(ExportFormat::LightSpace),
+            other => Err(format!("unknown export format '{}', expected: calman, lightspace", other)),
+        }
+    }
+}
+
+impl ExportFormat {
+    pub fn default_file(self) -> &'static str {
+        match self {
+            ExportFormat::Calman => "calman_export.csv",
+            ExportFormat::LightSpace => "lightspace_export.csv",
+        }
+    }
+}
+
+/// Grid size for `--uniformity`, written `RxC` (rows x columns), e.g. `3x3` or `5x5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniformityGrid {
+    pub rows: usize,
+    pub cols: usize,
+}
+
+impl FromStr for UniformityGrid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("bad grid '{}', expected RxC with at least 1 each, e.g. 3x3", s);
+        let (r, c) = s.to_ascii_lowercase().split_once('x').map(|(r, c)| (r.trim().parse::<usize>(), c.trim().parse::<usize>())).ok_or_else(bad)?;
+        match (r, c) {
+            (Ok(rows), Ok(cols)) if rows > 0 && cols > 0 => Ok(Self { rows, cols }),
+            _ => Err(bad()),
+        }
+    }
+}
+
+impl UniformityGrid {
+    /// (row, col) of every cell in the order they are measured: row by row, left to right.
+    pub fn positions(self) -> Vec<(usize, usize)> {
+        (0..self.rows).flat_map(|r| (0..self.cols).map(move |c| (r, c))).collect()
+    }
+}
+
+/// Read a `--patch-list` CSV: one patch per line as `red,green,blue[,bits]` code values
+/// (bits defaults to `default_bits`). Blank lines and `#` comments are skipped, and so
+/// is a header line of non-numeric fields before the first patch. Returns each patch
+/// with its 1-based line number; any bad line is an error naming the line.
+pub fn load_patch_list(path: &Path, default_bits: u8) -> io::Result<Vec<(usize, ColorRGB)>> {
+    let text = fs::read_to_string(path)?;
+    let mut patches = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let bad = |what: String| io::Error::new(io::ErrorKind::InvalidData, format!("{} line {}: {}", path.display(), n + 1, what));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if patches.is_empty() && fields.iter().all(|f| f.parse::<f64>().is_err()) {
+            continue;
+        }
+        let (r, g, b, bits) = match fields[..] {
+            [r, g, b] => (r, g, b, None),
+            [r, g, b, bits] => (r, g, b, Some(bits)),
+            _ => return Err(bad(format!("expected red,green,blue[,bits], got '{}'", line))),
+        };
+        let bits = match bits {
+            Some(v) => v.parse::<u8>().map_err(|_| bad(format!("bad bit depth '{}'", v)))?,
+            None => default_bits,
+        };
+        if !DEPTH_BITS.contains(&bits) {
+            return Err(bad(format!("bit depth {} out of range {}-{}", bits, DEPTH_BITS.start(), DEPTH_BITS.end())));
+        }
+        let max = ((1u32 << bits) - 1) as u16;
+        let code = |v: &str| match v.parse::<u16>() {
+            Ok(c) if c <= max => Ok(c),
+            _ => Err(bad(format!("bad code value '{}' for {}-bit", v, bits))),
+        };
+        patches.push((n + 1, ColorRGB::from_components_u16(code(r)?, code(g)?, code(b)?, bits)));
+    }
+    if patches.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: no patches", path.display())));
+    }
+    Ok(patches)
+}
+
+/// Read the patches of an ArgyllCMS .ti1 file: the first CGATS table with `RGB_R`,
+/// `RGB_G` and `RGB_B` fields (device values in percent), converted to `bits` code
+/// values. Only the data format and data blocks are looked at, not the rest of the CGATS
+/// spec. Returns each patch with its `SAMPLE_ID` (the 1-based row if there is none).
+pub fn load_ti1(path: &Path, bits: u8) -> io::Result<Vec<(usize, ColorRGB)>> {
+    let text = fs::read_to_string(path)?;
+    let bad = |what: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), what));
+    let mut fields: Vec<&str> = Vec::new();
+    let mut in_format = false;
+    let mut in_data = false;
+    let mut patches = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_whitespace().next() {
+            Some("BEGIN_DATA_FORMAT") => { fields.clear(); in_format = true; continue; }
+            Some("END_DATA_FORMAT") => { in_format = false; continue; }
+            Some("BEGIN_DATA") => { in_data = true; continue; }
+            Some("END_DATA") => {
+                // the first table with device RGB is the one
+                if !patches.is_empty() { break; }
+                in_data = false;
+                continue;
+            }
+            _ => {}
+        }
+        if in_format {
+            fields.extend(line.split_whitespace());
+            continue;
+        }
+        if !in_data {
+            continue;
+        }
+        let column = |name: &str| fields.iter().position(|&f| f == name);
+        let (Some(r), Some(g), Some(b)) = (column("RGB_R"), column("RGB_G"), column("RGB_B")) else { continue };
+        let values: Vec<&str> = line.split_whitespace().collect();
+        if values.len() != fields.len() {
+            return Err(bad(format!("line {}: {} values for {} fields", n + 1, values.len(), fields.len())));
+        }
+        let percent = |i: usize| values[i].parse::<f64>().ok().filter(|v| (0.0..=100.0).contains(v))
+        .ok_or_else(|| bad(format!("line {}: bad {} '{}'", n + 1, fields[i], values[i])));
+        let id = match column("SAMPLE_ID") {
+            Some(i) => values[i].parse::<usize>().map_err(|_| bad(format!("line {}: bad SAMPLE_ID '{}'", n + 1, values[i])))?,
+            None => patches.len() + 1,
+        };
+        patches.push((id, ColorRGB::from_normalized(percent(r)? / 100.0, percent(g)? / 100.0, percent(b)? / 100.0, bits)));
+    }
+    if patches.is_empty() {
+        return Err(bad("no RGB_R/RGB_G/RGB_B data found".to_string()));
+    }
+    Ok(patches)
+}
+
+/// One patch of a sweep and the (averaged) reading taken for it.
+#[derive(Debug, Clone)]
+pub struct SweepRow {
+    pub level: f64,
+    pub colour: ColorRGB,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub y_lum: Option<f64>,
+    /// sample standard deviation of Y across the repeats (None for a single reading)
+    pub y_lum_stddev: Option<f64>,
+    /// readings that went into the row (after outlier rejection, if enabled)
+    pub samples: usize,
+    /// server's name for the patch, from the last reading that had one
+    pub label: Option<String>,
+    /// no reading arrived within the measure timeout; x/y/Y are empty
+    pub timed_out: bool,
+}
+
+impl SweepRow {
+    /// `status` column of the CSVs: `ok`, or `timeout` for a patch that got no reading.
+    fn status(&self) -> &'static str {
+        if self.timed_out { "timeout" } else { "ok" }
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+fn stddev(values: &[f64]) -> Option<f64> {
+    let m = mean(values)?;
+    (values.len() > 1).then(|| (values.iter().map(|v| (v - m).powi(2)).sum::<f64>() / (values.len() - 1) as f64).sqrt())
+}
+
+/// Reduced x, y, Y and Y standard deviation for one patch.
+pub type Reading = (Option<f64>, Option<f64>, Option<f64>, Option<f64>);
+
+fn median(values: &[f64]) -> Option<f64> {
+    let mut v = values.to_vec();
+    v.sort_by(f64::total_cmp);
+    let n = v.len();
+    match n {
+        0 => None,
+        _ if n % 2 == 1 => Some(v[n / 2]),
+        _ => Some((v[n / 2 - 1] + v[n / 2]) / 2.0),
+    }
+}
+
+/// Mean of x/y/Y over repeated readings of one patch (each channel over the readings
+/// that have it), plus the standard deviation of Y.
+pub fn average(samples: &[MeasurementResult]) -> Reading {
+    let xs: Vec<f64> = samples.iter().filter_map(|m| m.x).collect();
+    let ys: Vec<f64> = samples.iter().filter_map(|m| m.y).collect();
+    let lums: Vec<f64> = samples.iter().filter_map(|m| m.y_lum).collect();
+    (mean(&xs), mean(&ys), mean(&lums), stddev(&lums))
+}
+
+/// Outlier-resistant alternative to `average`: drop the readings with the highest and
+/// lowest Y (once there are at least 3 with Y), then take the median of what is left,
+/// x/y included, so one spike can't skew a dark patch. Also returns how many readings
+/// were kept.
+pub fn median_rejecting_outliers(samples: &[MeasurementResult]) -> (Reading, usize) {
+    let mut with_y: Vec<usize> = (0..samples.len()).filter(|&i| samples[i].y_lum.is_some()).collect();
+    let mut kept: Vec<&MeasurementResult> = samples.iter().collect();
+    if with_y.len() >= 3 {
+        with_y.sort_by(|&a, &b| samples[a].y_lum.unwrap_or_default().total_cmp(&samples[b].y_lum.unwrap_or_default()));
+        let (lo, hi) = (with_y[0], with_y[with_y.len() - 1]);
+        kept = samples.iter().enumerate().filter(|(i, _)| *i != lo && *i != hi).map(|(_, m)| m).collect();
+    }
+
+    let xs: Vec<f64> = kept.iter().filter_map(|m| m.x).collect();
+    let ys: Vec<f64> = kept.iter().filter_map(|m| m.y).collect();
+    let lums: Vec<f64> = kept.iter().filter_map(|m| m.y_lum).collect();
+    ((median(&xs), median(&ys), median(&lums), stddev(&lums)), kept.len())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+    /// Patch is on screen; request the reading once it has settled. While cross-fading
+    /// into it `since` is still ahead: when the fade ends and the patch is at full colour.
+    Settling(Instant),
+    /// Request sent at `since`; waiting for `measurements_received` to move past `after`.
+    Waiting { after: u64, since: Instant },
+    Done,
+}
+
+/// What the render loop should do after a `Sweep::tick`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepAction {
+    None,
+    /// Ask the server to measure this (already displayed) patch.
+    Request(ColorRGB),
+    Finished,
+    /// The link stayed down too long; partial results are still available.
+    Aborted,
+    /// A reading timed out with `OnTimeout::Abort`; partial results are still available.
+    TimedOut,
+}
+
+/// Client-driven measurement sequence: show each patch, wait `settle`, request a
+/// reading, record it, move on. Driven once per frame from the main loop.
+pub struct Sweep {
+    kind: SweepKind,
+    patches: Vec<(f64, ColorRGB)>,
+    rows: Vec<SweepRow>,
+    phase: Phase,
+    settle: Duration,
+    link_lost: Option<Instant>,
+    /// readings to take (and average) per patch
+    repeats: usize,
+    /// reduce repeats with `median_rejecting_outliers` instead of the mean
+    reject_outliers: bool,
+    samples: Vec<MeasurementResult>,
+    /// uniformity scans: grid and the cell each patch is shown in (empty otherwise)
+    grid: Option<UniformityGrid>,
+    cells: Vec<(usize, usize)>,
+    /// patch lists: the input line of each patch; .ti1 targets: its SAMPLE_ID (empty otherwise)
+    lines: Vec<usize>,
+    /// give up on a reading after this long (None = wait for ever)
+    measure_timeout: Option<Duration>,
+    on_timeout: OnTimeout,
+    /// blend from the previous patch over this long before settling starts (zero = cut)
+    crossfade: Duration,
+    /// the patch being faded out of, while a cross-fade is running
+    fade_from: Option<ColorRGB>,
+}
+
+impl Sweep {
+    /// Gamma sweep with `steps` evenly spaced 8-bit greys from black to white,
+    /// each measured `repeats` times and averaged (or median-filtered).
+    pub fn gamma(steps: usize, settle: Duration, repeats: usize, reject_outliers: bool) -> Self {
+        let steps = steps.max(2);
+        let patches = (0..steps)
+        .map(|i| {
+            let level = i as f64 / (steps - 1) as f64;
+            let code = (level * 255.0).round() as u16;
+            (level, ColorRGB::from_components_u16(code, code, code, 8))
+        })
+        .collect();
+
+        Self {
+            kind: SweepKind::Gamma,
+            patches,
+            rows: Vec::new(),
+            phase: Phase::Settling(Instant::now()),
+            settle,
+            link_lost: None,
+            repeats: repeats.max(1),
+            reject_outliers,
+            samples: Vec::new(),
+            grid: None,
+            cells: Vec::new(),
+            lines: Vec::new(),
+            measure_timeout: None,
+            on_timeout: OnTimeout::Skip,
+            crossfade: Duration::ZERO,
+            fade_from: None,
+        }
+    }
+
+    /// Uniformity scan: full white shown in each cell of `grid` in turn, each measured
+    /// like a gamma step.
+    pub fn uniformity(grid: UniformityGrid, settle: Duration, repeats: usize, reject_outliers: bool) -> Self {
+        let cells = grid.positions();
+        let white = ColorRGB::from_components_u16(255, 255, 255, 8);
+        Self {
+            kind: SweepKind::Uniformity,
+            patches: vec![(1.0, white); cells.len()],
+            grid: Some(grid),
+            cells,
+            ..Self::gamma(2, settle, repeats, reject_outliers)
+        }
+    }
+
+    /// Patch list: the given patches (with their input lines, see `load_patch_list`) in
+    /// order, each measured like a gamma step. Level is the brightest channel's fraction.
+    pub fn patch_list(patches: Vec<(usize, ColorRGB)>, settle: Duration, repeats: usize, reject_outliers: bool) -> Self {
+        let level = |c: ColorRGB| c.red.max(c.green).max(c.blue) as f64 / ((1u32 << c.depth_bits.clamp(1, 16)) - 1) as f64;
+        Self {
+            kind: SweepKind::PatchList,
+            lines: patches.iter().map(|&(line, _)| line).collect(),
+            patches: patches.into_iter().map(|(_, c)| (level(c), c)).collect(),
+            ..Self::gamma(2, settle, repeats, reject_outliers)
+        }
+    }
+
+    /// .ti1 target: like a patch list, keyed by SAMPLE_ID (see `load_ti1`).
+    pub fn ti1(patches: Vec<(usize, ColorRGB)>, settle: Duration, repeats: usize, reject_outliers: bool) -> Self {
+        Self { kind: SweepKind::Ti1, ..Self::patch_list(patches, settle, repeats, reject_outliers) }
+    }
+
+    pub fn new(kind: SweepKind, steps: usize, settle: Duration, repeats: usize, reject_outliers: bool) -> Self {
+        match kind {
+            SweepKind::Gamma => Self::gamma(steps, settle, repeats, reject_outliers),
+            // the grid normally comes from `--uniformity RxC` via `Sweep::uniformity`
+            SweepKind::Uniformity => Self::uniformity(UniformityGrid { rows: 3, cols: 3 }, settle, repeats, reject_outliers),
+            // patches come from `--patch-list` via `Sweep::patch_list`; nothing to make up here
+            SweepKind::PatchList => Self::patch_list(Vec::new(), settle, repeats, reject_outliers),
+            SweepKind::Ti1 => Self::ti1(Vec::new(), settle, repeats, reject_outliers),
+        }
+    }
+
+    /// Stop waiting for a reading after `timeout` and skip the patch or abort the sweep.
+    pub fn with_measure_timeout(self, timeout: Option<Duration>, on_timeout: OnTimeout) -> Self {
+        Self { measure_timeout: timeout, on_timeout, ..self }
+    }
+
+    /// Cross-fade from each patch to the next over `crossfade` instead of cutting, to
+    /// spare the panel and meter a hard black-to-white step. Settling (and so the request)
+    /// only starts once the fade is over, so no reading can include the blend.
+    pub fn with_crossfade(self, crossfade: Duration) -> Self {
+        Self { crossfade, ..self }
+    }
+
+    pub fn kind(&self) -> SweepKind {
+        self.kind
+    }
+
+    pub fn rows(&self) -> &[SweepRow] {
+        &self.rows
+    }
+
+    /// The patch that should be on screen right now.
+    pub fn current_patch(&self) -> Option<ColorRGB> {
+        self.patches.get(self.rows.len()).map(|(_, c)| *c)
+    }
+
+    /// What to draw right now: `current_patch`, or during a cross-fade the blend from the
+    /// previous patch towards it (linear in code values, at the new patch's depth).
+    pub fn displayed_patch(&self, now: Instant) -> Option<ColorRGB> {
+        let to = self.current_patch()?;
+        let (Some(from), Phase::Settling(full_at)) = (self.fade_from, self.phase) else { return Some(to) };
+        if now >= full_at || self.crossfade.is_zero() {
+            return Some(to);
+        }
+        let t = 1.0 - full_at.duration_since(now).as_secs_f64() / self.crossfade.as_secs_f64();
+        let max = |c: ColorRGB| ((1u32 << c.depth_bits.clamp(1, 16)) - 1) as f64;
+        let mix = |a: u16, b: u16| (a as f64 / max(from)) * (1.0 - t) + (b as f64 / max(to)) * t;
+        Some(ColorRGB::from_normalized(mix(from.red, to.red), mix(from.green, to.green), mix(from.blue, to.blue), to.depth_bits))
+    }
+
+    /// Grid and (row, col) of the cell the current patch belongs in, for uniformity scans.
+    pub fn current_cell(&self) -> Option<(UniformityGrid, usize, usize)> {
+        let (row, col) = *self.cells.get(self.rows.len())?;
+        Some((self.grid?, row, col))
+    }
+
+    /// A reading has been requested and hasn't arrived yet.
+    pub fn measuring(&self) -> bool {
+        matches!(self.phase, Phase::Waiting { .. })
+    }
+
+    /// Restart the settle timer for the current patch (e.g. the request failed to send).
+    pub fn retry(&mut self, now: Instant) {
+        if !matches!(self.phase, Phase::Done) {
+            self.phase = Phase::Settling(now);
+            self.fade_from = None;
+        }
+    }
+
+    /// Advance the state machine. `measurements_received`/`last` come straight
+    /// from the worker's `SharedState`.
+    pub fn tick(&mut self, now: Instant, connected: bool, measurements_received: u64, last: Option<&MeasurementResult>) -> SweepAction {
+        if matches!(self.phase, Phase::Done) {
+            return SweepAction::None;
+        }
+
+        // On link loss, drop any outstanding request; the patch is re-measured once
+        // the link is back (resume), or the sweep is abandoned after a while (abort).
+        if !connected {
+            let lost = *self.link_lost.get_or_insert(now);
+            if now.duration_since(lost) >= LINK_LOST_ABORT {
+                self.phase = Phase::Done;
+                return SweepAction::Aborted;
+            }
+            return SweepAction::None;
+        }
+        if self.link_lost.take().is_some() {
+            eprintln!("Sweep: link restored, re-measuring step {}", self.rows.len() + 1);
+            self.samples.clear();
+            self.phase = Phase::Settling(now);
+            self.fade_from = None;
+        }
+
+        match self.phase {
+            // (duration_since is zero while a cross-fade still runs, `since` being ahead)
+            Phase::Settling(since) if now >= since && now.duration_since(since) >= self.settle => {
+                self.fade_from = None;
+                self.phase = Phase::Waiting { after: measurements_received, since: now };
+                match self.current_patch() {
+                    Some(colour) => SweepAction::Request(colour),
+                    // resumed from a file that already had every patch
+                    None => {
+                        self.phase = Phase::Done;
+                        SweepAction::Finished
+                    }
+                }
+            }
+            Phase::Waiting { after, .. } if measurements_received > after => {
+                let (level, colour) = self.patches[self.rows.len()];
+                if let Some(m) = last {
+                    self.samples.push(m.clone());
+                }
+
+                // Same patch again until we have enough readings to average.
+                if self.samples.len() < self.repeats {
+                    self.phase = Phase::Waiting { after: measurements_received, since: now };
+                    return SweepAction::Request(colour);
+                }
+
+                let ((x, y, y_lum, y_lum_stddev), samples) = if self.reject_outliers {
+                    median_rejecting_outliers(&self.samples)
+                } else {
+                    (average(&self.samples), self.samples.len())
+                };
+                let label = self.samples.iter().rev().find_map(|m| m.label.clone());
+                self.rows.push(SweepRow { level, colour, x, y, y_lum, y_lum_stddev, samples, label, timed_out: false });
+                self.next_patch(now)
+            }
+            // The meter never answered: note the patch as timed out (readings taken for
+            // it so far are dropped) and move on, or stop here.
+            Phase::Waiting { since, .. } if self.measure_timeout.is_some_and(|t| now.duration_since(since) >= t) => {
+                let (level, colour) = self.patches[self.rows.len()];
+                eprintln!("Sweep: no reading for step {} within {}ms", self.rows.len() + 1, self.measure_timeout.unwrap_or_default().as_millis());
+                if self.on_timeout == OnTimeout::Abort {
+                    self.phase = Phase::Done;
+                    return SweepAction::TimedOut;
+                }
+                self.rows.push(SweepRow { level, colour, x: None, y: None, y_lum: None, y_lum_stddev: None, samples: 0, label: None, timed_out: true });
+                self.next_patch(now)
+            }
+            _ => SweepAction::None,
+        }
+    }
+
+    /// After a row is recorded: settle the next patch, or finish.
+    fn next_patch(&mut self, now: Instant) -> SweepAction {
+        self.samples.clear();
+        if self.rows.len() == self.patches.len() {
+            self.phase = Phase::Done;
+            SweepAction::Finished
+        } else {
+            // fade from the patch just measured; settling counts from when the fade ends
+            self.fade_from = (!self.crossfade.is_zero()).then(|| self.patches[self.rows.len() - 1].1);
+            self.phase = Phase::Settling(now + self.crossfade);
+            SweepAction::None
+        }
+    }
+
+    /// Progress lines for the info overlay.
+    pub fn status_lines(&self) -> Vec<String> {
+        let total = self.patches.len();
+        let step = (self.rows.len() + 1).min(total);
+        let mut lines = vec![format!("{:?} sweep: step {} of {}", self.kind, step, total)];
+        if let Some((grid, row, col)) = self.current_cell() {
+            lines.push(format!("cell row {} of {}, column {} of {}", row + 1, grid.rows, col + 1, grid.cols));
+        } else if let (Some(line), Some((_, c))) = (self.lines.get(self.rows.len()), self.patches.get(self.rows.len())) {
+            lines.push(format!("input line {}  RGB {},{},{} ({}-bit)", line, c.red, c.green, c.blue, c.depth_bits));
+        } else if let Some((level, c)) = self.patches.get(self.rows.len()) {
+            lines.push(format!("level {:.0}%  RGB {},{},{}", level * 100.0, c.red, c.green, c.blue));
+        }
+        if self.repeats > 1 {
+            lines.push(format!("reading {} of {}", (self.samples.len() + 1).min(self.repeats), self.repeats));
+        }
+        lines.push(match (self.link_lost, self.phase) {
+            (Some(_), _) => "waiting for link...".to_string(),
+            (None, Phase::Settling(since)) if self.fade_from.is_some() && Instant::now() < since => "cross-fading".to_string(),
+            (None, Phase::Settling(_)) => "settling".to_string(),
+            (None, Phase::Waiting { since, .. }) => match self.measure_timeout {
+                Some(t) => format!("measuring (timeout in {:.0}s)", t.saturating_sub(since.elapsed()).as_secs_f64().ceil()),
+                None => "measuring".to_string(),
+            },
+            (None, Phase::Done) => "done".to_string(),
+        });
+        let timeouts = self.rows.iter().filter(|r| r.timed_out).count();
+        if timeouts > 0 {
+            lines.push(format!("{} patch(es) timed out, no reading", timeouts));
+        }
+        lines
+    }
+
+    /// Default progress file for a sweep writing `csv`: the same path plus `.resume`.
+    pub fn default_progress(csv: &Path) -> PathBuf {
+        let mut name = csv.as_os_str().to_owned();
+        name.push(".resume");
+        name.into()
+    }
+
+    /// Save the patch list and the rows measured so far, for `--resume` after a crash.
+    /// Plain `key = value` lines: `kind`, `grid` (uniformity), one `patch` per patch
+    /// in order (`level red green blue bits`, plus the input line or SAMPLE_ID for patch lists and .ti1), then one `row` per completed patch
+    /// (`level red green blue bits x y Y Y_stddev samples status label`, `-` for no
+    /// value, label to the end of the line). Written to a temp file and renamed over
+    /// `path`, so a crash mid-write leaves the previous save intact.
+    pub fn save_progress(&self, path: &Path) -> io::Result<()> {
+        fn opt(v: Option<f64>) -> String {
+            v.map_or("-".to_string(), |v| v.to_string())
+        }
+
+        let mut out = format!("# colourspace sweep progress, continue with --resume\nkind = {}\n", self.kind.name());
+        if let Some(grid) = self.grid {
+            out.push_str(&format!("grid = {}x{}\n", grid.rows, grid.cols));
+        }
+        for (i, (level, c)) in self.patches.iter().enumerate() {
+            let line = self.lines.get(i).map_or(String::new(), |l| format!(" {}", l));
+            out.push_str(&format!("patch = {} {} {} {} {}{}\n", level, c.red, c.green, c.blue, c.depth_bits, line));
+        }
+        for row in &self.rows {
+            let c = row.colour;
+            let label = row.label.as_deref().map(|l| l.replace(['\n', '\r'], " ")).unwrap_or_default();
+            out.push_str(&format!(
+                "row = {} {} {} {} {} {} {} {} {} {} {} {}\n",
+                row.level, c.red, c.green, c.blue, c.depth_bits,
+                opt(row.x), opt(row.y), opt(row.y_lum), opt(row.y_lum_stddev), row.samples, row.status(), label
+            ));
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Rebuild a sweep from `save_progress` output: same patches, the saved rows kept,
+    /// carrying on with the first patch that has no row. Settle time, repeats and outlier
+    /// rejection come from this run's flags. Malformed files are an error rather than a
+    /// silent fresh start, so a long run's progress is never thrown away.
+    pub fn resume(path: &Path, settle: Duration, repeats: usize, reject_outliers: bool) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let (mut kind, mut grid) = (None, None);
+        let (mut patches, mut rows, mut lines) = (Vec::new(), Vec::new(), Vec::new());
+        for (n, line) in text.lines().enumerate() {
+            let bad = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{} line {}: {}", path.display(), n + 1, what));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else { return Err(bad("expected key = value")) };
+            let value = value.trim();
+            let fields: Vec<&str> = value.splitn(12, ' ').collect();
+            let num = |i: usize| fields.get(i).and_then(|f| f.parse::<f64>().ok()).ok_or_else(|| bad("bad number"));
+            let opt = |i: usize| match fields.get(i) {
+                Some(&"-") => Ok(None),
+                _ => num(i).map(Some),
+            };
+            let code = |i: usize| fields.get(i).and_then(|f| f.parse::<u16>().ok()).ok_or_else(|| bad("bad code value"));
+            let colour = || -> io::Result<ColorRGB> {
+                let bits = fields.get(4).and_then(|f| f.parse::<u8>().ok()).ok_or_else(|| bad("bad bit depth"))?;
+                Ok(ColorRGB::from_components_u16(code(1)?, code(2)?, code(3)?, bits))
+            };
+            match key.trim() {
+                "kind" => kind = Some(match value {
+                    "gamma" => SweepKind::Gamma,
+                    "uniformity" => SweepKind::Uniformity,
+                    "patch-list" => SweepKind::PatchList,
+                    "ti1" => SweepKind::Ti1,
+                    _ => return Err(bad("unknown sweep kind")),
+                }),
+                "grid" => grid = Some(value.parse::<UniformityGrid>().map_err(|e| bad(&e))?),
+                "patch" => {
+                    patches.push((num(0)?, colour()?));
+                    if let Some(line) = fields.get(5) {
+                        lines.push(line.parse::<usize>().map_err(|_| bad("bad input line"))?);
+                    }
+                }
+                "row" => {
+                    let status = fields.get(10).copied().ok_or_else(|| bad("missing status"))?;
+                    rows.push(SweepRow {
+                        level: num(0)?,
+                        colour: colour()?,
+                        x: opt(5)?,
+                        y: opt(6)?,
+                        y_lum: opt(7)?,
+                        y_lum_stddev: opt(8)?,
+                        samples: fields.get(9).and_then(|f| f.parse().ok()).ok_or_else(|| bad("bad sample count"))?,
+                        label: fields.get(11).filter(|l| !l.is_empty()).map(|l| l.to_string()),
+                        timed_out: status == "timeout",
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), what));
+        let kind = kind.ok_or_else(|| invalid("no sweep kind"))?;
+        if patches.is_empty() || rows.len() > patches.len() {
+            return Err(invalid("patch list missing or shorter than the rows"));
+        }
+        let cells = match (kind, grid) {
+            (SweepKind::Uniformity, Some(grid)) if grid.rows * grid.cols == patches.len() => grid.positions(),
+            (SweepKind::Uniformity, _) => return Err(invalid("uniformity grid missing or doesn't match the patches")),
+            _ => Vec::new(),
+        };
+        if matches!(kind, SweepKind::PatchList | SweepKind::Ti1) && lines.len() != patches.len() {
+            return Err(invalid("patch list without an input line for every patch"));
+        }
+        Ok(Self {
+            kind,
+            patches,
+            rows,
+            grid: grid.filter(|_| kind == SweepKind::Uniformity),
+            cells,
+            lines,
+            ..Self::gamma(2, settle, repeats, reject_outliers)
+        })
+    }
+
+    /// Write the collected rows as CSV. For the gamma sweep each row also gets the
+    /// point gamma relative to the black and white readings; a uniformity scan gets
+    /// the cell position (1-based) and Y as a percentage of the brightest cell; a patch
+    /// list is keyed by the input line each patch came from. A .ti1 target is written as
+    /// .ti3 instead (see `write_ti3`).
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        match self.kind {
+            SweepKind::Uniformity => return self.write_uniformity_csv(path),
+            SweepKind::PatchList => return self.write_patch_list_csv(path),
+            SweepKind::Ti1 => return self.write_ti3(path),
+            SweepKind::Gamma => {}
+        }
+
+        let black = self.rows.first().filter(|r| r.level == 0.0).and_then(|r| r.y_lum).unwrap_or(0.0);
+        let white = self.rows.iter().rev().find(|r| r.level == 1.0).and_then(|r| r.y_lum);
+
+        fn opt(v: Option<f64>) -> String {
+            v.map(|v| format!("{:.6}", v)).unwrap_or_default()
+        }
+
+        // labels are free text: always quote, doubling any embedded quotes
+        fn quoted(v: Option<&str>) -> String {
+            v.map(|v| format!("\"{}\"", v.replace('"', "\"\""))).unwrap_or_default()
+        }
+
+        let mut out = String::from("step,level,red,green,blue,bits,x,y,Y,Y_stddev,samples,gamma,label,status\n");
+        for (i, row) in self.rows.iter().enumerate() {
+            let gamma = match (row.y_lum, white) {
+                (Some(y), Some(white)) if row.level > 0.0 && row.level < 1.0 && white > black => {
+                    let rel = (y - black) / (white - black);
+                    (rel > 0.0).then(|| rel.ln() / row.level.ln())
+                }
+                _ => None,
+            };
+            out.push_str(&format!(
+                "{},{:.4},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                i + 1, row.level, row.colour.red, row.colour.green, row.colour.blue, row.colour.depth_bits,
+                opt(row.x), opt(row.y), opt(row.y_lum), opt(row.y_lum_stddev), row.samples, opt(gamma), quoted(row.label.as_deref()), row.status()
+            ));
+        }
+        fs::write(path, out)
+    }
+
+    fn write_uniformity_csv(&self, path: &Path) -> io::Result<()> {
+        let brightest = self.rows.iter().filter_map(|r| r.y_lum).fold(None, |m: Option<f64>, y| Some(m.map_or(y, |m| m.max(y))));
+        let opt = |v: Option<f64>| v.map(|v| format!("{:.6}", v)).unwrap_or_default();
+
+        let mut out = String::from("row,col,x,y,Y,Y_stddev,samples,Y_pct_of_max,status\n");
+        for (row, &(r, c)) in self.rows.iter().zip(&self.cells) {
+            let pct = match (row.y_lum, brightest) {
+                (Some(y), Some(max)) if max > 0.0 => Some(y / max * 100.0),
+                _ => None,
+            };
+            out.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                r + 1, c + 1, opt(row.x), opt(row.y), opt(row.y_lum), opt(row.y_lum_stddev), row.samples, pct.map(|p| format!("{:.2}", p)).unwrap_or_default(), row.status()
+            ));
+        }
+        fs::write(path, out)
+    }
+
+    fn write_patch_list_csv(&self, path: &Path) -> io::Result<()> {
+        let opt = |v: Option<f64>| v.map(|v| format!("{:.6}", v)).unwrap_or_default();
+        let quoted = |v: Option<&str>| v.map(|v| format!("\"{}\"", v.replace('"', "\"\""))).unwrap_or_default();
+
+        let mut out = String::from("line,red,green,blue,bits,x,y,Y,Y_stddev,samples,label,status\n");
+        for (row, line) in self.rows.iter().zip(&self.lines) {
+            let c = row.colour;
+            out.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                line, c.red, c.green, c.blue, c.depth_bits,
+                opt(row.x), opt(row.y), opt(row.y_lum), opt(row.y_lum_stddev), row.samples, quoted(row.label.as_deref()), row.status()
+            ));
+        }
+        fs::write(path, out)
+    }
+
+    /// ArgyllCMS .ti3 (display, RGB_XYZ) of the measured patches, keyed by SAMPLE_ID.
+    /// RGB is what was shown (the code values, in percent); XYZ is normalized so the
+    /// brightest patch has Y = 100, with its absolute XYZ in LUMINANCE_XYZ_CDM2, as
+    /// dispread writes it. Patches without a reading are left out.
+    fn write_ti3(&self, path: &Path) -> io::Result<()> {
+        let measured: Vec<(usize, ColorRGB, [f64; 3])> = self.rows.iter().zip(&self.lines)
+        .filter_map(|(row, &id)| match (row.x, row.y, row.y_lum) {
+            (Some(x), Some(y), Some(big_y)) if !row.timed_out => Some((id, row.colour, xyy_to_xyz(x, y, big_y))),
+            _ => None,
+        })
+        .collect();
+        let white = measured.iter().map(|(_, _, xyz)| *xyz).fold([0.0; 3], |w, xyz| if xyz[1] > w[1] { xyz } else { w });
+        let scale = if white[1] > 0.0 { 100.0 / white[1] } else { 1.0 };
+        let percent = |c: ColorRGB, v: u16| v as f64 / ((1u32 << c.depth_bits.clamp(1, 16)) - 1) as f64 * 100.0;
+
+        let mut out = String::from("CTI3\n\nDESCRIPTOR \"Argyll Calibration Target chart information 3\"\nORIGINATOR \"colourspace calibrationclient\"\n");
+        out.push_str("DEVICE_CLASS \"DISPLAY\"\nCOLOR_REP \"RGB_XYZ\"\n");
+        out.push_str("KEYWORD \"LUMINANCE_XYZ_CDM2\"\nKEYWORD \"NORMALIZED_TO_Y_100\"\n");
+        out.push_str(&format!("LUMINANCE_XYZ_CDM2 \"{:.6} {:.6} {:.6}\"\nNORMALIZED_TO_Y_100 \"YES\"\n\n", white[0], white[1], white[2]));
+        out.push_str("NUMBER_OF_FIELDS 7\nBEGIN_DATA_FORMAT\nSAMPLE_ID RGB_R RGB_G RGB_B XYZ_X XYZ_Y XYZ_Z\nEND_DATA_FORMAT\n\n");
+        out.push_str(&format!("NUMBER_OF_SETS {}\nBEGIN_DATA\n", measured.len()));
+        for (id, c, xyz) in &measured {
+            out.push_str(&format!(
+                "{} {:.5} {:.5} {:.5} {:.6} {:.6} {:.6}\n",
+                id, percent(*c, c.red), percent(*c, c.green), percent(*c, c.blue), xyz[0] * scale, xyz[1] * scale, xyz[2] * scale
+            ));
+        }
+        out.push_str("END_DATA\n");
+        fs::write(path, out)
+    }
+
+    /// Write the measured patches in `format`'s layout instead of this sweep's own CSV.
+    pub fn write_export(&self, path: &Path, format: ExportFormat) -> io::Result<()> {
+        fs::write(path, self.export(format))
+    }
+
+    fn export(&self, format: ExportFormat) -> String {
+        let measured: Vec<(usize, &SweepRow, [f64; 3])> = self.rows.iter().enumerate()
+        .filter_map(|(i, row)| match (row.x, row.y, row.y_lum) {
+            (Some(x), Some(y), Some(big_y)) if !row.timed_out => Some((i + 1, row, xyy_to_xyz(x, y, big_y))),
+            _ => None,
+        })
+        .collect();
+        let unit = |c: ColorRGB, v: u16| v as f64 / ((1u32 << c.depth_bits.clamp(1, 16)) - 1) as f64;
+
+        let mut out = String::new();
+        match format {
+            ExportFormat::Calman => {
+                out.push_str("Name,R,G,B,X,Y,Z,x,y,Y(cd/m2)\n");
+                for (n, row, xyz) in &measured {
+                    let name = row.label.clone().unwrap_or_else(|| format!("Patch {}", n));
+                    let c = row.colour;
+                    out.push_str(&format!(
+                        "\"{}\",{},{},{},{:.6},{:.6},{:.6},{:.6},{:.6},{:.6}\n",
+                        name.replace('"', "\"\""), c.red, c.green, c.blue, xyz[0], xyz[1], xyz[2],
+                        row.x.unwrap_or_default(), row.y.unwrap_or_default(), row.y_lum.unwrap_or_default()
+                    ));
+                }
+            }
+            ExportFormat::LightSpace => {
+                let white = measured.iter().map(|(_, _, xyz)| xyz[1]).fold(0.0, f64::max);
+                let scale = if white > 0.0 { 1.0 / white } else { 1.0 };
+                out.push_str("R,G,B,X,Y,Z\n");
+                for (_, row, xyz) in &measured {
+                    let c = row.colour;
+                    out.push_str(&format!(
+                        "{:.6},{:.6},{:.6},{:.6},{:.6},{:.6}\n",
+                        unit(c, c.red), unit(c, c.green), unit(c, c.blue), xyz[0] * scale, xyz[1] * scale, xyz[2] * scale
+                    ));
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reading as the server reports it.
+    fn reading(x: f64, y: f64, y_lum: f64) -> MeasurementResult {
+        MeasurementResult {
+            red: 0, green: 0, blue: 0, x: Some(x), y: Some(y), y_lum: Some(y_lum),
+            label: None, fullscreen: false, hide_markers: false, hold: None, shapes: Vec::new(), error: None,
+        }
+    }
+
+    /// Drive `sweep` the way the render loop does, against a mock server: each request is
+    /// answered on the next frame with `meter(patch)`, or never if that is None. Returns
+    /// how the sweep ended.
+    fn drive(sweep: &mut Sweep, meter: impl FnMut(ColorRGB) -> Option<MeasurementResult>) -> SweepAction {
+        drive_until(sweep, meter, usize::MAX)
+    }
+
+    /// `drive`, but give up (as if the client died) once `rows` rows are recorded.
+    fn drive_until(sweep: &mut Sweep, mut meter: impl FnMut(ColorRGB) -> Option<MeasurementResult>, rows: usize) -> SweepAction {
+        let mut now = Instant::now();
+        let (mut received, mut last) = (0, None);
+        for _ in 0..100_000 {
+            if sweep.rows().len() >= rows {
+                return SweepAction::None;
+            }
+            match sweep.tick(now, true, received, last.as_ref()) {
+                SweepAction::Request(patch) => {
+                    if let Some(m) = meter(patch) {
+                        received += 1;
+                        last = Some(m);
+                    }
+                }
+                SweepAction::None => {}
+                end => return end,
+            }
+            now += Duration::from_millis(10);
+        }
+        panic!("sweep never finished");
+    }
+
+    /// A scratch file path unique to this test run.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("colourspace-sweep-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn gamma_sweep_against_a_mock_writes_one_row_per_step() {
+        const STEPS: usize = 11;
+        let mut sweep = Sweep::gamma(STEPS, Duration::ZERO, 1, false);
+        // a 2.2 gamma display peaking at 100 cd/m2
+        let action = drive(&mut sweep, |patch| Some(reading(0.3127, 0.3290, 100.0 * (patch.red as f64 / 255.0).powf(2.2))));
+        assert_eq!(action, SweepAction::Finished);
+
+        let path = temp_path("gamma.csv");
+        sweep.write_csv(&path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), STEPS + 1);
+        assert!(lines[0].starts_with("step,level,"));
+        // mid grey: code 128, Y and the point gamma from the mock's curve
+        let mid: Vec<&str> = lines[6].split(',').collect();
+        assert_eq!((mid[0], mid[2]), ("6", "128"));
+        assert_eq!(mid[8], format!("{:.6}", 100.0 * (128.0f64 / 255.0).powf(2.2)));
+        let gamma: f64 = mid[11].parse().unwrap();
+        assert!((gamma - 2.2).abs() < 0.02, "gamma {}", gamma);
+    }
+
+    #[test]
+    fn repeats_are_averaged_into_one_row() {
+        let mut sweep = Sweep::gamma(2, Duration::ZERO, 3, false);
+        // the mock drifts: each patch reads Y of 10, 12 then 17
+        let mut n = 0;
+        let action = drive(&mut sweep, |_| {
+            n += 1;
+            Some(reading(0.30 + 0.01 * (n % 3) as f64, 0.32, [10.0, 12.0, 17.0][(n - 1) % 3]))
+        });
+        assert_eq!(action, SweepAction::Finished);
+        assert_eq!(n, 6);
+        for row in sweep.rows() {
+            assert_eq!(row.samples, 3);
+            assert!((row.y_lum.unwrap() - 13.0).abs() < 1e-12);
+            assert!((row.x.unwrap() - 0.31).abs() < 1e-12);
+            assert_eq!(row.y, Some(0.32));
+            // sample stddev of 10, 12, 17
+            assert!((row.y_lum_stddev.unwrap() - 13.0f64.sqrt()).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn average_skips_missing_fields() {
+        let mut blank = reading(0.0, 0.0, 0.0);
+        (blank.x, blank.y, blank.y_lum) = (None, None, None);
+        assert_eq!(average(&[reading(0.2, 0.3, 4.0), blank.clone()]), (Some(0.2), Some(0.3), Some(4.0), None));
+        assert_eq!(average(&[blank]), (None, None, None, None));
+    }
+
+    #[test]
+    fn patch_label_survives_parsing_into_the_csv() {
+        let xml = "<CS_RMC version=1><result><name>Grey 50%, \"mid\"</name><x>0.3127</x><y>0.3290</y><Y>21.4</Y></result></CS_RMC>";
+        let parsed = crate::lan::parse_measurement_from_xml(xml, 128, 128, 128).unwrap().remove(0);
+        assert_eq!(parsed.label.as_deref(), Some("Grey 50%, \"mid\""));
+
+        let mut sweep = Sweep::gamma(2, Duration::ZERO, 1, false);
+        assert_eq!(drive(&mut sweep, |_| Some(parsed.clone())), SweepAction::Finished);
+        let path = temp_path("label.csv");
+        sweep.write_csv(&path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        // quoted, with the embedded quotes doubled, so the comma stays in the field
+        assert!(csv.lines().nth(1).unwrap().ends_with(",\"Grey 50%, \"\"mid\"\"\",ok"), "{}", csv);
+    }
+
+    #[test]
+    fn outlier_rejection_drops_a_spike() {
+        let mut sweep = Sweep::gamma(2, Duration::ZERO, 5, true);
+        // every patch reads around 0.5 cd/m2, except one reading that spikes to 40
+        let mut n = 0;
+        let action = drive(&mut sweep, |_| {
+            n += 1;
+            let y_lum = [0.52, 0.48, 40.0, 0.50, 0.49][(n - 1) % 5];
+            Some(reading(if y_lum > 1.0 { 0.6 } else { 0.31 }, 0.33, y_lum))
+        });
+        assert_eq!(action, SweepAction::Finished);
+        for row in sweep.rows() {
+            // highest and lowest dropped, median of 0.49, 0.50, 0.52
+            assert_eq!(row.samples, 3);
+            assert_eq!(row.y_lum, Some(0.50));
+            assert_eq!(row.x, Some(0.31));
+        }
+        // the plain mean is dragged well off by the spike
+        let spiked: Vec<MeasurementResult> = [0.52, 0.48, 40.0, 0.50, 0.49].iter().map(|&y_lum| reading(0.31, 0.33, y_lum)).collect();
+        assert!(average(&spiked).2.unwrap() > 8.0);
+        assert_eq!(median_rejecting_outliers(&spiked).0.2, Some(0.50));
+    }
+
+    #[test]
+    fn outlier_rejection_needs_three_readings() {
+        let two = [reading(0.3, 0.3, 1.0), reading(0.3, 0.3, 3.0)];
+        assert_eq!(median_rejecting_outliers(&two), ((Some(0.3), Some(0.3), Some(2.0), Some(2.0f64.sqrt())), 2));
+    }
+
+    #[test]
+    fn uniformity_grid_walks_row_by_row() {
+        let grid: UniformityGrid = "2x3".parse().unwrap();
+        assert_eq!(grid.positions(), [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2)]);
+        assert_eq!(UniformityGrid { rows: 1, cols: 1 }.positions(), [(0, 0)]);
+        for bad in ["0x3", "3", "3x", "ax2"] {
+            assert!(bad.parse::<UniformityGrid>().is_err(), "{}", bad);
+        }
+
+        // the sweep measures the cells in that order: the n-th reading lands in the n-th cell
+        let mut sweep = Sweep::uniformity(grid, Duration::ZERO, 1, false);
+        assert_eq!(sweep.current_cell(), Some((grid, 0, 0)));
+        let mut n = 0.0;
+        assert_eq!(drive(&mut sweep, |_| { n += 1.0; Some(reading(0.31, 0.33, n)) }), SweepAction::Finished);
+        let path = temp_path("uniformity.csv");
+        sweep.write_csv(&path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let cells: Vec<(&str, &str, &str)> = csv.lines().skip(1).map(|l| {
+            let f: Vec<&str> = l.split(',').collect();
+            (f[0], f[1], f[4])
+        }).collect();
+        assert_eq!(cells, [
+            ("1", "1", "1.000000"), ("1", "2", "2.000000"), ("1", "3", "3.000000"),
+            ("2", "1", "4.000000"), ("2", "2", "5.000000"), ("2", "3", "6.000000"),
+        ]);
+    }
+
+    /// A mock meter that reads every patch except the `silent`-th request (1-based).
+    fn meter_silent_on(silent: usize) -> impl FnMut(ColorRGB) -> Option<MeasurementResult> {
+        let mut n = 0;
+        move |_| {
+            n += 1;
+            (n != silent).then(|| reading(0.31, 0.33, n as f64))
+        }
+    }
+
+    #[test]
+    fn unanswered_patch_gets_a_timeout_row_and_the_sweep_goes_on() {
+        let mut sweep = Sweep::gamma(5, Duration::ZERO, 1, false).with_measure_timeout(Some(Duration::from_millis(500)), OnTimeout::Skip);
+        assert_eq!(drive(&mut sweep, meter_silent_on(3)), SweepAction::Finished);
+
+        let path = temp_path("timeout.csv");
+        sweep.write_csv(&path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let rows: Vec<(String, &str)> = csv.lines().skip(1).map(|l| {
+            let f: Vec<&str> = l.split(',').collect();
+            (f[8].to_string(), f[13])
+        }).collect();
+        assert_eq!(rows, [
+            ("1.000000".to_string(), "ok"), ("2.000000".to_string(), "ok"), (String::new(), "timeout"),
+            ("4.000000".to_string(), "ok"), ("5.000000".to_string(), "ok"),
+        ]);
+    }
+
+    #[test]
+    fn unanswered_patch_can_abort_the_sweep() {
+        let mut sweep = Sweep::gamma(5, Duration::ZERO, 1, false).with_measure_timeout(Some(Duration::from_millis(500)), OnTimeout::Abort);
+        assert_eq!(drive(&mut sweep, meter_silent_on(3)), SweepAction::TimedOut);
+        assert_eq!(sweep.rows().len(), 2);
+    }
+
+    #[test]
+    fn resumed_sweep_measures_only_what_is_left() {
+        const N: usize = 6;
+        const K: usize = 4;
+        let progress = temp_path("gamma.resume");
+        let mut first = Sweep::gamma(N, Duration::ZERO, 1, false);
+        let mut n = 0.0;
+        drive_until(&mut first, |_| {
+            n += 1.0;
+            Some(MeasurementResult { label: Some(format!("first run {}", n)), ..reading(0.31, 0.33, n) })
+        }, K);
+        first.save_progress(&progress).unwrap();
+        drop(first);
+
+        let resumed = Sweep::resume(&progress, Duration::ZERO, 1, false);
+        let _ = fs::remove_file(&progress);
+        let mut resumed = resumed.unwrap();
+        assert_eq!(resumed.rows().len(), K);
+        let mut requested = Vec::new();
+        assert_eq!(drive(&mut resumed, |patch| { requested.push(patch); Some(reading(0.31, 0.33, 100.0 + requested.len() as f64)) }), SweepAction::Finished);
+
+        // only the last N-K patches were shown again, and the saved rows came back intact
+        assert_eq!(requested, Sweep::gamma(N, Duration::ZERO, 1, false).patches[K..].iter().map(|(_, c)| *c).collect::<Vec<_>>());
+        let rows = resumed.rows();
+        assert_eq!(rows.iter().map(|r| r.y_lum.unwrap()).collect::<Vec<_>>(), [1.0, 2.0, 3.0, 4.0, 101.0, 102.0]);
+        assert_eq!(rows[1].label.as_deref(), Some("first run 2"));
+        assert_eq!((rows[3].x, rows[3].y, rows[3].samples), (Some(0.31), Some(0.33), 1));
+    }
+
+    #[test]
+    fn patch_list_rows_come_out_in_input_order() {
+        let input = temp_path("patches.csv");
+        fs::write(&input, "red,green,blue,bits\n# a dark grey, then 10-bit white, then red\n\n16,16,16\n1023,1023,1023,10\n255,0,0,8\n").unwrap();
+        let patches = load_patch_list(&input, 8);
+        let _ = fs::remove_file(&input);
+        let patches = patches.unwrap();
+        assert_eq!(patches.iter().map(|(line, c)| (*line, c.red, c.depth_bits)).collect::<Vec<_>>(), [(4, 16, 8), (5, 1023, 10), (6, 255, 8)]);
+
+        let mut sweep = Sweep::patch_list(patches, Duration::ZERO, 1, false);
+        // the mock reads back each patch's red code as its Y
+        assert_eq!(drive(&mut sweep, |patch| Some(reading(0.31, 0.33, patch.red as f64))), SweepAction::Finished);
+        let output = temp_path("patches-results.csv");
+        sweep.write_csv(&output).unwrap();
+        let csv = fs::read_to_string(&output).unwrap();
+        let _ = fs::remove_file(&output);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "line,red,green,blue,bits,x,y,Y,Y_stddev,samples,label,status");
+        let rows: Vec<Vec<&str>> = lines[1..].iter().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows.iter().map(|f| (f[0], f[1], f[4], f[7])).collect::<Vec<_>>(), [
+            ("4", "16", "8", "16.000000"), ("5", "1023", "10", "1023.000000"), ("6", "255", "8", "255.000000"),
+        ]);
+    }
+
+    #[test]
+    fn ti1_patches_measure_into_a_ti3() {
+        let input = temp_path("target.ti1");
+        fs::write(&input, "CTI1\n\nDESCRIPTOR \"Argyll Calibration Target chart information 1\"\nCOLOR_REP \"RGB\"\n\n\
+            NUMBER_OF_FIELDS 7\nBEGIN_DATA_FORMAT\nSAMPLE_ID RGB_R RGB_G RGB_B XYZ_X XYZ_Y XYZ_Z\nEND_DATA_FORMAT\n\n\
+            NUMBER_OF_SETS 4\nBEGIN_DATA\n1 100.00 100.00 100.00 95.046 100.00 108.91\n2 0.0000 0.0000 0.0000 1.0000 1.0000 1.0000\n\
+            3 100.00 0.0000 0.0000 41.830 22.052 2.9132\n4 50.000 50.000 50.000 20.344 21.404 23.311\nEND_DATA\n\n\
+            CTI1\nBEGIN_DATA_FORMAT\nINDEX RGB_R RGB_G RGB_B\nEND_DATA_FORMAT\nBEGIN_DATA\n0 1 1 1\nEND_DATA\n").unwrap();
+        let patches = load_ti1(&input, 8);
+        let _ = fs::remove_file(&input);
+        let patches = patches.unwrap();
+        // the second table is not read
+        assert_eq!(patches.iter().map(|(id, c)| (*id, c.red, c.green, c.blue)).collect::<Vec<_>>(), [
+            (1, 255, 255, 255), (2, 0, 0, 0), (3, 255, 0, 0), (4, 128, 128, 128),
+        ]);
+
+        let mut sweep = Sweep::ti1(patches, Duration::ZERO, 1, false);
+        // the mock's Y is 200 cd/m2 at full red
+        assert_eq!(drive(&mut sweep, |patch| Some(reading(0.3127, 0.3290, patch.red as f64 / 255.0 * 200.0 + 0.5))), SweepAction::Finished);
+        let output = temp_path("target.ti3");
+        sweep.write_csv(&output).unwrap();
+        let ti3 = fs::read_to_string(&output).unwrap();
+        let _ = fs::remove_file(&output);
+        assert!(ti3.starts_with("CTI3\n"));
+        assert!(ti3.contains("\nNUMBER_OF_SETS 4\n"), "{}", ti3);
+        let data: Vec<Vec<&str>> = ti3.lines().skip_while(|l| *l != "BEGIN_DATA").skip(1).take_while(|l| *l != "END_DATA")
+        .map(|l| l.split_whitespace().collect()).collect();
+        assert_eq!(data.len(), 4);
+        assert_eq!(data.iter().map(|f| (f[0], f[1], f[2], f[3])).collect::<Vec<_>>(), [
+            ("1", "100.00000", "100.00000", "100.00000"), ("2", "0.00000", "0.00000", "0.00000"),
+            ("3", "100.00000", "0.00000", "0.00000"), ("4", "50.19608", "50.19608", "50.19608"),
+        ]);
+        // the brightest patch is Y 100
+        assert_eq!(data[0][5], "100.000000");
+    }
+
+    #[test]
+    fn crossfade_holds_the_request_until_it_has_finished() {
+        let (black, white) = (ColorRGB { red: 0, green: 0, blue: 0, depth_bits: 8 }, ColorRGB { red: 255, green: 255, blue: 255, depth_bits: 8 });
+        let (settle, crossfade) = (Duration::from_millis(20), Duration::from_millis(100));
+        let mut sweep = Sweep::patch_list(vec![(1, black), (2, white)], settle, 1, false).with_crossfade(crossfade);
+        let start = Instant::now();
+        // the first patch has nothing to fade from
+        assert_eq!(sweep.tick(start + settle, true, 0, None), SweepAction::Request(black));
+        let measured = start + settle + Duration::from_millis(5);
+        assert_eq!(sweep.tick(measured, true, 1, Some(&reading(0.31, 0.33, 0.1))), SweepAction::None);
+
+        let mut now = measured;
+        let mut blends = 0;
+        let requested_at = loop {
+            now += Duration::from_millis(5);
+            assert!(now < measured + Duration::from_secs(1), "no request for the second patch");
+            let shown = sweep.displayed_patch(now).unwrap();
+            match sweep.tick(now, true, 1, None) {
+                SweepAction::Request(colour) => {
+                    assert_eq!((colour, shown), (white, white));
+                    break now;
+                }
+                action => assert_eq!(action, SweepAction::None),
+            }
+            blends += (shown != black && shown != white) as usize;
+        };
+        // the fade ran its course and the patch settled before the request
+        assert!(requested_at >= measured + crossfade + settle, "requested {:?} after the reading", requested_at - measured);
+        assert!(blends >= 15, "{} blended frames", blends);
+        assert_eq!(sweep.displayed_patch(requested_at + Duration::from_millis(1)), Some(white));
+    }
+
+    /// White and red measured, blue timed out.
+    fn export_sweep() -> Sweep {
+        let rgb = |red, green, blue| ColorRGB { red, green, blue, depth_bits: 8 };
+        let mut sweep = Sweep::patch_list(vec![(1, rgb(255, 255, 255)), (2, rgb(255, 0, 0)), (3, rgb(0, 0, 255))], Duration::ZERO, 1, false)
+        .with_measure_timeout(Some(Duration::from_millis(50)), OnTimeout::Skip);
+        let action = drive(&mut sweep, |patch| match (patch.red, patch.blue) {
+            (255, 255) => Some(MeasurementResult { label: Some("Peak \"100%\"".to_string()), ..reading(0.3127, 0.3290, 100.0) }),
+            (255, 0) => Some(reading(0.64, 0.33, 21.26)),
+            _ => None,
+        });
+        assert_eq!(action, SweepAction::Finished);
+        sweep
+    }
+
+    #[test]
+    fn calman_export_columns() {
+        let path = temp_path("calman.csv");
+        export_sweep().write_export(&path, ExportFormat::Calman).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(text, "Name,R,G,B,X,Y,Z,x,y,Y(cd/m2)\n\
+            \"Peak \"\"100%\"\"\",255,255,255,95.045593,100.000000,108.905775,0.312700,0.329000,100.000000\n\
+            \"Patch 2\",255,0,0,41.231515,21.260000,1.932727,0.640000,0.330000,21.260000\n");
+    }
+
+    #[test]
+    fn lightspace_export_columns() {
+        assert_eq!(export_sweep().export(ExportFormat::LightSpace), "R,G,B,X,Y,Z\n\
+            1.000000,1.000000,1.000000,0.950456,1.000000,1.089058\n\
+            1.000000,0.000000,0.000000,0.412315,0.212600,0.019327\n");
+        assert_eq!("LightSpace".parse(), Ok(ExportFormat::LightSpace));
+        assert!("ti3".parse::<ExportFormat>().is_err());
+    }
+
+    #[test]
+    fn patch_list_errors_name_the_line() {
+        let input = temp_path("bad-patches.csv");
+        fs::write(&input, "16,16,16\n300,0,0\n").unwrap();
+        let err = load_patch_list(&input, 8).unwrap_err();
+        let _ = fs::remove_file(&input);
+        assert!(err.to_string().contains(" line 2: "), "{}", err);
+    }
+}
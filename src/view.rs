@@ -0,0 +1,154 @@
+use crate::lan::{ColorRGB, ShapeInstruction};
+
+// What the window shows for the state of the link: the server's patch, a colour we asked
+// to have measured, or a stand-in while the link is down. Also what closing a window does.
+
+/// Screen contents while the link to the server is down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectMode {
+    /// keep re-drawing the last frame the server drove
+    Hold,
+    Black,
+    /// dark grey, so it's obvious no measurement patch is up
+    Background,
+}
+
+impl std::str::FromStr for DisconnectMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hold" => Ok(DisconnectMode::Hold),
+            "black" => Ok(DisconnectMode::Black),
+            "background" => Ok(DisconnectMode::Background),
+            other => Err(format!("unknown disconnect mode '{}', expected: hold, black, background", other)),
+        }
+    }
+}
+
+/// `DisconnectMode::Background`'s colour.
+pub const DISCONNECTED_BACKGROUND: ColorRGB = ColorRGB { red: 64, green: 64, blue: 64, depth_bits: 8 };
+
+/// One frame's patch: shapes to draw (none for a flat field of `colour`), and whether
+/// the field covers the whole screen rather than the region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PatchView<'a> {
+    pub shapes: &'a [ShapeInstruction],
+    pub fullscreen: bool,
+    pub colour: ColorRGB,
+}
+
+impl PatchView<'_> {
+    fn flat(colour: ColorRGB) -> Self {
+        PatchView { shapes: &[], fullscreen: false, colour }
+    }
+}
+
+/// The patch to draw this frame.
+/// - link up: a measurement we requested wins until its reading is in (`pending_request`),
+///   then a patch still inside its hold time (`held`), then the server's latest (`live`);
+/// - link down: per `mode`, the last patch drawn with the link up (`last_good`; `live`'s
+///   colour before there was one), black, or the background grey.
+pub fn on_screen<'a>(
+    link_down: bool,
+    mode: DisconnectMode,
+    pending_request: Option<ColorRGB>,
+    held: Option<PatchView<'a>>,
+    live: PatchView<'a>,
+    last_good: Option<PatchView<'a>>,
+) -> PatchView<'a> {
+    match (link_down, mode) {
+        (false, _) => match (pending_request, held) {
+            (Some(requested), _) => PatchView::flat(requested),
+            (None, Some(held)) => held,
+            (None, None) => live,
+        },
+        (true, DisconnectMode::Hold) => last_good.unwrap_or(PatchView::flat(live.colour)),
+        (true, DisconnectMode::Black) => PatchView::flat(ColorRGB::default()),
+        (true, DisconnectMode::Background) => PatchView::flat(DISCONNECTED_BACKGROUND),
+    }
+}
+
+/// What closing a window (its close button, or the WM asking) does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseAction {
+    /// the main window went: leave the app, mirrors and all
+    Quit,
+    /// just this mirror goes; the app carries on
+    CloseMirror(u32),
+}
+
+/// Decide what `closed` (a window id) closing means when `main` is the main window's id.
+/// SDL only turns a close into `Quit` for the last window, so with mirrors open the main
+/// window's close button would otherwise leave the app running.
+pub fn on_close(closed: u32, main: u32) -> CloseAction {
+    if closed == main { CloseAction::Quit } else { CloseAction::CloseMirror(closed) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lan::{Clamped, GeometryUnits, RectangleGeometry, RectangleShape};
+
+    fn grey(level: u16) -> ColorRGB {
+        ColorRGB { red: level, green: level, blue: level, depth_bits: 8 }
+    }
+
+    fn patch(level: u16) -> ShapeInstruction {
+        ShapeInstruction::Rectangle(RectangleShape {
+            color: grey(level),
+            geometry: RectangleGeometry { width: 0.1, height: 0.1, units: GeometryUnits::Normalized },
+            ycbcr: None, xyz: None, clamped: Clamped::default(), border: None, corner_radius: 0.0, z: 0,
+        })
+    }
+
+    #[test]
+    fn hold_keeps_the_last_patch_across_a_disconnect() {
+        let before = [patch(128)];
+        let last_good = PatchView { shapes: &before, fullscreen: false, colour: grey(128) };
+        // the worker cleared its shapes when the link dropped
+        let live = PatchView::flat(grey(0));
+
+        assert_eq!(on_screen(false, DisconnectMode::Hold, None, None, last_good, None), last_good);
+        assert_eq!(on_screen(true, DisconnectMode::Hold, None, None, live, Some(last_good)), last_good);
+        // reconnected: the server drives the screen again
+        let after = [patch(200)];
+        let live = PatchView { shapes: &after, fullscreen: false, colour: grey(200) };
+        assert_eq!(on_screen(false, DisconnectMode::Hold, None, None, live, Some(last_good)), live);
+    }
+
+    #[test]
+    fn other_disconnect_modes_replace_the_patch() {
+        let before = [patch(128)];
+        let last_good = PatchView { shapes: &before, fullscreen: true, colour: grey(128) };
+        let live = PatchView::flat(grey(90));
+        assert_eq!(on_screen(true, DisconnectMode::Black, None, None, live, Some(last_good)), PatchView::flat(ColorRGB::default()));
+        assert_eq!(on_screen(true, DisconnectMode::Background, None, None, live, Some(last_good)), PatchView::flat(DISCONNECTED_BACKGROUND));
+        // nothing drawn with the link up yet: hold what the worker has
+        assert_eq!(on_screen(true, DisconnectMode::Hold, None, None, live, None), live);
+    }
+
+    #[test]
+    fn requested_colour_wins_until_its_reading_arrives() {
+        let mut state = crate::lan::SharedState { request_colour: grey(100), measuring: true, ..Default::default() };
+        let echoed = [patch(99)];
+        let shown = |state: &crate::lan::SharedState, shapes: &[ShapeInstruction]| {
+            let live = PatchView { shapes, fullscreen: false, colour: shapes.first().map_or(grey(0), ShapeInstruction::color) };
+            on_screen(false, DisconnectMode::Hold, state.pending_request(), None, live, None).colour
+        };
+
+        // request out, then the server echoes a slightly different patch before the reading
+        let mut seen = vec![shown(&state, &[]), shown(&state, &echoed), shown(&state, &echoed)];
+        // the reading is in: the server's shapes decide from here on
+        state.measuring = false;
+        seen.push(shown(&state, &echoed));
+        seen.push(shown(&state, &echoed));
+        assert_eq!(seen, [grey(100), grey(100), grey(100), grey(99), grey(99)]);
+    }
+
+    #[test]
+    fn closing_the_main_window_quits_and_a_mirror_only_closes() {
+        assert_eq!(on_close(1, 1), CloseAction::Quit);
+        assert_eq!(on_close(3, 1), CloseAction::CloseMirror(3));
+    }
+}
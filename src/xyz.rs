@@ -0,0 +1,148 @@
+use std::str::FromStr;
+
+use crate::delta_e::{D65, xyy_to_xyz};
+use crate::lan::{Clamped, ColorRGB};
+
+/// Display primaries a device-independent patch (`<xyY>`/`<XYZ>`) is converted for.
+/// All three use a D65 white. The display is assumed to be calibrated to them, with
+/// Y = 1 being its white: sRGB and Display P3 are encoded with the sRGB curve,
+/// BT.2020 with BT.1886 (a pure 2.4 power law, zero black).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primaries {
+    /// sRGB / BT.709 (the default)
+    Srgb,
+    DisplayP3,
+    Bt2020,
+}
+
+impl FromStr for Primaries {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "srgb" | "709" | "bt709" => Ok(Primaries::Srgb),
+            "p3" | "display-p3" | "displayp3" => Ok(Primaries::DisplayP3),
+            "2020" | "bt2020" => Ok(Primaries::Bt2020),
+            _ => Err(format!("unknown primaries '{}', expected: srgb, p3, bt2020", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Primaries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Primaries::Srgb => "sRGB",
+            Primaries::DisplayP3 => "Display P3",
+            Primaries::Bt2020 => "BT.2020",
+        })
+    }
+}
+
+impl Primaries {
+    /// Red, green and blue chromaticities x, y.
+    fn chromaticities(self) -> [(f64, f64); 3] {
+        match self {
+            Primaries::Srgb => [(0.640, 0.330), (0.300, 0.600), (0.150, 0.060)],
+            Primaries::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060)],
+            Primaries::Bt2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046)],
+        }
+    }
+
+    /// Linear light 0..1 to signal 0..1.
+    fn encode(self, v: f64) -> f64 {
+        match self {
+            Primaries::Srgb | Primaries::DisplayP3 => if v <= 0.0031308 { 12.92 * v } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 },
+            Primaries::Bt2020 => v.powf(1.0 / 2.4),
+        }
+    }
+
+    /// XYZ (white Y = 1) to linear RGB.
+    fn xyz_to_linear(self, xyz: [f64; 3]) -> [f64; 3] {
+        // columns: XYZ of each primary at unit luminance, scaled so R + G + B = white
+        let p = self.chromaticities().map(|(x, y)| xyy_to_xyz(x, y, 1.0));
+        let primaries = [[p[0][0], p[1][0], p[2][0]], [p[0][1], p[1][1], p[2][1]], [p[0][2], p[1][2], p[2][2]]];
+        let s = mul(invert(primaries), xyy_to_xyz(D65.0, D65.1, 1.0));
+        let rgb_to_xyz = primaries.map(|row| [row[0] * s[0], row[1] * s[1], row[2] * s[2]]);
+        mul(invert(rgb_to_xyz), xyz)
+    }
+}
+
+fn mul(m: [[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn invert(m: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let c = |r: usize, k: usize| m[(r + 1) % 3][(k + 1) % 3] * m[(r + 2) % 3][(k + 2) % 3] - m[(r + 1) % 3][(k + 2) % 3] * m[(r + 2) % 3][(k + 1) % 3];
+    let det = m[0][0] * c(0, 0) + m[0][1] * c(0, 1) + m[0][2] * c(0, 2);
+    [[c(0, 0) / det, c(1, 0) / det, c(2, 0) / det], [c(0, 1) / det, c(1, 1) / det, c(2, 1) / det], [c(0, 2) / det, c(1, 2) / det, c(2, 2) / det]]
+}
+
+/// A patch requested in device-independent coordinates (`<xyY>` or `<XYZ>`), kept next
+/// to the converted RGB like `Ycbcr`. `xyz` is relative to the display white (Y = 1).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct XyzColour {
+    pub xyz: [f64; 3],
+    pub depth_bits: u8,
+    pub primaries: Primaries,
+}
+
+impl XyzColour {
+    pub fn from_xyy(x: f64, y: f64, big_y: f64, depth_bits: u8, primaries: Primaries) -> Self {
+        Self { xyz: xyy_to_xyz(x, y, big_y), depth_bits, primaries }
+    }
+
+    /// Convert to R'G'B' code values. Colours outside the primaries' gamut (or brighter
+    /// than white) are clamped per channel, and the channels that were are reported.
+    pub fn to_rgb(self) -> (ColorRGB, Clamped) {
+        let bits = self.depth_bits.clamp(1, 16);
+        let max = ((1u32 << bits) - 1) as f64;
+        // some tolerance so white and the primaries themselves don't count as clamped when
+        // given to the usual 4 decimals (that alone puts them ~1e-4 outside the cube)
+        const EPSILON: f64 = 1e-3;
+        let linear = self.primaries.xyz_to_linear(self.xyz);
+        let clamped = Clamped::from_normalized(linear, EPSILON);
+        let [r, g, b] = linear.map(|v| (self.primaries.encode(v.clamp(0.0, 1.0)) * max).round() as u16);
+        (ColorRGB::from_components_u16(r, g, b, bits), clamped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(x: f64, y: f64, big_y: f64, bits: u8, primaries: Primaries) -> ((u16, u16, u16), Clamped) {
+        let (c, clamped) = XyzColour::from_xyy(x, y, big_y, bits, primaries).to_rgb();
+        ((c.red, c.green, c.blue), clamped)
+    }
+
+    #[test]
+    fn d65_white_is_full_scale_under_every_primaries() {
+        for primaries in [Primaries::Srgb, Primaries::DisplayP3, Primaries::Bt2020] {
+            assert_eq!(rgb(0.3127, 0.3290, 1.0, 8, primaries), ((255, 255, 255), Clamped::default()), "{}", primaries);
+            assert_eq!(rgb(0.3127, 0.3290, 1.0, 10, primaries).0, (1023, 1023, 1023), "{}", primaries);
+        }
+        // 18% grey through the sRGB curve: 0.4614 of full scale
+        assert_eq!(rgb(0.3127, 0.3290, 0.18, 8, Primaries::Srgb).0, (118, 118, 118));
+        assert_eq!(rgb(0.3127, 0.3290, 0.0, 8, Primaries::Srgb).0, (0, 0, 0));
+    }
+
+    #[test]
+    fn primaries_and_out_of_gamut_colours() {
+        // sRGB red at its own luminance
+        assert_eq!(rgb(0.64, 0.33, 0.2126, 8, Primaries::Srgb), ((255, 0, 0), Clamped::default()));
+        // BT.2020 green is outside sRGB: red and blue go negative
+        let (_, clamped) = rgb(0.170, 0.797, 0.3, 8, Primaries::Srgb);
+        assert_eq!(clamped, Clamped { red: true, green: false, blue: true });
+        assert_eq!(rgb(0.170, 0.797, 0.6780, 8, Primaries::Bt2020), ((0, 255, 0), Clamped::default()));
+        // brighter than white clamps every channel
+        assert_eq!(rgb(0.3127, 0.3290, 1.5, 8, Primaries::Srgb), ((255, 255, 255), Clamped { red: true, green: true, blue: true }));
+    }
+
+    #[test]
+    fn primaries_names() {
+        assert_eq!("sRGB".parse(), Ok(Primaries::Srgb));
+        assert_eq!("display-p3".parse(), Ok(Primaries::DisplayP3));
+        assert_eq!("2020".parse(), Ok(Primaries::Bt2020));
+        assert!("adobe".parse::<Primaries>().is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::str::FromStr;
+
+use crate::lan::{Clamped, ColorRGB};
+
+/// Luma coefficients (Kr, Kb) of the supported YCbCr matrices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YcbcrMatrix {
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+impl FromStr for YcbcrMatrix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().trim_start_matches("bt").trim_start_matches('.') {
+            "601" => Ok(YcbcrMatrix::Bt601),
+            "709" => Ok(YcbcrMatrix::Bt709),
+            "2020" => Ok(YcbcrMatrix::Bt2020),
+            _ => Err(format!("unknown YCbCr matrix '{}', expected: 601, 709, 2020", s)),
+        }
+    }
+}
+
+impl YcbcrMatrix {
+    fn kr_kb(self) -> (f64, f64) {
+        match self {
+            YcbcrMatrix::Bt601 => (0.299, 0.114),
+            YcbcrMatrix::Bt709 => (0.2126, 0.0722),
+            YcbcrMatrix::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+/// A patch requested as Y'CbCr code values. Kept next to the converted RGB so logs
+/// can show what the server actually asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ycbcr {
+    pub y: u16,
+    pub cb: u16,
+    pub cr: u16,
+    pub depth_bits: u8,
+    pub matrix: YcbcrMatrix,
+    /// full-range codes (0..max) instead of video/limited range (16..235/240 at 8-bit)
+    pub full_range: bool,
+}
+
+impl Ycbcr {
+    /// Convert to full-range R'G'B' at the same bit depth, clamped to the code range.
+    /// Y'CbCr combinations outside the RGB cube report which channels were clamped.
+    pub fn to_rgb(self) -> (ColorRGB, Clamped) {
+        let bits = self.depth_bits.clamp(1, 16);
+        let max = ((1u32 << bits) - 1) as f64;
+        let scale = (1u32 << bits.saturating_sub(8)) as f64;
+
+        // normalise to Y' in 0..1 and Pb/Pr in -0.5..0.5
+        let (y, pb, pr) = if self.full_range {
+            let mid = (1u32 << (bits - 1)) as f64;
+            (self.y as f64 / max, (self.cb as f64 - mid) / max, (self.cr as f64 - mid) / max)
+        } else {
+            (
+                (self.y as f64 - 16.0 * scale) / (219.0 * scale),
+                (self.cb as f64 - 128.0 * scale) / (224.0 * scale),
+                (self.cr as f64 - 128.0 * scale) / (224.0 * scale),
+            )
+        };
+
+        let (kr, kb) = self.matrix.kr_kb();
+        let kg = 1.0 - kr - kb;
+        let r = y + 2.0 * (1.0 - kr) * pr;
+        let b = y + 2.0 * (1.0 - kb) * pb;
+        let g = (y - kr * r - kb * b) / kg;
+
+        // half a code of slack: rounding in the source codes alone shouldn't count
+        let clamped = Clamped::from_normalized([r, g, b], 0.5 / max);
+        let code = |v: f64| (v.clamp(0.0, 1.0) * max).round() as u16;
+        (ColorRGB::from_components_u16(code(r), code(g), code(b), bits), clamped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(matrix: YcbcrMatrix, (y, cb, cr): (u16, u16, u16), depth_bits: u8, full_range: bool) -> (u16, u16, u16) {
+        let (c, _) = Ycbcr { y, cb, cr, depth_bits, matrix, full_range }.to_rgb();
+        (c.red, c.green, c.blue)
+    }
+
+    /// Within one code: the reference Y'CbCr values are themselves rounded.
+    fn near((r, g, b): (u16, u16, u16), expected: (u16, u16, u16)) -> bool {
+        r.abs_diff(expected.0) <= 1 && g.abs_diff(expected.1) <= 1 && b.abs_diff(expected.2) <= 1
+    }
+
+    #[test]
+    fn primaries_match_the_reference_codes_per_matrix() {
+        // 8-bit limited-range 100% red, green and blue for each matrix
+        let references = [
+            (YcbcrMatrix::Bt601, [(81, 90, 240), (145, 54, 34), (41, 240, 110)]),
+            (YcbcrMatrix::Bt709, [(63, 102, 240), (173, 42, 26), (32, 240, 118)]),
+            (YcbcrMatrix::Bt2020, [(74, 97, 240), (164, 47, 25), (29, 240, 119)]),
+        ];
+        for (matrix, [red, green, blue]) in references {
+            for (ycbcr, expected) in [(red, (255, 0, 0)), (green, (0, 255, 0)), (blue, (0, 0, 255))] {
+                let got = rgb(matrix, ycbcr, 8, false);
+                assert!(near(got, expected), "{:?} {:?} gave {:?}, expected {:?}", matrix, ycbcr, got, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn greys_are_exact_in_any_range_and_depth() {
+        for matrix in [YcbcrMatrix::Bt601, YcbcrMatrix::Bt709, YcbcrMatrix::Bt2020] {
+            assert_eq!(rgb(matrix, (235, 128, 128), 8, false), (255, 255, 255));
+            assert_eq!(rgb(matrix, (16, 128, 128), 8, false), (0, 0, 0));
+            assert_eq!(rgb(matrix, (940, 512, 512), 10, false), (1023, 1023, 1023));
+            assert_eq!(rgb(matrix, (255, 128, 128), 8, true), (255, 255, 255));
+            assert_eq!(rgb(matrix, (0, 128, 128), 8, true), (0, 0, 0));
+        }
+    }
+
+    #[test]
+    fn out_of_gamut_codes_report_the_clamp() {
+        let (c, clamped) = Ycbcr { y: 235, cb: 16, cr: 16, depth_bits: 8, matrix: YcbcrMatrix::Bt709, full_range: false }.to_rgb();
+        assert!(clamped.green && !clamped.red && !clamped.blue, "{:?}", clamped);
+        assert_eq!(c.green, 255);
+        let (_, clamped) = Ycbcr { y: 126, cb: 128, cr: 128, depth_bits: 8, matrix: YcbcrMatrix::Bt709, full_range: false }.to_rgb();
+        assert!(!clamped.any());
+    }
+
+    #[test]
+    fn matrix_names() {
+        assert_eq!("BT.709".parse(), Ok(YcbcrMatrix::Bt709));
+        assert_eq!("bt2020".parse(), Ok(YcbcrMatrix::Bt2020));
+        assert_eq!("601".parse(), Ok(YcbcrMatrix::Bt601));
+        assert!("470".parse::<YcbcrMatrix>().is_err());
+    }
+}